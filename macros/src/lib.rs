@@ -0,0 +1,296 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Attribute, Data, DataEnum, DataStruct, DeriveInput, Error, Expr, Fields, Ident, LitInt, LitStr, Type};
+
+/// Generates an `RmcSerialize` implementation.
+///
+/// Structs are written field by field in declaration order, the following attributes are
+/// supported:
+/// - `#[rmc(version = N)]` on the struct: marks it as a nex `Structure`, on nex versions which use
+///   structure headers the content gets prefixed with the version and the content length
+/// - `#[rmc(parent)]` on the first field: the parent structure, it gets written before the header
+///   of this structure like nex does with its structure hierarchies
+/// - `#[rmc(since_nex = "3.5")]` on a field: the field only exists starting with the given nex
+///   version (taken from the `SerializationContext`), on older versions it gets skipped and
+///   filled with its default value when reading
+///
+/// Enums have to be fieldless and have a `#[repr(u8/u16/u32/...)]`, they get written as that
+/// integer.
+#[proc_macro_derive(RmcSerialize, attributes(rmc))]
+pub fn rmc_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let result = match &input.data {
+        Data::Struct(data) => derive_struct(&input, data),
+        Data::Enum(data) => derive_enum(&input, data),
+        Data::Union(_) => Err(Error::new_spanned(&input.ident, "unions can't be serialized")),
+    };
+
+    result.unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[derive(Default)]
+struct StructAttributes {
+    version: Option<LitInt>,
+}
+
+#[derive(Default)]
+struct FieldAttributes {
+    parent: bool,
+    since_nex: Option<(u8, u8, u8)>,
+}
+
+fn parse_struct_attributes(attrs: &[Attribute]) -> syn::Result<StructAttributes> {
+    let mut out = StructAttributes::default();
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("rmc")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("version") {
+                out.version = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown rmc struct attribute"))
+            }
+        })?;
+    }
+
+    Ok(out)
+}
+
+fn parse_field_attributes(attrs: &[Attribute]) -> syn::Result<FieldAttributes> {
+    let mut out = FieldAttributes::default();
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("rmc")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("parent") {
+                out.parent = true;
+                Ok(())
+            } else if meta.path.is_ident("since_nex") {
+                let lit: LitStr = meta.value()?.parse()?;
+                out.since_nex = Some(parse_nex_version(&lit)?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown rmc field attribute"))
+            }
+        })?;
+    }
+
+    Ok(out)
+}
+
+fn parse_nex_version(lit: &LitStr) -> syn::Result<(u8, u8, u8)> {
+    let value = lit.value();
+    let parts: Result<Vec<u8>, _> = value.split('.').map(|p| p.parse()).collect();
+
+    match parts.as_deref() {
+        Ok([major, minor]) => Ok((*major, *minor, 0)),
+        Ok([major, minor, patch]) => Ok((*major, *minor, *patch)),
+        _ => Err(Error::new_spanned(lit, "expected a nex version like \"3.5\" or \"3.5.0\"")),
+    }
+}
+
+struct FieldInfo {
+    accessor: TokenStream2,
+    binding: Ident,
+    ty: Type,
+    attributes: FieldAttributes,
+}
+
+fn derive_struct(input: &DeriveInput, data: &DataStruct) -> syn::Result<TokenStream2> {
+    let struct_attributes = parse_struct_attributes(&input.attrs)?;
+
+    let fields: Vec<FieldInfo> = data.fields.iter().enumerate().map(|(idx, field)| {
+        let accessor = match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let idx = syn::Index::from(idx);
+                quote!(#idx)
+            }
+        };
+
+        Ok(FieldInfo {
+            accessor,
+            binding: format_ident!("field_{}", idx),
+            ty: field.ty.clone(),
+            attributes: parse_field_attributes(&field.attrs)?,
+        })
+    }).collect::<syn::Result<_>>()?;
+
+    for (idx, field) in fields.iter().enumerate() {
+        if field.attributes.parent && idx != 0 {
+            return Err(Error::new_spanned(&field.ty, "the parent has to be the first field"));
+        }
+        if field.attributes.parent && struct_attributes.version.is_none() {
+            return Err(Error::new_spanned(&field.ty, "only structures with a version can have a parent"));
+        }
+    }
+
+    let (parent, own_fields) = match fields.first() {
+        Some(first) if first.attributes.parent => (Some(first), &fields[1..]),
+        _ => (None, &fields[..]),
+    };
+
+    let write_field = |field: &FieldInfo, writer: TokenStream2| {
+        let accessor = &field.accessor;
+        let write = quote! {
+            crate::rmc::structures::RmcSerialize::serialize(&self.#accessor, #writer, ctx)?;
+        };
+
+        gate_on_version(&field.attributes, write, quote!())
+    };
+
+    let read_field = |field: &FieldInfo, reader: TokenStream2| {
+        let binding = &field.binding;
+        let ty = &field.ty;
+
+        let read = quote! {
+            <#ty as crate::rmc::structures::RmcSerialize>::deserialize(#reader, ctx)?
+        };
+
+        let value = gate_on_version(&field.attributes, read, quote!(::std::default::Default::default()));
+
+        quote! { let #binding = #value; }
+    };
+
+    let write_parent = parent.map(|p| write_field(p, quote!(writer)));
+    let read_parent = parent.map(|p| read_field(p, quote!(reader)));
+
+    let (serialize_body, deserialize_body) = match &struct_attributes.version {
+        Some(version) => {
+            let write_own = own_fields.iter().map(|f| write_field(f, quote!(&mut content)));
+            let read_own = own_fields.iter().map(|f| read_field(f, quote!(content)));
+
+            (
+                quote! {
+                    #write_parent
+
+                    let mut content: ::std::vec::Vec<u8> = ::std::vec::Vec::new();
+                    #(#write_own)*
+
+                    crate::rmc::structures::write_structure_header(writer, ctx, #version, &content)?;
+                    writer.write_all(&content)?;
+                },
+                quote! {
+                    #read_parent
+
+                    let mut content_buffer;
+                    let content: &mut dyn ::std::io::Read = match crate::rmc::structures::read_structure_content(reader, ctx)? {
+                        Some(data) => {
+                            content_buffer = ::std::io::Cursor::new(data);
+                            &mut content_buffer
+                        }
+                        None => reader
+                    };
+                    #(#read_own)*
+                }
+            )
+        }
+        None => {
+            let write_own = own_fields.iter().map(|f| write_field(f, quote!(writer)));
+            let read_own = own_fields.iter().map(|f| read_field(f, quote!(reader)));
+
+            (
+                quote! { #(#write_own)* },
+                quote! { #(#read_own)* }
+            )
+        }
+    };
+
+    let construct = match &data.fields {
+        Fields::Named(_) => {
+            let names = fields.iter().map(|f| &f.accessor);
+            let bindings = fields.iter().map(|f| &f.binding);
+            quote! { Self { #(#names: #bindings),* } }
+        }
+        Fields::Unnamed(_) => {
+            let bindings = fields.iter().map(|f| &f.binding);
+            quote! { Self( #(#bindings),* ) }
+        }
+        Fields::Unit => quote! { Self },
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics crate::rmc::structures::RmcSerialize for #name #ty_generics #where_clause {
+            #[allow(unused_imports, unused_variables)]
+            fn serialize(&self, writer: &mut dyn ::std::io::Write, ctx: &crate::rmc::structures::SerializationContext) -> crate::rmc::structures::Result<()> {
+                use ::std::io::Write;
+                #serialize_body
+                Ok(())
+            }
+
+            #[allow(unused_variables)]
+            fn deserialize(reader: &mut dyn ::std::io::Read, ctx: &crate::rmc::structures::SerializationContext) -> crate::rmc::structures::Result<Self> {
+                #deserialize_body
+                Ok(#construct)
+            }
+        }
+    })
+}
+
+fn gate_on_version(attributes: &FieldAttributes, tokens: TokenStream2, otherwise: TokenStream2) -> TokenStream2 {
+    match attributes.since_nex {
+        Some((major, minor, patch)) => quote! {
+            if ctx.nex_version >= crate::rmc::structures::NexVersion::new(#major, #minor, #patch) {
+                #tokens
+            } else {
+                #otherwise
+            }
+        },
+        None => tokens,
+    }
+}
+
+fn derive_enum(input: &DeriveInput, data: &DataEnum) -> syn::Result<TokenStream2> {
+    let repr: Ident = input.attrs.iter()
+        .filter(|a| a.path().is_ident("repr"))
+        .find_map(|a| a.parse_args().ok())
+        .ok_or_else(|| Error::new_spanned(&input.ident, "enums need a #[repr(...)] to be serialized"))?;
+
+    let mut variants = Vec::new();
+    let mut discriminants: Vec<Expr> = Vec::new();
+
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(variant, "only fieldless enums can be serialized"));
+        }
+        let Some((_, discriminant)) = &variant.discriminant else {
+            return Err(Error::new_spanned(variant, "every variant needs an explicit value"));
+        };
+
+        variants.push(&variant.ident);
+        discriminants.push(discriminant.clone());
+    }
+
+    let name = &input.ident;
+    let name_str = name.to_string();
+
+    Ok(quote! {
+        impl crate::rmc::structures::RmcSerialize for #name {
+            fn serialize(&self, writer: &mut dyn ::std::io::Write, _ctx: &crate::rmc::structures::SerializationContext) -> crate::rmc::structures::Result<()> {
+                use crate::endianness::WriteExtensions;
+
+                let value = *self as #repr;
+                writer.write_struct(crate::endianness::IS_BIG_ENDIAN, value)?;
+                Ok(())
+            }
+
+            fn deserialize(mut reader: &mut dyn ::std::io::Read, _ctx: &crate::rmc::structures::SerializationContext) -> crate::rmc::structures::Result<Self> {
+                use crate::endianness::ReadExtensions;
+
+                let value: #repr = reader.read_struct(crate::endianness::IS_BIG_ENDIAN)?;
+
+                match value {
+                    #(v if v == (#discriminants) => Ok(Self::#variants),)*
+                    v => Err(crate::rmc::structures::Error::InvalidValue {
+                        name: #name_str,
+                        value: v as u64,
+                    })
+                }
+            }
+        }
+    })
+}
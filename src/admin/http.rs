@@ -0,0 +1,219 @@
+use std::io;
+use std::time::Duration;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// nothing the admin api receives comes close to these
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+// how long a client gets to send its whole request, `Request::read` itself doesn't time out
+pub const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum Error{
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("malformed request: {0}")]
+    Malformed(&'static str),
+    #[error("request is too large")]
+    TooLarge,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Just enough of an http/1.1 request for the admin api, every connection carries a single request
+#[derive(Debug, Clone, Default)]
+pub struct Request{
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request{
+    pub fn header(&self, name: &str) -> Option<&str>{
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn bearer_token(&self) -> Option<&str>{
+        self.header("authorization")?.strip_prefix("Bearer ")
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str>{
+        self.query.as_deref()?
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v)
+    }
+
+    pub async fn read(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Self>{
+        let mut head_size = 0;
+        let mut lines = Vec::new();
+
+        loop {
+            let mut line = String::new();
+
+            // one byte more than what is left so that we notice if the head is too large, even
+            // if the line never ends
+            let read = (&mut *reader).take((MAX_HEAD_SIZE + 1 - head_size) as u64).read_line(&mut line).await?;
+
+            if read == 0 {
+                return Err(Error::Malformed("connection closed before the end of the head"));
+            }
+
+            head_size += read;
+
+            if head_size > MAX_HEAD_SIZE {
+                return Err(Error::TooLarge);
+            }
+
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if line.is_empty() {
+                break;
+            }
+
+            lines.push(line.to_string());
+        }
+
+        let mut lines = lines.into_iter();
+
+        let request_line = lines.next().ok_or(Error::Malformed("missing request line"))?;
+
+        let mut parts = request_line.split(' ');
+
+        let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(Error::Malformed("invalid request line"));
+        };
+
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target.to_string(), None),
+        };
+
+        let headers = lines
+            .map(|l| {
+                let (name, value) = l.split_once(':').ok_or(Error::Malformed("invalid header"))?;
+                Ok((name.trim().to_string(), value.trim().to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut request = Request{
+            method: method.to_string(),
+            path,
+            query,
+            headers,
+            body: Vec::new(),
+        };
+
+        let length = match request.header("content-length") {
+            Some(length) => length.parse().map_err(|_| Error::Malformed("invalid content length"))?,
+            None => 0,
+        };
+
+        if length > MAX_BODY_SIZE {
+            return Err(Error::TooLarge);
+        }
+
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body).await?;
+
+        Ok(request)
+    }
+}
+
+/// A json response, the connection gets closed after sending it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response{
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+impl Response{
+    pub fn ok(body: impl Serialize) -> Self{
+        match serde_json::to_value(body) {
+            Ok(body) => Self{
+                status: 200,
+                body,
+            },
+            Err(e) => Self::error(500, &format!("unable to serialize response: {}", e)),
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self{
+        Self{
+            status,
+            body: serde_json::json!({ "error": message }),
+        }
+    }
+
+    pub async fn write(&self, writer: &mut (impl AsyncWrite + Unpin)) -> io::Result<()>{
+        write_response(writer, self.status, "application/json", self.body.to_string().as_bytes()).await
+    }
+}
+
+fn reason(status: u16) -> &'static str{
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        501 => "Not Implemented",
+        _ => "Internal Server Error",
+    }
+}
+
+// for responses which aren't json
+pub async fn write_response(writer: &mut (impl AsyncWrite + Unpin), status: u16, content_type: &str, body: &[u8]) -> io::Result<()>{
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    );
+
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod test{
+    use tokio::io::BufReader;
+    use super::{Error, Request, Response};
+
+    #[tokio::test]
+    async fn read_request(){
+        let raw = b"POST /kick?dry=1 HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer secret\r\nContent-Length: 12\r\n\r\n{\"pid\": 123}";
+
+        let request = Request::read(&mut BufReader::new(&raw[..])).await.unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/kick");
+        assert_eq!(request.query_param("dry"), Some("1"));
+        assert_eq!(request.bearer_token(), Some("secret"));
+        assert_eq!(request.body, b"{\"pid\": 123}");
+
+        let too_large = b"POST / HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n";
+        assert!(matches!(Request::read(&mut BufReader::new(&too_large[..])).await, Err(Error::TooLarge)));
+
+        let endless_line = vec![b'a'; 1024 * 1024];
+        assert!(matches!(Request::read(&mut BufReader::new(&endless_line[..])).await, Err(Error::TooLarge)));
+
+        let mut written = Vec::new();
+        Response::error(404, "nope").write(&mut written).await.unwrap();
+
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(written.ends_with("\r\n\r\n{\"error\":\"nope\"}"));
+    }
+}
@@ -0,0 +1,574 @@
+pub mod http;
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::BufReader;
+use tokio::net::{TcpListener, TcpStream};
+use crate::protocols::bans::{self, BanTarget, Bans};
+use crate::protocols::maintenance::{Maintenance, MaintenanceScope, MaintenanceWindow};
+use crate::protocols::notifications::{NotificationEvent, NotificationManager, OfflinePolicy};
+use crate::prudp::router::Router;
+use crate::prudp::socket::{ConnectionData, SocketData};
+use crate::rmc::structures::SerializationContext;
+use crate::rmc::structures::pid::Pid;
+use http::{Request, Response};
+
+/// A socket the admin api can show and control, together with the server it belongs to
+pub struct AdminSocket{
+    pub title: String,
+    pub server: String,
+    pub socket: Arc<SocketData>,
+    pub serialization_context: SerializationContext,
+}
+
+#[derive(Serialize)]
+struct SocketInfo{
+    title: Option<String>,
+    server: Option<String>,
+    virtual_port: u8,
+    stream_type: u8,
+    accepting: bool,
+    connections: usize,
+}
+
+#[derive(Serialize)]
+struct RouterInfo{
+    address: SocketAddrV4,
+    running: bool,
+    capturing: bool,
+    sockets: Vec<SocketInfo>,
+}
+
+#[derive(Serialize)]
+struct ConnectionInfo{
+    title: String,
+    server: String,
+    virtual_port: u8,
+    address: SocketAddrV4,
+    connection_id: u64,
+    active: bool,
+    pid: Option<u32>,
+    session_id: Option<u8>,
+    packets_received: u64,
+    packets_sent: u64,
+    connected_secs: u64,
+    idle_ms: u64,
+}
+
+#[derive(Serialize)]
+struct AutoBanInfo{
+    router: SocketAddrV4,
+    ip: Ipv4Addr,
+    remaining_secs: u64,
+}
+
+#[derive(Serialize)]
+struct MaintenanceInfo{
+    // none for a global maintenance
+    title: Option<String>,
+    message: Option<String>,
+    // unix timestamp
+    until: Option<u64>,
+    allowed_pids: Vec<u32>,
+}
+
+// either a pid or an address has to be given
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KickRequest{
+    pid: Option<u32>,
+    address: Option<SocketAddrV4>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BanRequest{
+    // e.g. {"pid": 1337} or {"ip_range": "10.0.0.0/8"}
+    target: BanTarget,
+    reason: String,
+    #[serde(default = "default_issuer")]
+    issuer: String,
+    // in seconds, the ban is permanent if left out
+    duration: Option<u64>,
+}
+
+fn default_issuer() -> String{
+    "admin api".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaintenanceRequest{
+    // every title if left out
+    title: Option<String>,
+    message: Option<String>,
+    // in seconds, the maintenance has to be ended by hand if left out
+    duration: Option<u64>,
+    #[serde(default)]
+    allowed_pids: Vec<u32>,
+    #[serde(default)]
+    drain: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NotificationRequest{
+    // every title if left out
+    title: Option<String>,
+    // everyone who is online if left out
+    pids: Option<Vec<u32>>,
+    #[serde(default)]
+    pid_source: u64,
+    notification_type: u32,
+    #[serde(default)]
+    param_1: u32,
+    #[serde(default)]
+    param_2: u32,
+    #[serde(default)]
+    str_param: String,
+    #[serde(default)]
+    param_3: u32,
+}
+
+fn parse_body<T: DeserializeOwned>(request: &Request) -> Result<T, Response>{
+    serde_json::from_slice(&request.body)
+        .map_err(|e| Response::error(400, &format!("invalid body: {}", e)))
+}
+
+fn unix_secs(time: SystemTime) -> u64{
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// compares in constant time so the token can't be guessed byte by byte
+fn token_matches(expected: &str, given: &str) -> bool{
+    expected.len() == given.len() &&
+        expected.bytes().zip(given.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn connection_info(socket: &AdminSocket, connection: &ConnectionData) -> ConnectionInfo{
+    let active_connection = connection.active_connection_data.as_ref();
+
+    ConnectionInfo{
+        title: socket.title.clone(),
+        server: socket.server.clone(),
+        virtual_port: socket.socket.get_virual_port().get_port_number(),
+        address: connection.sock_addr.regular_socket_addr,
+        connection_id: connection.id,
+        active: active_connection.is_some(),
+        pid: active_connection.and_then(|a| a.pid),
+        session_id: active_connection.map(|a| a.server_session_id),
+        packets_received: connection.packets_received,
+        packets_sent: connection.packets_sent,
+        connected_secs: connection.connected_at.elapsed().as_secs(),
+        idle_ms: connection.last_activity.elapsed().as_millis() as u64,
+    }
+}
+
+/// Http api for looking into and controlling the running servers, every request needs the
+/// configured token as a bearer token and gets a json response
+pub struct Admin{
+    token: String,
+    routers: Vec<Arc<Router>>,
+    sockets: Vec<AdminSocket>,
+    maintenance: Arc<Maintenance>,
+    bans: Arc<Bans>,
+}
+
+impl Admin{
+    pub fn new(token: String, maintenance: Arc<Maintenance>, bans: Arc<Bans>) -> Self{
+        Self{
+            token,
+            routers: Vec::new(),
+            sockets: Vec::new(),
+            maintenance,
+            bans,
+        }
+    }
+
+    pub fn add_router(&mut self, router: Arc<Router>){
+        self.routers.push(router);
+    }
+
+    pub fn add_socket(&mut self, socket: AdminSocket){
+        self.sockets.push(socket);
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: TcpListener){
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("admin api: unable to accept connection: {}", e);
+                    continue;
+                }
+            };
+
+            tokio::spawn(self.clone().handle_connection(stream, peer));
+        }
+    }
+
+    async fn handle_connection(self: Arc<Self>, stream: TcpStream, peer: SocketAddr){
+        let (reader, mut writer) = stream.into_split();
+
+        let Ok(request) = tokio::time::timeout(http::READ_TIMEOUT, Request::read(&mut BufReader::new(reader))).await else {
+            warn!("admin api: {} didn't send a request within {:?}", peer, http::READ_TIMEOUT);
+            return;
+        };
+
+        let response = match request {
+            Ok(request) => {
+                let response = self.handle(&request).await;
+
+                info!("admin api: {} {} from {}: {}", request.method, request.path, peer, response.status);
+
+                response
+            }
+            Err(http::Error::Io(e)) => {
+                warn!("admin api: unable to read request from {}: {}", peer, e);
+                return;
+            }
+            Err(e @ http::Error::TooLarge) => Response::error(413, &e.to_string()),
+            Err(e) => Response::error(400, &e.to_string()),
+        };
+
+        if let Err(e) = response.write(&mut writer).await {
+            warn!("admin api: unable to send response to {}: {}", peer, e);
+        }
+    }
+
+    pub async fn handle(&self, request: &Request) -> Response{
+        if !request.bearer_token().is_some_and(|t| token_matches(&self.token, t)) {
+            return Response::error(401, "missing or invalid token");
+        }
+
+        let path: Vec<_> = request.path.split('/').filter(|p| !p.is_empty()).collect();
+
+        let result = match (request.method.as_str(), &path[..]) {
+            ("GET", ["routers"]) => Ok(self.routers().await),
+            ("GET", ["connections"]) => Ok(self.connections().await),
+            ("POST", ["connections", "kick"]) => self.kick(request).await,
+            ("GET", ["bans"]) => Ok(Response::ok(self.bans.list())),
+            ("POST", ["bans"]) => self.ban(request).await,
+            ("DELETE", ["bans", id]) => self.unban(id),
+            ("GET", ["autobans"]) => Ok(self.auto_bans()),
+            ("DELETE", ["autobans", ip]) => self.lift_auto_ban(ip),
+            ("GET", ["maintenance"]) => Ok(self.maintenance_windows()),
+            ("POST", ["maintenance"]) => self.start_maintenance(request).await,
+            ("DELETE", ["maintenance"]) => self.end_maintenance(request),
+            ("POST", ["notifications"]) => self.broadcast(request).await,
+            // there is no matchmaking in here yet, so there is nothing to show
+            ("GET", ["gatherings"] | ["splatfest"]) => Err(Response::error(501, "matchmaking isn't implemented yet")),
+            (_, ["routers" | "connections" | "bans" | "autobans" | "maintenance" | "notifications" | "gatherings" | "splatfest", ..]) => {
+                Err(Response::error(405, "method not allowed"))
+            }
+            _ => Err(Response::error(404, "not found")),
+        };
+
+        result.unwrap_or_else(|e| e)
+    }
+
+    fn admin_socket(&self, socket: &Arc<SocketData>) -> Option<&AdminSocket>{
+        self.sockets.iter().find(|s| Arc::ptr_eq(&s.socket, socket))
+    }
+
+    fn has_title(&self, title: &str) -> bool{
+        self.sockets.iter().any(|s| s.title == title)
+    }
+
+    fn sockets_of<'a>(&'a self, title: Option<&'a str>) -> impl Iterator<Item=&'a AdminSocket>{
+        self.sockets.iter().filter(move |s| title.is_none_or(|t| s.title == t))
+    }
+
+    async fn routers(&self) -> Response{
+        let mut routers = Vec::new();
+
+        for router in &self.routers {
+            let mut sockets = Vec::new();
+
+            for socket in router.sockets().await {
+                let admin_socket = self.admin_socket(&socket);
+
+                sockets.push(SocketInfo{
+                    title: admin_socket.map(|s| s.title.clone()),
+                    server: admin_socket.map(|s| s.server.clone()),
+                    virtual_port: socket.get_virual_port().get_port_number(),
+                    stream_type: socket.get_virual_port().get_stream_type(),
+                    accepting: socket.is_accepting(),
+                    connections: socket.connections().await.len(),
+                });
+            }
+
+            routers.push(RouterInfo{
+                address: router.get_own_address(),
+                running: router.is_running(),
+                capturing: router.capture().is_active(),
+                sockets,
+            });
+        }
+
+        Response::ok(routers)
+    }
+
+    // this waits for calls which are being handled right now as it needs the lock of every
+    // connection
+    async fn connections(&self) -> Response{
+        let mut connections = Vec::new();
+
+        for socket in &self.sockets {
+            for connection in socket.socket.connections().await {
+                connections.push(connection_info(socket, &*connection.lock().await));
+            }
+        }
+
+        Response::ok(connections)
+    }
+
+    async fn disconnect_where(&self, filter: impl Fn(&ConnectionData) -> bool) -> usize{
+        let mut disconnected = 0;
+
+        for socket in &self.sockets {
+            disconnected += socket.socket.disconnect_where(&filter).await;
+        }
+
+        disconnected
+    }
+
+    async fn kick(&self, request: &Request) -> Result<Response, Response>{
+        let kick: KickRequest = parse_body(request)?;
+
+        if kick.pid.is_none() && kick.address.is_none() {
+            return Err(Response::error(400, "either pid or address is needed"));
+        }
+
+        let disconnected = self.disconnect_where(|c| {
+            let pid = c.active_connection_data.as_ref().and_then(|a| a.pid);
+
+            (kick.pid.is_some() && pid == kick.pid) ||
+                kick.address == Some(c.sock_addr.regular_socket_addr)
+        }).await;
+
+        Ok(Response::ok(serde_json::json!({ "disconnected": disconnected })))
+    }
+
+    // also kicks everyone the ban applies to
+    async fn ban(&self, request: &Request) -> Result<Response, Response>{
+        let ban: BanRequest = parse_body(request)?;
+
+        let ban = self.bans.add(ban.target, ban.reason, ban.issuer, ban.duration.map(Duration::from_secs))
+            .map_err(|e| match e {
+                bans::Error::UnsupportedTarget(_) => Response::error(400, &e.to_string()),
+                _ => Response::error(500, &e.to_string()),
+            })?;
+
+        let disconnected = self.bans.kick(&ban).await;
+
+        Ok(Response::ok(serde_json::json!({ "ban": ban, "disconnected": disconnected })))
+    }
+
+    fn unban(&self, id: &str) -> Result<Response, Response>{
+        let id = id.parse().map_err(|_| Response::error(400, "invalid ban id"))?;
+
+        let removed = self.bans.remove(id)
+            .map_err(|e| Response::error(500, &e.to_string()))?;
+
+        Ok(Response::ok(serde_json::json!({ "removed": removed.is_some() })))
+    }
+
+    // ips the rate limiters of the routers are ignoring right now
+    fn auto_bans(&self) -> Response{
+        let bans: Vec<_> = self.routers.iter()
+            .flat_map(|router| {
+                router.rate_limiter().bans().into_iter().map(|(ip, remaining)| AutoBanInfo{
+                    router: router.get_own_address(),
+                    ip,
+                    remaining_secs: remaining.as_secs(),
+                })
+            })
+            .collect();
+
+        Response::ok(bans)
+    }
+
+    fn lift_auto_ban(&self, ip: &str) -> Result<Response, Response>{
+        let ip: Ipv4Addr = ip.parse().map_err(|_| Response::error(400, "invalid ip"))?;
+
+        let mut lifted = false;
+
+        for router in &self.routers {
+            lifted |= router.rate_limiter().unban(ip);
+        }
+
+        Ok(Response::ok(serde_json::json!({ "lifted": lifted })))
+    }
+
+    fn maintenance_windows(&self) -> Response{
+        let windows: Vec<_> = self.maintenance.windows().into_iter()
+            .map(|(scope, window)| {
+                let mut allowed_pids: Vec<_> = window.allowed_pids.into_iter().collect();
+                allowed_pids.sort();
+
+                MaintenanceInfo{
+                    title: match scope {
+                        MaintenanceScope::Global => None,
+                        MaintenanceScope::Title(title) => Some(title),
+                    },
+                    message: window.message,
+                    until: window.until.map(unix_secs),
+                    allowed_pids,
+                }
+            })
+            .collect();
+
+        Response::ok(windows)
+    }
+
+    fn maintenance_scope(&self, title: Option<&str>) -> Result<MaintenanceScope, Response>{
+        match title {
+            None => Ok(MaintenanceScope::Global),
+            Some(title) if self.has_title(title) => Ok(MaintenanceScope::Title(title.to_string())),
+            Some(title) => Err(Response::error(400, &format!("unknown title {:?}", title))),
+        }
+    }
+
+    async fn start_maintenance(&self, request: &Request) -> Result<Response, Response>{
+        let maintenance: MaintenanceRequest = parse_body(request)?;
+
+        let scope = self.maintenance_scope(maintenance.title.as_deref())?;
+
+        let drained = self.maintenance.start(scope, MaintenanceWindow{
+            message: maintenance.message,
+            until: maintenance.duration.map(|d| SystemTime::now() + Duration::from_secs(d)),
+            allowed_pids: maintenance.allowed_pids.into_iter().collect(),
+            drain: maintenance.drain,
+        }).await;
+
+        Ok(Response::ok(serde_json::json!({ "drained": drained })))
+    }
+
+    // ends the maintenance of the title given in the query or the global one
+    fn end_maintenance(&self, request: &Request) -> Result<Response, Response>{
+        let scope = self.maintenance_scope(request.query_param("title"))?;
+
+        Ok(Response::ok(serde_json::json!({ "ended": self.maintenance.end(&scope) })))
+    }
+
+    async fn broadcast(&self, request: &Request) -> Result<Response, Response>{
+        let notification: NotificationRequest = parse_body(request)?;
+
+        if let Some(title) = notification.title.as_deref().filter(|t| !self.has_title(t)) {
+            return Err(Response::error(400, &format!("unknown title {:?}", title)));
+        }
+
+        let event = NotificationEvent{
+            pid_source: Pid(notification.pid_source),
+            notification_type: notification.notification_type,
+            param_1: notification.param_1,
+            param_2: notification.param_2,
+            str_param: notification.str_param,
+            param_3: notification.param_3,
+        };
+
+        let mut sent = 0;
+
+        for socket in self.sockets_of(notification.title.as_deref()) {
+            let manager = NotificationManager::new(socket.serialization_context, OfflinePolicy::Drop);
+            manager.register_socket(&socket.socket);
+
+            // only users with a live connection to this socket, the rest would just be dropped
+            let online: Vec<_> = socket.socket.pid_addresses().await.into_iter()
+                .map(|(pid, _)| pid)
+                .filter(|pid| notification.pids.as_ref().is_none_or(|pids| pids.contains(pid)))
+                .collect();
+
+            for pid in online {
+                manager.send(pid, event.clone()).await;
+                sent += 1;
+            }
+        }
+
+        Ok(Response::ok(serde_json::json!({ "sent": sent })))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use serde_json::json;
+    use crate::protocols::bans::Subject;
+    use crate::rmc::response::ErrorCode;
+    use super::Admin;
+    use super::http::Request;
+
+    fn request(method: &str, path: &str, body: serde_json::Value) -> Request{
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (path, None),
+        };
+
+        Request{
+            method: method.to_string(),
+            path: path.to_string(),
+            query,
+            headers: vec![("Authorization".to_string(), "Bearer secret".to_string())],
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    #[tokio::test]
+    async fn maintenance_and_bans(){
+        let admin = Admin::new("secret".to_string(), Default::default(), Default::default());
+
+        let mut unauthorized = request("GET", "/bans", json!(null));
+        unauthorized.headers.clear();
+        assert_eq!(admin.handle(&unauthorized).await.status, 401);
+
+        unauthorized.headers.push(("Authorization".to_string(), "Bearer secreT".to_string()));
+        assert_eq!(admin.handle(&unauthorized).await.status, 401);
+
+        assert_eq!(admin.handle(&request("GET", "/nothing", json!(null))).await.status, 404);
+        assert_eq!(admin.handle(&request("GET", "/gatherings", json!(null))).await.status, 501);
+
+        let response = admin.handle(&request("POST", "/maintenance", json!({
+            "message": "updating",
+            "allowed_pids": [2, 1],
+        }))).await;
+        assert_eq!(response.body, json!({ "drained": 0 }));
+
+        let response = admin.handle(&request("GET", "/maintenance", json!(null))).await;
+        assert_eq!(response.body, json!([{ "title": null, "message": "updating", "until": null, "allowed_pids": [1, 2] }]));
+        assert!(admin.maintenance.blocks("splatoon", Some(3)).is_some());
+
+        // there are no servers for this title
+        let response = admin.handle(&request("POST", "/maintenance", json!({ "title": "splatoon" }))).await;
+        assert_eq!(response.status, 400);
+
+        let response = admin.handle(&request("DELETE", "/maintenance", json!(null))).await;
+        assert_eq!(response.body, json!({ "ended": true }));
+
+        let response = admin.handle(&request("POST", "/bans", json!({
+            "target": { "pid": 1337 },
+            "reason": "cheating",
+            "duration": 60,
+        }))).await;
+        assert_eq!(response.body["ban"]["id"], 1);
+        assert_eq!(response.body["ban"]["issuer"], "admin api");
+        assert_eq!(response.body["disconnected"], 0);
+
+        let ban = admin.bans.find(&Subject{
+            pid: Some(1337),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(ban.error_code(), ErrorCode::RendezVous_AccountTemporarilyDisabled);
+
+        let response = admin.handle(&request("GET", "/bans", json!(null))).await;
+        assert_eq!(response.body[0]["target"], json!({ "pid": 1337 }));
+
+        assert_eq!(admin.handle(&request("POST", "/bans", json!({ "target": { "pid": "a" }, "reason": "" }))).await.status, 400);
+
+        let response = admin.handle(&request("DELETE", "/bans/1", json!(null))).await;
+        assert_eq!(response.body, json!({ "removed": true }));
+    }
+}
@@ -0,0 +1,112 @@
+// Edits the ban file of a server, a running server picks the changes up within a few seconds and
+// kicks everyone who is newly banned.
+//
+// usage: bans [--file <bans.json>] list
+//        bans [--file <bans.json>] add <pid|username|ip_range> <value> --reason <reason>
+//                                      [--issuer <name>] [--duration <seconds>]
+//        bans [--file <bans.json>] remove <id>
+
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use splatoon_server_rust::protocols::bans::{Ban, BanTarget, Bans};
+
+fn usage() -> ! {
+    eprintln!("usage: bans [--file <bans.json>] list");
+    eprintln!("       bans [--file <bans.json>] add <pid|username|ip_range> <value> --reason <reason> [--issuer <name>] [--duration <seconds>]");
+    eprintln!("       bans [--file <bans.json>] remove <id>");
+    exit(1)
+}
+
+fn parse_target(kind: &str, value: String) -> BanTarget{
+    match kind {
+        "pid" => BanTarget::Pid(value.parse().unwrap_or_else(|_| usage())),
+        "username" => BanTarget::Username(value),
+        "console_id" => {
+            eprintln!("console bans can't be enforced yet, the console id of a login isn't known");
+            exit(1)
+        }
+        "ip_range" => BanTarget::IpRange(value.parse().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1)
+        })),
+        _ => usage()
+    }
+}
+
+fn print_ban(ban: &Ban){
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    let expires = match ban.expires {
+        Some(expires) => format!("{}s left", expires.saturating_sub(now)),
+        None => "permanent".to_string(),
+    };
+
+    println!("{:>4}  {:<24}  {:<10}  {:<12}  {}", ban.id, ban.target.to_string(), expires, ban.issuer, ban.reason);
+}
+
+fn main(){
+    let mut args = std::env::args().skip(1).peekable();
+
+    let mut file = PathBuf::from("bans.json");
+
+    if args.peek().is_some_and(|a| a == "--file") {
+        args.next();
+        file = args.next().unwrap_or_else(|| usage()).into();
+    }
+
+    let bans = Bans::load(&file).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1)
+    });
+
+    let result = match args.next().as_deref() {
+        Some("list") => {
+            for ban in bans.list() {
+                print_ban(&ban);
+            }
+
+            Ok(())
+        }
+        Some("add") => {
+            let kind = args.next().unwrap_or_else(|| usage());
+            let target = parse_target(&kind, args.next().unwrap_or_else(|| usage()));
+
+            let mut reason = None;
+            let mut issuer = "cli".to_string();
+            let mut duration = None;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--reason" => reason = args.next(),
+                    "--issuer" => issuer = args.next().unwrap_or_else(|| usage()),
+                    "--duration" => duration = Some(args.next().and_then(|d| d.parse().ok()).unwrap_or_else(|| usage())),
+                    _ => usage()
+                }
+            }
+
+            let Some(reason) = reason else {
+                usage()
+            };
+
+            bans.add(target, reason, issuer, duration.map(Duration::from_secs))
+                .map(|ban| print_ban(&ban))
+        }
+        Some("remove") => {
+            let id = args.next().and_then(|id| id.parse().ok()).unwrap_or_else(|| usage());
+
+            bans.remove(id).map(|removed| {
+                if removed.is_none() {
+                    eprintln!("there is no ban with id {}", id);
+                    exit(1);
+                }
+            })
+        }
+        _ => usage()
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
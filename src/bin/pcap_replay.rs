@@ -0,0 +1,760 @@
+// Reads captured console sessions and prints what was said over rmc, optionally replaying the
+// client side against a local server to see where our responses differ.
+//
+// usage: pcap_replay <capture.pcap(ng)> --port <udp port> [--key <rc4 key> | --key-hex <hex>]
+//                    [--access-key <key>] [--nex-version <version>] [--replay]
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{error, warn};
+use rc4::{KeyInit, Rc4, StreamCipher};
+use rc4::consts::{U16, U32, U5};
+use simplelog::{ColorChoice, Config, LevelFilter, TerminalMode, TermLogger};
+use splatoon_server_rust::pcap::Datagram;
+use splatoon_server_rust::pcap::reader::read_datagrams;
+use splatoon_server_rust::protocols::{auth, secure, ServerState};
+use splatoon_server_rust::protocols::accounts::{Account, Accounts};
+use splatoon_server_rust::protocols::server::RMCProtocolServer;
+use splatoon_server_rust::prudp::client::{default_ciphers, Connection};
+use splatoon_server_rust::prudp::packet::{PRUDPPacket, VirtualPort};
+use splatoon_server_rust::prudp::packet::flags::{ACK, RELIABLE};
+use splatoon_server_rust::prudp::packet::types::{CONNECT, DATA, DISCONNECT, PING, SYN};
+use splatoon_server_rust::prudp::router::Router;
+use splatoon_server_rust::rmc::message::{MessageHeader, RMCMessage};
+use splatoon_server_rust::rmc::response::{RMCResponse, RMCResponseResult};
+use splatoon_server_rust::rmc::structures::{NexVersion, RmcSerialize, SerializationContext};
+use splatoon_server_rust::rmc::structures::any::{Any, AnyData};
+use splatoon_server_rust::rmc::structures::buffer::Buffer;
+use splatoon_server_rust::rmc::structures::result_code::ResultCode;
+
+type Cipher = Box<dyn StreamCipher + Send + Sync>;
+
+struct Options{
+    capture: String,
+    port: u16,
+    key: Vec<u8>,
+    access_key: &'static str,
+    nex_version: NexVersion,
+    replay: bool,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: pcap_replay <capture> --port <udp port> [--key <rc4 key> | --key-hex <hex>] [--access-key <key>] [--nex-version <version>] [--replay]");
+    exit(1)
+}
+
+fn parse_options() -> Options{
+    let mut args = std::env::args().skip(1);
+
+    let mut capture = None;
+    let mut port = None;
+    let mut key = b"CD&ML".to_vec();
+    let mut access_key = "6f599f81".to_string();
+    let mut nex_version = NexVersion::new(3, 8, 3);
+    let mut replay = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => port = args.next().and_then(|p| p.parse().ok()),
+            "--key" => key = args.next().unwrap_or_else(|| usage()).into_bytes(),
+            "--key-hex" => {
+                let hex = args.next().unwrap_or_else(|| usage());
+
+                key = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                    .collect::<Option<_>>()
+                    .unwrap_or_else(|| usage());
+            }
+            "--access-key" => access_key = args.next().unwrap_or_else(|| usage()),
+            "--nex-version" => nex_version = args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage()),
+            "--replay" => replay = true,
+            _ if capture.is_none() && !arg.starts_with("--") => capture = Some(arg),
+            _ => usage()
+        }
+    }
+
+    let (Some(capture), Some(port)) = (capture, port) else {
+        usage()
+    };
+
+    Options{
+        capture,
+        port,
+        key,
+        // the sockets want a static access key and this lives for the whole program anyways
+        access_key: access_key.leak(),
+        nex_version,
+        replay,
+    }
+}
+
+fn new_cipher(key: &[u8]) -> Cipher{
+    match key.len() {
+        5 => Box::new(Rc4::<U5>::new_from_slice(key).unwrap()),
+        16 => Box::new(Rc4::<U16>::new_from_slice(key).unwrap()),
+        32 => Box::new(Rc4::<U32>::new_from_slice(key).unwrap()),
+        len => {
+            eprintln!("unsupported rc4 key length {}", len);
+            exit(1)
+        }
+    }
+}
+
+// one direction of a prudp connection, reliable packets get put back into order and decrypted
+struct Stream{
+    next_sequence_id: Option<u16>,
+    queue: BTreeMap<u16, PRUDPPacket>,
+    cipher: Cipher,
+}
+
+impl Stream{
+    fn new(key: &[u8], next_sequence_id: Option<u16>) -> Self{
+        Self{
+            next_sequence_id,
+            queue: BTreeMap::new(),
+            cipher: new_cipher(key),
+        }
+    }
+
+    // returns the decrypted payloads which are now in order
+    fn push(&mut self, packet: PRUDPPacket) -> Vec<Vec<u8>>{
+        let next_sequence_id = *self.next_sequence_id.get_or_insert(packet.header.sequence_id);
+
+        if packet.header.sequence_id.wrapping_sub(next_sequence_id) > u16::MAX / 2 {
+            // retransmission of something we already have
+            return Vec::new();
+        }
+
+        self.queue.entry(packet.header.sequence_id).or_insert(packet);
+
+        let mut payloads = Vec::new();
+
+        while let Some(mut packet) = self.next_sequence_id.and_then(|s| self.queue.remove(&s)) {
+            self.next_sequence_id = self.next_sequence_id.map(|s| s.wrapping_add(1));
+
+            self.cipher.apply_keystream(&mut packet.payload);
+            payloads.push(packet.payload);
+        }
+
+        payloads
+    }
+}
+
+struct Session{
+    server: SocketAddrV4,
+    server_port: VirtualPort,
+    // when the session started, the replay goes in this order so logins come before the secure
+    // connections using their tickets
+    time: f64,
+    // the connect carried a ticket, so this is a session with the secure server
+    ticketed: bool,
+    to_server: Stream,
+    to_client: Stream,
+    // everything the client asked in order and what the server answered, by call id
+    requests: Vec<RMCMessage>,
+    responses: HashMap<u32, RMCResponseResult>,
+}
+
+fn packet_type_name(packet_type: u8) -> &'static str{
+    match packet_type {
+        SYN => "SYN",
+        CONNECT => "CONNECT",
+        DATA => "DATA",
+        DISCONNECT => "DISCONNECT",
+        PING => "PING",
+        _ => "UNKNOWN",
+    }
+}
+
+fn hex(data: &[u8]) -> String{
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn describe_response(result: &RMCResponseResult) -> String{
+    match result {
+        RMCResponseResult::Success { call_id, method_id, data } =>
+            format!("#{} method {} success ({} bytes) {}", call_id, method_id, data.len(), hex(data)),
+        RMCResponseResult::Error { call_id, error_code } =>
+            format!("#{} error {:#}", call_id, error_code),
+    }
+}
+
+fn call_id(result: &RMCResponseResult) -> u32{
+    match result {
+        RMCResponseResult::Success { call_id, .. } |
+        RMCResponseResult::Error { call_id, .. } => *call_id
+    }
+}
+
+// our call ids differ from the captured ones so only the rest gets compared
+fn same_result(ours: &RMCResponseResult, theirs: &RMCResponseResult) -> bool{
+    match (ours, theirs) {
+        (RMCResponseResult::Success { method_id: a, data: data_a, .. }, RMCResponseResult::Success { method_id: b, data: data_b, .. }) =>
+            a == b && data_a == data_b,
+        (RMCResponseResult::Error { error_code: a, .. }, RMCResponseResult::Error { error_code: b, .. }) =>
+            a == b,
+        _ => false,
+    }
+}
+
+struct Transcript{
+    options: Options,
+    start: Option<Duration>,
+    sessions: BTreeMap<(SocketAddrV4, VirtualPort), Session>,
+}
+
+impl Transcript{
+    fn process_datagram(&mut self, datagram: Datagram){
+        let to_server = datagram.destination.port() == self.options.port;
+
+        if !to_server && datagram.source.port() != self.options.port {
+            return;
+        }
+
+        let start = *self.start.get_or_insert(datagram.timestamp);
+        let time = datagram.timestamp.saturating_sub(start).as_secs_f64();
+
+        let (client, server) = if to_server {
+            (datagram.source, datagram.destination)
+        } else {
+            (datagram.destination, datagram.source)
+        };
+
+        let mut cursor = Cursor::new(&datagram.payload);
+
+        while (cursor.position() as usize) < datagram.payload.len() {
+            let packet = match PRUDPPacket::new(&mut cursor){
+                Ok(p) => p,
+                Err(e) => {
+                    println!("[{:>10.3}] {} unable to read packet: {}", time, client, e);
+                    break;
+                }
+            };
+
+            self.process_packet(time, client, server, to_server, packet);
+        }
+    }
+
+    fn process_packet(&mut self, time: f64, client: SocketAddrV4, server: SocketAddrV4, to_server: bool, packet: PRUDPPacket){
+        let arrow = if to_server { "->" } else { "<-" };
+
+        let (client_port, server_port) = if to_server {
+            (packet.header.source_port, packet.header.destination_port)
+        } else {
+            (packet.header.destination_port, packet.header.source_port)
+        };
+
+        let packet_type = packet.header.types_and_flags.get_types();
+        let flags = packet.header.types_and_flags.get_flags();
+
+        if packet_type != DATA || (flags & ACK) != 0 {
+            println!(
+                "[{:>10.3}] {} {} server {}{} seq {}",
+                time, client, arrow, packet_type_name(packet_type), if (flags & ACK) != 0 { " ACK" } else { "" }, packet.header.sequence_id
+            );
+        }
+
+        let key = &self.options.key;
+
+        // a new connection resets the rc4 streams
+        if packet_type == CONNECT && to_server && (flags & ACK) == 0 {
+            self.sessions.insert((client, client_port), Session{
+                server,
+                server_port,
+                time,
+                ticketed: !packet.payload.is_empty(),
+                to_server: Stream::new(key, Some(packet.header.sequence_id.wrapping_add(1))),
+                to_client: Stream::new(key, Some(1)),
+                requests: Vec::new(),
+                responses: HashMap::new(),
+            });
+        }
+
+        if packet_type != DATA || (flags & ACK) != 0 {
+            return;
+        }
+
+        if (flags & RELIABLE) == 0 {
+            println!("[{:>10.3}] {} {} server unreliable data ({} bytes)", time, client, arrow, packet.payload.len());
+            return;
+        }
+
+        let session = self.sessions.entry((client, client_port)).or_insert_with(|| {
+            warn!("data for {} without a connect in the capture, decryption will most likely fail", client);
+
+            Session{
+                server,
+                server_port,
+                time,
+                ticketed: false,
+                to_server: Stream::new(key, None),
+                to_client: Stream::new(key, None),
+                requests: Vec::new(),
+                responses: HashMap::new(),
+            }
+        });
+
+        let stream = if to_server { &mut session.to_server } else { &mut session.to_client };
+
+        for payload in stream.push(packet) {
+            let mut cursor = Cursor::new(&payload);
+
+            while (cursor.position() as usize) < payload.len() {
+                let start = cursor.position();
+
+                let header = match MessageHeader::read(&mut cursor) {
+                    Ok(h) => h,
+                    Err(e) => {
+                        println!("[{:>10.3}] {} {} server undecodable rmc ({}): {}", time, client, arrow, e, hex(&payload));
+                        break;
+                    }
+                };
+
+                cursor.set_position(start);
+
+                let result = if header.is_request {
+                    RMCMessage::new(&mut cursor).map(|request| {
+                        println!(
+                            "[{:>10.3}] {} {} server request #{} protocol {} method {} ({} bytes) {}",
+                            time, client, arrow, request.call_id, request.protocol_id, request.method_id,
+                            request.rest_of_data.len(), hex(&request.rest_of_data)
+                        );
+
+                        if to_server {
+                            session.requests.push(request);
+                        }
+                    })
+                } else {
+                    RMCResponse::new(&mut cursor).map(|response| {
+                        println!(
+                            "[{:>10.3}] {} {} server response protocol {} {}",
+                            time, client, arrow, response.protocol_id, describe_response(&response.response_result)
+                        );
+
+                        if !to_server {
+                            session.responses.insert(call_id(&response.response_result), response.response_result);
+                        }
+                    })
+                };
+
+                if let Err(e) = result {
+                    println!("[{:>10.3}] {} {} server invalid rmc: {}", time, client, arrow, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    // the accounts which logged in during the capture, with the pid the original server gave them
+    // and the token they logged in with so that the local server lets them in too
+    fn captured_accounts(&self, ctx: &SerializationContext) -> Vec<Account>{
+        let mut accounts = Vec::new();
+
+        for session in self.sessions.values() {
+            let logins = session.requests.iter()
+                .filter(|r| r.protocol_id == auth::PROTOCOL_ID && r.method_id == auth::METHOD_LOGIN_EX);
+
+            for request in logins {
+                let Ok((username, any)) = <(String, Any)>::deserialize(&mut Cursor::new(&request.rest_of_data), ctx) else {
+                    warn!("unable to read the login #{} of the capture", request.call_id);
+                    continue;
+                };
+
+                let AnyData::AuthenticationInfo(authentication_info) = any.data else {
+                    continue;
+                };
+
+                let captured_pid = match session.responses.get(&request.call_id) {
+                    Some(RMCResponseResult::Success { data, .. }) =>
+                        <(ResultCode, u32)>::deserialize(&mut Cursor::new(data), ctx).ok().map(|(_, pid)| pid),
+                    _ => None,
+                };
+
+                let Some(pid) = captured_pid.or_else(|| username.parse().ok()) else {
+                    warn!("no pid for the captured login of {}", username);
+                    continue;
+                };
+
+                accounts.push(Account{
+                    pid,
+                    username,
+                    token: authentication_info.token,
+                });
+            }
+        }
+
+        accounts
+    }
+
+    // sends every request of every session to a local server and compares the results with what
+    // the original server answered
+    async fn replay(&self){
+        let ctx = SerializationContext::for_version(self.options.nex_version);
+
+        let (router, _) = Router::new(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await
+            .expect("unable to start local router");
+
+        let accounts = Accounts::default();
+
+        for account in self.captured_accounts(&ctx) {
+            accounts.add(account);
+        }
+
+        let mut sessions: Vec<_> = self.sessions.iter().collect();
+        sessions.sort_by(|(_, a), (_, b)| a.time.total_cmp(&b.time));
+
+        // the local server runs auth and secure on the same router, so logins get sent to the
+        // port the captured secure sessions used
+        let secure_station_url = sessions.iter().find(|(_, s)| s.ticketed).map(|(_, s)| format!(
+            "prudp:/address={};port={};CID=1;PID=2;sid={};stream={};type=2",
+            router.get_own_address().ip(),
+            router.get_own_address().port(),
+            s.server_port.get_port_number(),
+            s.server_port.get_stream_type()
+        ));
+
+        let rmcserver = RMCProtocolServer::new(Box::new([
+            Box::new(auth::AuthenticationProtocol),
+            Box::new(secure::SecureConnectionProtocol),
+        ]), Arc::new(ServerState{
+            accounts: Arc::new(accounts),
+            secure_station_url,
+            ..Default::default()
+        }), ctx);
+
+        let mut sockets = Vec::new();
+        // the ticket of the last replayed login of each client, for its secure session
+        let mut tickets: HashMap<Ipv4Addr, Vec<u8>> = HashMap::new();
+        let mut matching = 0;
+        let mut differing = 0;
+
+        for ((client, _), session) in sessions {
+            println!("replaying {} requests of {} to {}", session.requests.len(), client, session.server);
+
+            if !sockets.iter().any(|(port, _)| *port == session.server_port) {
+                match rmcserver.listen(router.clone(), session.server_port, self.options.access_key).await {
+                    Ok(socket) => sockets.push((session.server_port, socket)),
+                    Err(e) => {
+                        error!("unable to listen on {:?}: {}", session.server_port, e);
+                        continue;
+                    }
+                }
+            }
+
+            let connection = if session.ticketed {
+                let Some(ticket) = tickets.get(client.ip()) else {
+                    error!("no replayed login of {} to get a ticket for its secure session from", client);
+                    continue;
+                };
+
+                Connection::connect_with_ticket(router.get_own_address(), session.server_port, self.options.access_key, default_ciphers(), ticket).await
+            } else {
+                Connection::connect(router.get_own_address(), session.server_port, self.options.access_key, default_ciphers()).await
+            };
+
+            let connection = match connection {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("unable to connect to local server: {}", e);
+                    continue;
+                }
+            };
+
+            for request in &session.requests {
+                let ours = match connection.call(request.protocol_id, request.method_id, request.rest_of_data.clone()).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        println!("DIFF #{} protocol {} method {}: local call failed: {}", request.call_id, request.protocol_id, request.method_id, e);
+                        differing += 1;
+                        continue;
+                    }
+                };
+
+                if let RMCResponseResult::Success { data, .. } = &ours {
+                    if request.protocol_id == auth::PROTOCOL_ID && request.method_id == auth::METHOD_LOGIN_EX {
+                        if let Ok((_, _, Buffer(ticket))) = <(ResultCode, u32, Buffer)>::deserialize(&mut Cursor::new(data), &ctx) {
+                            tickets.insert(*client.ip(), ticket);
+                        }
+                    }
+                }
+
+                let Some(theirs) = session.responses.get(&request.call_id) else {
+                    println!("SKIP #{} protocol {} method {}: no response in capture", request.call_id, request.protocol_id, request.method_id);
+                    continue;
+                };
+
+                if same_result(&ours, theirs) {
+                    matching += 1;
+                } else {
+                    differing += 1;
+                    println!("DIFF #{} protocol {} method {}", request.call_id, request.protocol_id, request.method_id);
+                    println!("  captured: {}", describe_response(theirs));
+                    println!("  local:    {}", describe_response(&ours));
+                }
+            }
+
+            if let Err(e) = connection.disconnect().await {
+                warn!("unable to disconnect from local server: {}", e);
+            }
+        }
+
+        println!("{} responses matched, {} differed", matching, differing);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    TermLogger::init(LevelFilter::Warn, Config::default(), TerminalMode::Stderr, ColorChoice::Auto).unwrap();
+
+    let options = parse_options();
+
+    let file = match File::open(&options.capture) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("unable to open {}: {}", options.capture, e);
+            exit(1)
+        }
+    };
+
+    let datagrams = match read_datagrams(&mut BufReader::new(file)) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("unable to read capture: {}", e);
+            exit(1)
+        }
+    };
+
+    let mut transcript = Transcript{
+        options,
+        start: None,
+        sessions: BTreeMap::new(),
+    };
+
+    for datagram in datagrams {
+        transcript.process_datagram(datagram);
+    }
+
+    if transcript.options.replay {
+        transcript.replay().await;
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::time::Duration;
+    use splatoon_server_rust::pcap::Datagram;
+    use splatoon_server_rust::pcap::reader::read_datagrams;
+    use splatoon_server_rust::pcap::writer::{Direction, PcapngWriter};
+    use splatoon_server_rust::prudp::packet::{PRUDPHeader, PRUDPPacket, VirtualPort};
+    use splatoon_server_rust::prudp::packet::flags::RELIABLE;
+    use splatoon_server_rust::prudp::packet::types::{CONNECT, DATA};
+    use splatoon_server_rust::rmc::message::RMCMessage;
+    use splatoon_server_rust::rmc::response::{generate_response, ErrorCode, RMCResponseResult};
+    use splatoon_server_rust::rmc::structures::NexVersion;
+    use splatoon_server_rust::protocols::accounts::Account;
+    use splatoon_server_rust::protocols::auth;
+    use splatoon_server_rust::rmc::structures::{RmcSerialize, SerializationContext};
+    use splatoon_server_rust::rmc::structures::any::{Any, AnyData};
+    use splatoon_server_rust::rmc::structures::authentication_info::AuthenticationInfo;
+    use splatoon_server_rust::rmc::structures::result_code::ResultCode;
+    use super::{new_cipher, same_result, Options, Session, Stream, Transcript};
+
+    const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1234);
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 60000);
+
+    fn datagram(to_server: bool, packet_type: u8, sequence_id: u16, payload: Vec<u8>) -> (Datagram, Direction){
+        let mut packet = PRUDPPacket{
+            header: PRUDPHeader::default(),
+            packet_signature: [0; 16],
+            options: Vec::new(),
+            payload,
+        };
+
+        let (client_port, server_port) = (VirtualPort::new(15, 10), VirtualPort::new(1, 10));
+
+        packet.header.types_and_flags.set_types(packet_type);
+        packet.header.sequence_id = sequence_id;
+
+        if to_server {
+            packet.header.source_port = client_port;
+            packet.header.destination_port = server_port;
+        } else {
+            packet.header.source_port = server_port;
+            packet.header.destination_port = client_port;
+        }
+
+        if packet_type == DATA {
+            packet.header.types_and_flags.set_flag(RELIABLE);
+        }
+
+        packet.set_sizes();
+
+        let mut data = Vec::new();
+        packet.write_to(&mut data).unwrap();
+
+        let (source, destination, direction) = match to_server {
+            true => (CLIENT, SERVER, Direction::Inbound),
+            false => (SERVER, CLIENT, Direction::Outbound),
+        };
+
+        (Datagram{
+            timestamp: Duration::from_secs(1_700_000_000),
+            source,
+            destination,
+            payload: data,
+        }, direction)
+    }
+
+    // a login with two calls where the second request arrived before the first one
+    fn capture() -> Vec<u8>{
+        let key = b"CD&ML";
+
+        let requests = [
+            RMCMessage{ protocol_id: 10, call_id: 1, method_id: 2, rest_of_data: vec![1, 2, 3] },
+            RMCMessage{ protocol_id: 10, call_id: 2, method_id: 3, rest_of_data: vec![] },
+        ];
+
+        let responses = [
+            generate_response(10, RMCResponseResult::Success { call_id: 1, method_id: 2, data: vec![4, 5] }).unwrap(),
+            generate_response(10, RMCResponseResult::Error { call_id: 2, error_code: ErrorCode::RendezVous_InvalidUsername }).unwrap(),
+        ];
+
+        let mut cipher = new_cipher(key);
+        let requests = requests.map(|request| {
+            let mut data = request.to_data().unwrap();
+            cipher.apply_keystream(&mut data);
+            data
+        });
+
+        let mut cipher = new_cipher(key);
+        let responses = responses.map(|mut data| {
+            cipher.apply_keystream(&mut data);
+            data
+        });
+
+        let [first_request, second_request] = requests;
+        let [first_response, second_response] = responses;
+
+        let datagrams = [
+            datagram(true, CONNECT, 0, Vec::new()),
+            datagram(true, DATA, 2, second_request),
+            datagram(true, DATA, 1, first_request),
+            datagram(false, DATA, 1, first_response),
+            datagram(false, DATA, 2, second_response),
+        ];
+
+        let mut file = Vec::new();
+        let mut writer = PcapngWriter::new(&mut file).unwrap();
+
+        for (datagram, direction) in &datagrams {
+            writer.write_datagram(datagram, *direction).unwrap();
+        }
+
+        drop(writer);
+        file
+    }
+
+    fn transcript_for(port: u16) -> Transcript{
+        Transcript{
+            options: Options{
+                capture: String::new(),
+                port,
+                key: b"CD&ML".to_vec(),
+                access_key: "6f599f81",
+                nex_version: NexVersion::new(3, 8, 3),
+                replay: false,
+            },
+            start: None,
+            sessions: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn transcript(){
+        let mut transcript = transcript_for(SERVER.port());
+
+        for datagram in read_datagrams(&mut Cursor::new(capture())).unwrap() {
+            transcript.process_datagram(datagram);
+        }
+
+        assert_eq!(transcript.sessions.len(), 1);
+
+        let session = &transcript.sessions[&(CLIENT, VirtualPort::new(15, 10))];
+
+        assert_eq!(session.server, SERVER);
+        assert_eq!(session.server_port, VirtualPort::new(1, 10));
+
+        // put back into the order they were sent in
+        let requests: Vec<_> = session.requests.iter()
+            .map(|r| (r.call_id, r.method_id, r.rest_of_data.clone()))
+            .collect();
+        assert_eq!(requests, [(1, 2, vec![1, 2, 3]), (2, 3, vec![])]);
+
+        assert_eq!(session.responses[&1], RMCResponseResult::Success { call_id: 1, method_id: 2, data: vec![4, 5] });
+        assert_eq!(session.responses[&2], RMCResponseResult::Error { call_id: 2, error_code: ErrorCode::RendezVous_InvalidUsername });
+    }
+
+    #[test]
+    fn replay_diff(){
+        let captured = RMCResponseResult::Success { call_id: 1, method_id: 2, data: vec![4, 5] };
+
+        assert!(same_result(&RMCResponseResult::Success { call_id: 7, method_id: 2, data: vec![4, 5] }, &captured));
+        assert!(!same_result(&RMCResponseResult::Success { call_id: 1, method_id: 2, data: vec![4] }, &captured));
+        assert!(!same_result(&RMCResponseResult::Error { call_id: 1, error_code: ErrorCode::Core_NotImplemented }, &captured));
+
+        assert!(same_result(
+            &RMCResponseResult::Error { call_id: 7, error_code: ErrorCode::Core_NotImplemented },
+            &RMCResponseResult::Error { call_id: 1, error_code: ErrorCode::Core_NotImplemented }
+        ));
+    }
+
+    #[test]
+    fn captured_accounts(){
+        let ctx = SerializationContext::for_version(NexVersion::new(3, 8, 3));
+        let mut transcript = transcript_for(SERVER.port());
+
+        let login = |call_id, username: &str| {
+            let authentication_info = Any{
+                name: "AuthenticationInfo".to_string(),
+                data: AnyData::AuthenticationInfo(AuthenticationInfo{
+                    token: format!("token of {}", username),
+                    ..Default::default()
+                }),
+            };
+
+            let mut data = Vec::new();
+            (username.to_string(), authentication_info).serialize(&mut data, &ctx).unwrap();
+
+            RMCMessage{ protocol_id: auth::PROTOCOL_ID, call_id, method_id: auth::METHOD_LOGIN_EX, rest_of_data: data }
+        };
+
+        let mut response = Vec::new();
+        (ResultCode::SUCCESS, 1337u32).serialize(&mut response, &ctx).unwrap();
+
+        transcript.sessions.insert((CLIENT, VirtualPort::new(15, 10)), Session{
+            server: SERVER,
+            server_port: VirtualPort::new(1, 10),
+            time: 0.0,
+            ticketed: false,
+            to_server: Stream::new(b"CD&ML", None),
+            to_client: Stream::new(b"CD&ML", None),
+            // the pid comes from the captured response if there is one, otherwise the username
+            requests: vec![login(1, "someone"), login(2, "42"), login(3, "nobody")],
+            responses: [(1, RMCResponseResult::Success { call_id: 1, method_id: auth::METHOD_LOGIN_EX, data: response })].into(),
+        });
+
+        assert_eq!(transcript.captured_accounts(&ctx), [
+            Account{ pid: 1337, username: "someone".to_string(), token: "token of someone".to_string() },
+            Account{ pid: 42, username: "42".to_string(), token: "token of 42".to_string() },
+        ]);
+    }
+}
@@ -0,0 +1,595 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+use crate::protocols::{protocol_by_name, PROTOCOL_NAMES};
+use crate::protocols::server::DEFAULT_ENCRYPTION_KEY;
+use crate::prudp::packet::VirtualPort;
+use crate::prudp::rate_limit::RateLimits;
+use crate::rmc::structures::NexVersion;
+
+#[derive(Debug, Error)]
+pub enum Error{
+    #[error("unable to read config file {path}: {source}")]
+    Io{
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("invalid config file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("invalid value {value:?} in environment variable {name}")]
+    InvalidEnv{
+        name: String,
+        value: String,
+    },
+    #[error("no titles configured")]
+    NoTitles,
+    #[error("title {0}: no servers configured")]
+    NoServers(String),
+    #[error("unknown title {0:?}")]
+    UnknownTitle(String),
+    #[error("title name {0:?} is used more than once")]
+    DuplicateTitle(String),
+    #[error("server name {0:?} is used more than once")]
+    DuplicateName(String),
+    #[error("server {server}: virtual port {port} has to be between 1 and 15")]
+    InvalidVirtualPort{
+        server: String,
+        port: u8,
+    },
+    #[error("server {server}: virtual port {port} on {bind} is already used by another server")]
+    DuplicateEndpoint{
+        server: String,
+        bind: SocketAddrV4,
+        port: u8,
+    },
+    #[error("server {0}: no protocols enabled")]
+    NoProtocols(String),
+    #[error("server {server}: unknown protocol {protocol:?} (known protocols: {known})")]
+    UnknownProtocol{
+        server: String,
+        protocol: String,
+        known: String,
+    },
+    #[error("title {0}: access key must not be empty")]
+    EmptyAccessKey(String),
+    #[error("title {0}: encryption key has to be 5 bytes long")]
+    InvalidEncryptionKey(String),
+    #[error("server {0}: there is no address to advertise, set advertised_address when binding to 0.0.0.0")]
+    NoAdvertisedAddress(String),
+    #[error("the admin api needs a token")]
+    EmptyAdminToken,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn from_str<'de, T: FromStr, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    T::Err: Display
+{
+    let s = String::deserialize(deserializer)?;
+
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+fn default_virtual_ports() -> Vec<u8>{
+    vec![1]
+}
+
+fn default_stream_type() -> u8{
+    10
+}
+
+fn default_access_key() -> String{
+    "6f599f81".to_string()
+}
+
+fn default_encryption_key() -> String{
+    DEFAULT_ENCRYPTION_KEY.to_string()
+}
+
+fn default_shutdown_timeout() -> u64{
+    10
+}
+
+fn default_nex_version() -> NexVersion{
+    NexVersion::new(3, 8, 3)
+}
+
+fn default_admin_bind() -> SocketAddr{
+    SocketAddr::from((Ipv4Addr::LOCALHOST, 8080))
+}
+
+fn default_metrics_bind() -> SocketAddr{
+    SocketAddr::from((Ipv4Addr::LOCALHOST, 9100))
+}
+
+fn default_bans_file() -> PathBuf{
+    PathBuf::from("bans.json")
+}
+
+fn default_accounts_file() -> PathBuf{
+    PathBuf::from("accounts.json")
+}
+
+/// A single router endpoint and the protocols running on it, servers of different titles which
+/// bind to the same address share one router and are told apart by their virtual ports
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig{
+    // also used for the environment overrides, e.g. AUTH_SERVER_PORT for a server named "auth"
+    pub name: String,
+    pub bind: SocketAddrV4,
+    // the address clients are told to connect to, defaults to the one in `bind`
+    pub advertised_address: Option<Ipv4Addr>,
+    #[serde(default = "default_virtual_ports")]
+    pub virtual_ports: Vec<u8>,
+    #[serde(default = "default_stream_type")]
+    pub stream_type: u8,
+    pub protocols: Vec<String>,
+}
+
+impl ServerConfig{
+    pub fn advertised_address(&self) -> SocketAddrV4{
+        SocketAddrV4::new(self.advertised_address.unwrap_or(*self.bind.ip()), self.bind.port())
+    }
+
+    pub fn virtual_ports(&self) -> impl Iterator<Item=VirtualPort> + '_{
+        self.virtual_ports.iter().map(|p| VirtualPort::new(*p, self.stream_type))
+    }
+}
+
+/// Everything belonging to one game, the keys and nex version are the same for all of its servers
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TitleConfig{
+    pub name: String,
+    // titles with the same realm share their accounts, defaults to the name of the title
+    pub realm: Option<String>,
+    #[serde(default = "default_access_key")]
+    pub access_key: String,
+    #[serde(default = "default_encryption_key")]
+    pub encryption_key: String,
+    #[serde(default = "default_nex_version", deserialize_with = "from_str")]
+    pub nex_version: NexVersion,
+    pub servers: Vec<ServerConfig>,
+}
+
+impl TitleConfig{
+    pub fn realm(&self) -> &str{
+        self.realm.as_deref().unwrap_or(&self.name)
+    }
+
+    // where the auth server sends clients after logging in, the first server running the secure
+    // connection protocol
+    pub fn secure_station_url(&self) -> Option<String>{
+        let server = self.servers.iter().find(|s| s.protocols.iter().any(|p| p == "SecureConnection"))?;
+        let port = server.virtual_ports().next()?;
+        let address = server.advertised_address();
+
+        Some(format!(
+            "prudp:/address={};port={};CID=1;PID=2;sid={};stream={};type=2",
+            address.ip(),
+            address.port(),
+            port.get_port_number(),
+            port.get_stream_type()
+        ))
+    }
+}
+
+/// Maintenance to start the servers in, it can be ended at runtime
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceConfig{
+    // the titles which are in maintenance, all of them if this is empty
+    #[serde(default)]
+    pub titles: Vec<String>,
+    pub message: Option<String>,
+    #[serde(default)]
+    pub allowed_pids: Vec<u32>,
+}
+
+/// The http api for inspecting and controlling the running servers
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig{
+    // only reachable from the machine itself unless this is changed
+    #[serde(default = "default_admin_bind")]
+    pub bind: SocketAddr,
+    // has to be sent as a bearer token with every request
+    #[serde(default)]
+    pub token: String,
+}
+
+/// Where prometheus can scrape the metrics from, there is no authentication on this
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig{
+    #[serde(default = "default_metrics_bind")]
+    pub bind: SocketAddr,
+}
+
+/// Where the bans are kept, the file gets created with the first ban
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BansConfig{
+    #[serde(default = "default_bans_file")]
+    pub file: PathBuf,
+}
+
+impl Default for BansConfig{
+    fn default() -> Self {
+        Self{
+            file: default_bans_file(),
+        }
+    }
+}
+
+/// Where the accounts which can log in are kept, keyed by their realm
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountsConfig{
+    #[serde(default = "default_accounts_file")]
+    pub file: PathBuf,
+}
+
+impl Default for AccountsConfig{
+    fn default() -> Self {
+        Self{
+            file: default_accounts_file(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config{
+    #[serde(default)]
+    pub titles: Vec<TitleConfig>,
+    // seconds to wait for connections to be closed when shutting down
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    pub maintenance: Option<MaintenanceConfig>,
+    pub admin: Option<AdminConfig>,
+    pub metrics: Option<MetricsConfig>,
+    // applied to every router
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    #[serde(default)]
+    pub bans: BansConfig,
+    #[serde(default)]
+    pub accounts: AccountsConfig,
+}
+
+impl Default for Config{
+    // what main used to start before there was a config file
+    fn default() -> Self {
+        Self{
+            titles: vec![
+                TitleConfig{
+                    name: "splatoon".to_string(),
+                    realm: None,
+                    access_key: default_access_key(),
+                    encryption_key: default_encryption_key(),
+                    nex_version: default_nex_version(),
+                    servers: vec![
+                        ServerConfig{
+                            name: "auth".to_string(),
+                            bind: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 10000),
+                            advertised_address: None,
+                            virtual_ports: default_virtual_ports(),
+                            stream_type: default_stream_type(),
+                            protocols: vec!["Authentication".to_string()],
+                        }
+                    ],
+                }
+            ],
+            shutdown_timeout: default_shutdown_timeout(),
+            maintenance: None,
+            admin: None,
+            metrics: None,
+            rate_limits: RateLimits::default(),
+            bans: BansConfig::default(),
+            accounts: AccountsConfig::default(),
+        }
+    }
+}
+
+fn parse_env<T: FromStr>(name: &str, value: String) -> Result<T>{
+    value.parse().map_err(|_| Error::InvalidEnv {
+        name: name.to_string(),
+        value,
+    })
+}
+
+fn env_prefix(name: &str) -> String{
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+impl Config{
+    pub fn parse(s: &str) -> Result<Self>{
+        Ok(toml::from_str(s)?)
+    }
+
+    // reads the file, applies the environment overrides and validates the result
+    pub fn load(path: &Path) -> Result<Self>{
+        let content = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let mut config = Self::parse(&content)?;
+
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // SERVER_IP and NEX_VERSION apply to everything, <TITLE>_ACCESS_KEY to a single title and
+    // <TITLE>_<SERVER>_SERVER_IP and <TITLE>_<SERVER>_SERVER_PORT to a single server. With only one
+    // title the title can be left out of the server variables (e.g. AUTH_SERVER_PORT).
+    // ADMIN_TOKEN sets the token of the admin api and enables it if it isn't configured,
+    // METRICS_BIND does the same for the metrics endpoint
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()>{
+        if let Some(bind) = var("METRICS_BIND") {
+            self.metrics = Some(MetricsConfig{
+                bind: parse_env("METRICS_BIND", bind)?,
+            });
+        }
+
+        if let Some(token) = var("ADMIN_TOKEN") {
+            self.admin.get_or_insert_with(|| AdminConfig{
+                bind: default_admin_bind(),
+                token: String::new(),
+            }).token = token;
+        }
+
+        let ip: Option<Ipv4Addr> = var("SERVER_IP").map(|v| parse_env("SERVER_IP", v)).transpose()?;
+        let nex_version: Option<NexVersion> = var("NEX_VERSION").map(|v| parse_env("NEX_VERSION", v)).transpose()?;
+
+        let single_title = self.titles.len() == 1;
+
+        for title in &mut self.titles {
+            let title_prefix = env_prefix(&title.name);
+
+            if let Some(nex_version) = nex_version {
+                title.nex_version = nex_version;
+            }
+
+            if let Some(value) = var(&format!("{}_ACCESS_KEY", title_prefix)) {
+                title.access_key = value;
+            }
+
+            for server in &mut title.servers {
+                if let Some(ip) = ip {
+                    server.bind.set_ip(ip);
+                }
+
+                let mut prefixes = vec![format!("{}_{}", title_prefix, env_prefix(&server.name))];
+
+                if single_title {
+                    prefixes.insert(0, env_prefix(&server.name));
+                }
+
+                for prefix in prefixes {
+                    let name = format!("{}_SERVER_IP", prefix);
+                    if let Some(value) = var(&name) {
+                        server.bind.set_ip(parse_env(&name, value)?);
+                    }
+
+                    let name = format!("{}_SERVER_PORT", prefix);
+                    if let Some(value) = var(&name) {
+                        server.bind.set_port(parse_env(&name, value)?);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()>{
+        if self.titles.is_empty() {
+            return Err(Error::NoTitles);
+        }
+
+        let mut titles = HashSet::new();
+        let mut endpoints = HashSet::new();
+
+        for title in &self.titles {
+            if !titles.insert(&title.name) {
+                return Err(Error::DuplicateTitle(title.name.clone()));
+            }
+
+            if title.servers.is_empty() {
+                return Err(Error::NoServers(title.name.clone()));
+            }
+
+            if title.access_key.is_empty() {
+                return Err(Error::EmptyAccessKey(title.name.clone()));
+            }
+
+            if title.encryption_key.len() != 5 {
+                return Err(Error::InvalidEncryptionKey(title.name.clone()));
+            }
+
+            let mut names = HashSet::new();
+
+            for server in &title.servers {
+                let full_name = format!("{}/{}", title.name, server.name);
+
+                if !names.insert(&server.name) {
+                    return Err(Error::DuplicateName(full_name));
+                }
+
+                for &port in &server.virtual_ports {
+                    if !(1..16).contains(&port) {
+                        return Err(Error::InvalidVirtualPort {
+                            server: full_name,
+                            port,
+                        });
+                    }
+
+                    // servers on the same address share a router so they need distinct ports
+                    if !endpoints.insert((server.bind, port)) {
+                        return Err(Error::DuplicateEndpoint {
+                            server: full_name,
+                            bind: server.bind,
+                            port,
+                        });
+                    }
+                }
+
+                if server.protocols.is_empty() {
+                    return Err(Error::NoProtocols(full_name));
+                }
+
+                if let Some(protocol) = server.protocols.iter().find(|p| protocol_by_name(p).is_none()) {
+                    return Err(Error::UnknownProtocol {
+                        server: full_name,
+                        protocol: protocol.clone(),
+                        known: PROTOCOL_NAMES.join(", "),
+                    });
+                }
+
+                if server.advertised_address().ip().is_unspecified() {
+                    return Err(Error::NoAdvertisedAddress(full_name));
+                }
+            }
+        }
+
+        if let Some(maintenance) = &self.maintenance {
+            if let Some(title) = maintenance.titles.iter().find(|t| !titles.contains(t)) {
+                return Err(Error::UnknownTitle(title.clone()));
+            }
+        }
+
+        if self.admin.as_ref().is_some_and(|a| a.token.is_empty()) {
+            return Err(Error::EmptyAdminToken);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use crate::rmc::structures::NexVersion;
+    use super::{Config, Error, MaintenanceConfig};
+
+    const EXAMPLE: &str = r#"
+        [[titles]]
+        name = "splatoon"
+
+        [[titles.servers]]
+        name = "auth"
+        bind = "0.0.0.0:10000"
+        advertised_address = "192.168.1.10"
+        protocols = ["Authentication"]
+
+        [[titles.servers]]
+        name = "secure"
+        bind = "0.0.0.0:10001"
+        advertised_address = "192.168.1.10"
+        virtual_ports = [1, 2]
+        protocols = ["SecureConnection"]
+
+        [[titles]]
+        name = "mario kart 8"
+        realm = "splatoon"
+        access_key = "25dbf96a"
+        nex_version = "3.5"
+
+        [[titles.servers]]
+        name = "auth"
+        bind = "0.0.0.0:10000"
+        advertised_address = "192.168.1.10"
+        virtual_ports = [3]
+        protocols = ["Authentication"]
+    "#;
+
+    #[test]
+    fn parse_and_validate(){
+        let config = Config::parse(EXAMPLE).unwrap();
+
+        config.validate().unwrap();
+
+        let splatoon = &config.titles[0];
+        assert_eq!(splatoon.access_key, "6f599f81");
+        assert_eq!(splatoon.encryption_key, "CD&ML");
+        assert_eq!(splatoon.realm(), "splatoon");
+        assert_eq!(splatoon.servers[0].advertised_address(), SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), 10000));
+        assert_eq!(splatoon.servers[1].virtual_ports().count(), 2);
+        assert_eq!(splatoon.secure_station_url().unwrap(), "prudp:/address=192.168.1.10;port=10001;CID=1;PID=2;sid=1;stream=10;type=2");
+
+        let mk8 = &config.titles[1];
+        assert_eq!(mk8.nex_version, NexVersion::new(3, 5, 0));
+        assert_eq!(mk8.realm(), "splatoon");
+        assert!(mk8.secure_station_url().is_none());
+
+        let mut unknown = config.clone();
+        unknown.titles[0].servers[1].protocols.push("Matchmaking".to_string());
+        assert!(matches!(unknown.validate(), Err(Error::UnknownProtocol { .. })));
+
+        let mut duplicate = config.clone();
+        duplicate.titles[0].servers[1].name = "auth".to_string();
+        assert!(matches!(duplicate.validate(), Err(Error::DuplicateName(_))));
+
+        // both titles on the same router and virtual port
+        let mut shared = config.clone();
+        shared.titles[1].servers[0].virtual_ports = vec![1];
+        assert!(matches!(shared.validate(), Err(Error::DuplicateEndpoint { .. })));
+
+        let mut maintenance = config.clone();
+        maintenance.maintenance = Some(MaintenanceConfig{
+            titles: vec!["splatoon 2".to_string()],
+            ..Default::default()
+        });
+        assert!(matches!(maintenance.validate(), Err(Error::UnknownTitle(_))));
+
+        assert!(config.rate_limits.enabled);
+
+        let admin = Config::parse(&format!("{}\n[admin]\n", EXAMPLE)).unwrap();
+        assert!(matches!(admin.validate(), Err(Error::EmptyAdminToken)));
+        assert!(admin.admin.unwrap().bind.ip().is_loopback());
+
+        assert!(Config::parse("[[titles]]\nname = \"a\"\nservers = []\nport = 3").is_err());
+    }
+
+    #[test]
+    fn env_overrides(){
+        let mut config = Config::parse(EXAMPLE).unwrap();
+
+        config.apply_env(|name| match name {
+            "NEX_VERSION" => Some("4.0.0".to_string()),
+            "SPLATOON_SECURE_SERVER_PORT" => Some("20000".to_string()),
+            "MARIO_KART_8_ACCESS_KEY" => Some("12345678".to_string()),
+            // ambiguous with more than one title
+            "AUTH_SERVER_PORT" => Some("30000".to_string()),
+            _ => None
+        }).unwrap();
+
+        assert_eq!(config.titles[1].access_key, "12345678");
+        assert_eq!(config.titles[0].servers[0].bind.port(), 10000);
+        assert_eq!(config.titles[0].servers[1].bind.port(), 20000);
+        assert!(config.titles.iter().all(|t| t.nex_version == NexVersion::new(4, 0, 0)));
+
+        let mut single = Config::default();
+        single.apply_env(|name| (name == "AUTH_SERVER_PORT").then(|| "30000".to_string())).unwrap();
+        assert_eq!(single.titles[0].servers[0].bind.port(), 30000);
+
+        let invalid = single.apply_env(|name| (name == "AUTH_SERVER_PORT").then(|| "port".to_string()));
+        assert!(matches!(invalid, Err(Error::InvalidEnv { .. })));
+
+        single.apply_env(|name| (name == "ADMIN_TOKEN").then(|| "secret".to_string())).unwrap();
+        assert_eq!(single.admin.unwrap().token, "secret");
+    }
+}
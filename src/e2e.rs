@@ -0,0 +1,702 @@
+// End to end tests which boot the servers in-process on loopback and talk to them through the
+// client side prudp implementation, nothing here leaves localhost.
+//
+// Accounts only live in memory, tests add the ones they log in with. Matchmaking isn't implemented
+// yet, so the flows stop at the secure server.
+
+use std::io::Cursor;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+use crate::metrics::METRICS;
+use log::{LevelFilter, Log, Metadata, Record};
+use crate::protocols::{auth, notifications, secure, trace, HandlerFuture, Protocol, RmcContext, ServerState};
+use crate::protocols::notifications::{categories, participation_subtypes, NotificationEvent, NotificationManager, OfflinePolicy};
+use crate::protocols::accounts::{Account, Accounts};
+use crate::protocols::bans::{BanTarget, Bans};
+use crate::protocols::maintenance::{Maintenance, MaintenanceScope, MaintenanceWindow};
+use crate::protocols::server::RMCProtocolServer;
+use crate::pcap::reader::read_datagrams;
+use crate::prudp::capture::{CaptureConfig, PacketCapture};
+use crate::prudp::client::{default_ciphers, Connection, Error};
+use crate::prudp::packet::{types, PRUDPHeader, PRUDPPacket, VirtualPort};
+use crate::prudp::rate_limit::RateLimits;
+use crate::prudp::router::Router;
+use crate::prudp::socket::Socket;
+use crate::rmc::response::ErrorCode;
+use crate::rmc::structures::any::{Any, AnyData};
+use crate::rmc::structures::authentication_info::AuthenticationInfo;
+use crate::rmc::structures::buffer::Buffer;
+use crate::rmc::structures::connection_data::ConnectionData;
+use crate::rmc::structures::pid::Pid;
+use crate::rmc::structures::result_code::ResultCode;
+use crate::rmc::structures::{RmcSerialize, SerializationContext};
+
+const ACCESS_KEY: &str = "6f599f81";
+const TITLE: &str = "splatoon";
+
+// answers with what the handlers see as the pid of the caller, 0 if there is none
+struct WhoAmIProtocol;
+
+const WHO_AM_I_PROTOCOL_ID: u16 = 0x7F00;
+
+impl Protocol for WhoAmIProtocol{
+    fn id(&self) -> u16 {
+        WHO_AM_I_PROTOCOL_ID
+    }
+
+    fn name(&self) -> &'static str {
+        "WhoAmI"
+    }
+
+    fn handle<'a>(&'a self, ctx: &'a mut RmcContext<'_>, method_id: u32, _params: &'a [u8]) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            ctx.respond(method_id, Ok(ctx.pid().unwrap_or(0)))
+        })
+    }
+}
+
+// keeps the rmc trace lines, the logger is process wide so tests have to pick out their own lines
+struct TraceLog(Mutex<Vec<String>>);
+
+static TRACE_LOG: TraceLog = TraceLog(Mutex::new(Vec::new()));
+
+impl Log for TraceLog{
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == trace::TARGET
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn capture_traces(){
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        log::set_logger(&TRACE_LOG).expect("a logger was already installed");
+        log::set_max_level(LevelFilter::Info);
+    });
+}
+
+fn traced_calls(pid: u32) -> Vec<String>{
+    let pid = format!(" pid={} ", pid);
+
+    TRACE_LOG.0.lock().unwrap().iter()
+        .filter(|l| l.contains(&pid))
+        .cloned()
+        .collect()
+}
+
+// what LoginEx returns
+type LoginResult = (ResultCode, u32, Buffer, ConnectionData, String);
+
+struct TestServers{
+    accounts: Arc<Accounts>,
+    notifications: Arc<NotificationManager>,
+    secure_state: Arc<ServerState>,
+    maintenance: Arc<Maintenance>,
+    bans: Arc<Bans>,
+    auth_router: Arc<Router>,
+    auth_socket: Socket,
+    secure_router: Arc<Router>,
+    secure_socket: Socket,
+    ctx: SerializationContext,
+}
+
+impl TestServers{
+    // starts the auth and secure server on ephemeral ports the same way main does
+    async fn start() -> Self{
+        let ctx = SerializationContext::default();
+        let loopback = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+
+        let capture = Arc::new(PacketCapture::default());
+
+        let (auth_router, _) = Router::with_capture(loopback, capture.clone()).await.expect("unable to start auth router");
+        let (secure_router, _) = Router::with_capture(loopback, capture.clone()).await.expect("unable to start secure router");
+
+        let accounts = Arc::new(Accounts::default());
+        let maintenance = Arc::new(Maintenance::default());
+        let bans = Arc::new(Bans::default());
+        let notifications = NotificationManager::new(ctx, OfflinePolicy::Queue(8));
+
+        let secure_address = secure_router.get_own_address();
+        let secure_station_url = format!("prudp:/address={};port={};CID=1;PID=2;sid=1;stream=10;type=2", secure_address.ip(), secure_address.port());
+
+        let state = || Arc::new(ServerState{
+            title: TITLE.to_string(),
+            realm: TITLE.to_string(),
+            accounts: accounts.clone(),
+            secure_station_url: Some(secure_station_url.clone()),
+            maintenance: maintenance.clone(),
+            bans: bans.clone(),
+            notifications: Some(notifications.clone()),
+            ..Default::default()
+        });
+
+        let auth_server = RMCProtocolServer::new(Box::new([
+            Box::new(auth::AuthenticationProtocol)
+        ]), state(), ctx);
+
+        let secure_state = state();
+
+        let secure_server = RMCProtocolServer::new(Box::new([
+            Box::new(secure::SecureConnectionProtocol),
+            Box::new(WhoAmIProtocol),
+        ]), secure_state.clone(), ctx);
+
+        let auth_socket = auth_server.listen(auth_router.clone(), VirtualPort::new(1, 10), ACCESS_KEY)
+            .await.expect("unable to create auth socket");
+        let secure_socket = secure_server.listen(secure_router.clone(), VirtualPort::new(1, 10), ACCESS_KEY)
+            .await.expect("unable to create secure socket");
+
+        maintenance.register_socket(TITLE, &auth_socket.get_socket_data());
+        maintenance.register_socket(TITLE, &secure_socket.get_socket_data());
+        bans.register_socket(&auth_socket.get_socket_data());
+        bans.register_socket(&secure_socket.get_socket_data());
+        notifications.register_socket(&secure_socket.get_socket_data());
+
+        Self{
+            accounts,
+            notifications,
+            secure_state,
+            maintenance,
+            bans,
+            auth_router,
+            auth_socket,
+            secure_router,
+            secure_socket,
+            ctx,
+        }
+    }
+
+    async fn connect_auth(&self) -> Connection{
+        Connection::connect(self.auth_router.get_own_address(), VirtualPort::new(1, 10), ACCESS_KEY, default_ciphers())
+            .await.expect("unable to connect to auth server")
+    }
+
+    async fn connect_secure(&self) -> Connection{
+        Connection::connect(self.secure_router.get_own_address(), VirtualPort::new(1, 10), ACCESS_KEY, default_ciphers())
+            .await.expect("unable to connect to secure server")
+    }
+
+    async fn connect_secure_with_ticket(&self, ticket: &[u8]) -> Connection{
+        Connection::connect_with_ticket(self.secure_router.get_own_address(), VirtualPort::new(1, 10), ACCESS_KEY, default_ciphers(), ticket)
+            .await.expect("unable to connect to secure server")
+    }
+
+    fn add_account(&self, pid: u32){
+        self.accounts.add(Account{
+            pid,
+            username: pid.to_string(),
+            token: token_of(&pid.to_string()),
+        });
+    }
+
+    async fn login(&self, auth: &Connection, username: &str) -> Result<LoginResult, Error>{
+        let authentication_info = Any{
+            name: "AuthenticationInfo".to_string(),
+            data: AnyData::AuthenticationInfo(AuthenticationInfo{
+                token: token_of(username),
+                ..Default::default()
+            }),
+        };
+
+        auth.invoke(auth::PROTOCOL_ID, auth::METHOD_LOGIN_EX, &(username.to_string(), authentication_info), &self.ctx).await
+    }
+
+    // logs in over the auth server and connects to the secure server with the ticket
+    async fn connect_as(&self, pid: u32) -> Connection{
+        self.add_account(pid);
+
+        let auth = self.connect_auth().await;
+        let (_, _, ticket, _, _) = self.login(&auth, &pid.to_string()).await.expect("unable to log in");
+        auth.disconnect().await.unwrap();
+
+        self.connect_secure_with_ticket(&ticket.0).await
+    }
+
+    async fn who_am_i(&self, secure: &Connection) -> u32{
+        secure.invoke(WHO_AM_I_PROTOCOL_ID, 1, &(), &self.ctx).await.expect("unable to ask for the pid")
+    }
+
+    async fn register(&self, secure: &Connection) -> Result<(ResultCode, u32, String), Error>{
+        let station_urls = vec!["prudp:/address=192.168.0.2;port=5000".to_string()];
+
+        secure.invoke(secure::PROTOCOL_ID, secure::METHOD_REGISTER, &station_urls, &self.ctx).await
+    }
+}
+
+// the token the accounts of the tests log in with
+fn token_of(username: &str) -> String{
+    format!("token of {}", username)
+}
+
+#[tokio::test]
+async fn login_register_disconnect(){
+    let servers = TestServers::start().await;
+
+    let auth = servers.connect_auth().await;
+
+    let authentication_info = Any{
+        name: "AuthenticationInfo".to_string(),
+        data: AnyData::AuthenticationInfo(AuthenticationInfo{
+            token: token_of("1234567890"),
+            ngs_version: 3,
+            token_type: 1,
+            server_version: 0,
+            ..Default::default()
+        }),
+    };
+
+    let login_params = ("1234567890".to_string(), authentication_info);
+
+    let unknown = auth.invoke::<_, LoginResult>(auth::PROTOCOL_ID, auth::METHOD_LOGIN_EX, &login_params, &servers.ctx).await;
+    assert!(matches!(unknown, Err(Error::ErrorResponse(ErrorCode::RendezVous_InvalidUsername))));
+
+    servers.add_account(1234567890);
+
+    servers.accounts.add(Account{
+        pid: 1234567891,
+        username: "1234567891".to_string(),
+        token: "another token".to_string(),
+    });
+    let wrong_token = servers.login(&auth, "1234567891").await;
+    assert!(matches!(wrong_token, Err(Error::ErrorResponse(ErrorCode::RendezVous_InvalidPassword))));
+
+    let (result, pid, ticket, connection_data, _): LoginResult = auth.invoke(auth::PROTOCOL_ID, auth::METHOD_LOGIN_EX, &login_params, &servers.ctx).await.unwrap();
+
+    assert_eq!(result, ResultCode::SUCCESS);
+    assert_eq!(pid, 1234567890);
+    assert!(!ticket.0.is_empty());
+    assert!(connection_data.regular_protocols.contains(&format!("port={};", servers.secure_router.get_own_address().port())));
+
+    // the global registry is shared with the other tests, so it can only go up
+    let logins = METRICS.rmc_calls.with(&["Authentication", "LoginEx", "success"]);
+    assert!(logins.get() >= 1);
+
+    let invalid_login = auth.invoke::<_, ()>(auth::PROTOCOL_ID, auth::METHOD_LOGIN_EX, &"1234567890".to_string(), &servers.ctx).await;
+    assert!(matches!(invalid_login, Err(Error::ErrorResponse(ErrorCode::Core_InvalidArgument))));
+
+    auth.disconnect().await.unwrap();
+
+    let secure = servers.connect_secure_with_ticket(&ticket.0).await;
+    let local_addr = secure.local_addr().unwrap();
+
+    let station_urls = vec!["prudp:/address=192.168.0.2;port=5000;natf=0;natm=0;type=2".to_string()];
+
+    let (result, _connection_id, public_url): (ResultCode, u32, String) = secure.invoke(
+        secure::PROTOCOL_ID,
+        secure::METHOD_REGISTER,
+        &station_urls,
+        &servers.ctx
+    ).await.unwrap();
+
+    assert_eq!(result, ResultCode::SUCCESS);
+    assert_eq!(public_url, format!("prudp:/address=127.0.0.1;port={};natf=0;natm=0;type=2", local_addr.port()));
+
+    let connections = servers.secure_socket.connections().await;
+    assert_eq!(connections.len(), 1);
+    {
+        let connection = connections[0].lock().await;
+        let active_connection = connection.active_connection_data.as_ref().expect("connection should be active");
+
+        assert_eq!(active_connection.station_urls, station_urls);
+        assert_eq!(active_connection.ticket_pid, Some(1234567890));
+        assert_eq!(active_connection.pid, Some(1234567890));
+    }
+
+    assert!(servers.secure_socket.find_connection_by_pid(1234567890).await.is_some());
+
+    // tickets can only be used once
+    assert!(servers.accounts.redeem_ticket(&ticket.0).is_none());
+
+    // matchmaking isn't implemented on the secure server yet
+    let matchmake = secure.invoke::<_, ()>(109, 0x2B, &(), &servers.ctx).await;
+    assert!(matches!(matchmake, Err(Error::ErrorResponse(ErrorCode::Core_NotImplemented))));
+
+    // ids of protocols we don't know don't end up in the metrics
+    assert!(METRICS.rmc_calls.with(&["unknown", "unknown", "Core_NotImplemented"]).get() >= 1);
+    let mut metrics = String::new();
+    METRICS.render(&mut metrics);
+    assert!(!metrics.contains("protocol=\"109\""));
+
+    secure.disconnect().await.unwrap();
+
+    // the server processes the disconnect right after acknowledging it
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert!(servers.secure_socket.connections().await.is_empty());
+    assert!(servers.auth_socket.connections().await.is_empty());
+}
+
+#[tokio::test]
+async fn titles_share_a_router(){
+    let ctx = SerializationContext::default();
+    let (router, _) = Router::new(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await.expect("unable to start router");
+
+    const OTHER_ACCESS_KEY: &str = "12345678";
+
+    let splatoon = RMCProtocolServer::new(Box::new([
+        Box::new(secure::SecureConnectionProtocol)
+    ]), Default::default(), ctx);
+
+    let other = RMCProtocolServer::new(Box::new([
+        Box::new(auth::AuthenticationProtocol)
+    ]), Default::default(), ctx);
+
+    let splatoon_socket = splatoon.listen(router.clone(), VirtualPort::new(1, 10), ACCESS_KEY)
+        .await.expect("unable to create socket");
+    let other_socket = other.listen(router.clone(), VirtualPort::new(2, 10), OTHER_ACCESS_KEY)
+        .await.expect("unable to create socket");
+
+    let connection = Connection::connect(router.get_own_address(), VirtualPort::new(2, 10), OTHER_ACCESS_KEY, default_ciphers())
+        .await.expect("unable to connect to the other title");
+
+    // the call only reaches the protocols of the title it was made to
+    let register = connection.invoke::<_, ()>(secure::PROTOCOL_ID, secure::METHOD_REGISTER, &Vec::<String>::new(), &ctx).await;
+    assert!(matches!(register, Err(Error::ErrorResponse(ErrorCode::Core_NotImplemented))));
+
+    assert_eq!(other_socket.connections().await.len(), 1);
+    assert!(splatoon_socket.connections().await.is_empty());
+
+    connection.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_disconnects_everyone(){
+    let servers = TestServers::start().await;
+
+    let secure = servers.connect_secure().await;
+
+    assert!(servers.secure_router.shutdown(Duration::from_secs(1)).await);
+
+    // the disconnect is sent before shutdown returns, give the client a moment to process it
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert!(secure.is_closed());
+    assert!(!servers.secure_socket.is_accepting());
+    assert!(servers.secure_socket.connections().await.is_empty());
+
+    let call = secure.invoke::<_, ()>(secure::PROTOCOL_ID, secure::METHOD_REGISTER, &Vec::<String>::new(), &servers.ctx).await;
+    assert!(matches!(call, Err(Error::Disconnected)));
+}
+
+#[tokio::test]
+async fn maintenance_rejects_logins(){
+    let servers = TestServers::start().await;
+
+    let tester = servers.connect_as(1337).await;
+    assert!(servers.register(&tester).await.is_ok());
+
+    let player = servers.connect_as(1234567890).await;
+    assert!(servers.register(&player).await.is_ok());
+
+    // logged in but not registered yet, the pid of the ticket counts
+    let late_tester = servers.connect_as(1337).await;
+    let unticketed = servers.connect_secure().await;
+
+    let drained = servers.maintenance.start(MaintenanceScope::Title(TITLE.to_string()), MaintenanceWindow{
+        message: Some("testing".to_string()),
+        allowed_pids: [1337].into(),
+        drain: true,
+        ..Default::default()
+    }).await;
+
+    // only the testers are allowed to stay
+    assert_eq!(drained, 2);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(player.is_closed());
+    assert!(unticketed.is_closed());
+    assert!(!tester.is_closed());
+    assert!(!late_tester.is_closed());
+
+    let auth = servers.connect_auth().await;
+
+    let login = servers.login(&auth, "1234567890").await;
+    assert!(matches!(login, Err(Error::ErrorResponse(ErrorCode::Authentication_UnderMaintenance))));
+
+    // testers still get in
+    assert!(servers.register(&late_tester).await.is_ok());
+
+    let (_, pid, ticket, _, _) = servers.login(&auth, "1337").await.unwrap();
+    assert_eq!(pid, 1337);
+
+    let tester = servers.connect_secure_with_ticket(&ticket.0).await;
+    assert!(servers.register(&tester).await.is_ok());
+
+    servers.maintenance.end(&MaintenanceScope::Title(TITLE.to_string()));
+    let player = servers.connect_as(1234567890).await;
+
+    servers.maintenance.start(MaintenanceScope::Global, Default::default()).await;
+
+    let register = servers.register(&player).await;
+    assert!(matches!(register, Err(Error::ErrorResponse(ErrorCode::RendezVous_GameServerMaintenance))));
+
+    servers.maintenance.end(&MaintenanceScope::Global);
+
+    assert!(servers.register(&player).await.is_ok());
+}
+
+#[tokio::test]
+async fn banned_users_are_rejected(){
+    let servers = TestServers::start().await;
+
+    let banned = servers.connect_as(42).await;
+    assert!(servers.register(&banned).await.is_ok());
+
+    let other = servers.connect_as(43).await;
+    assert!(servers.register(&other).await.is_ok());
+
+    // logged in but not registered yet
+    let pending = servers.connect_as(42).await;
+
+    servers.add_account(42);
+    let auth = servers.connect_auth().await;
+    let (_, _, ticket, _, _) = servers.login(&auth, "42").await.unwrap();
+    auth.disconnect().await.unwrap();
+
+    let ban = servers.bans.add(BanTarget::Pid(42), "cheating".to_string(), "test".to_string(), Some(Duration::from_secs(60))).unwrap();
+    assert_eq!(servers.bans.kick(&ban).await, 2);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(banned.is_closed());
+    assert!(pending.is_closed());
+    assert!(!other.is_closed());
+
+    // the ticket was handed out before the ban
+    let banned = servers.connect_secure_with_ticket(&ticket.0).await;
+    let register = servers.register(&banned).await;
+    assert!(matches!(register, Err(Error::ErrorResponse(ErrorCode::RendezVous_AccountTemporarilyDisabled))));
+
+    servers.bans.add(BanTarget::Username("1234567890".to_string()), "cheating".to_string(), "test".to_string(), Some(Duration::from_secs(60))).unwrap();
+
+    let auth = servers.connect_auth().await;
+
+    let login = servers.login(&auth, "1234567890").await;
+    assert!(matches!(login, Err(Error::ErrorResponse(ErrorCode::RendezVous_AccountTemporarilyDisabled))));
+
+    servers.add_account(1337);
+
+    let (_, pid, ticket, _, _) = servers.login(&auth, "1337").await.unwrap();
+    assert_eq!(pid, 1337);
+
+    let secure = servers.connect_secure().await;
+
+    // banning the whole range kicks the open connections right away
+    let ban = servers.bans.add(BanTarget::IpRange("127.0.0.0/8".parse().unwrap()), "abuse".to_string(), "test".to_string(), None).unwrap();
+    assert_eq!(servers.bans.kick(&ban).await, 4);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(auth.is_closed());
+    assert!(secure.is_closed());
+    assert!(other.is_closed());
+
+    let secure = servers.connect_secure_with_ticket(&ticket.0).await;
+
+    let register = servers.register(&secure).await;
+    assert!(matches!(register, Err(Error::ErrorResponse(ErrorCode::RendezVous_AccountDisabled))));
+}
+
+#[tokio::test]
+async fn pids_get_captured_once_registered(){
+    let servers = TestServers::start().await;
+
+    let path = std::env::temp_dir().join(format!("e2e-capture-{}.pcapng", std::process::id()));
+
+    // started on the auth router, the secure router shares the capture
+    servers.auth_router.start_capture(CaptureConfig{
+        path: path.clone(),
+        pids: vec![4343],
+        ..Default::default()
+    }).await.unwrap();
+
+    let secure = servers.connect_as(4343).await;
+    let client_port = secure.local_addr().unwrap().port();
+
+    assert!(servers.register(&secure).await.is_ok());
+    assert_eq!(servers.who_am_i(&secure).await, 4343);
+
+    servers.auth_router.capture().stop();
+
+    let datagrams = read_datagrams(&mut std::fs::File::open(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // the response to register and the who am i call with its response
+    assert!(datagrams.len() >= 3);
+    let secure_address = servers.secure_router.get_own_address();
+    assert!(datagrams.iter().all(|d| {
+        (d.source == secure_address && d.destination.port() == client_port) ||
+            (d.destination == secure_address && d.source.port() == client_port)
+    }));
+}
+
+#[tokio::test]
+async fn rmc_calls_are_rate_limited(){
+    let servers = TestServers::start().await;
+
+    servers.secure_router.rate_limiter().set_limits(RateLimits{
+        rmc_calls_per_second: 0.001,
+        rmc_burst: 1,
+        ..Default::default()
+    });
+
+    let secure = servers.connect_as(1337).await;
+
+    // the first call counts against the ip as the pid only gets registered by it, the pid gets
+    // its own bucket afterwards
+    assert!(servers.register(&secure).await.is_ok());
+    assert!(servers.register(&secure).await.is_ok());
+
+    let register = servers.register(&secure).await;
+    assert!(matches!(register, Err(Error::ErrorResponse(ErrorCode::RendezVous_LimitExceeded))));
+}
+
+#[tokio::test]
+async fn idle_connections_are_evicted(){
+    let servers = TestServers::start().await;
+    let socket = servers.secure_socket.get_socket_data();
+
+    servers.secure_router.rate_limiter().set_limits(RateLimits{
+        max_connections_per_ip: 1,
+        ..Default::default()
+    });
+
+    let packet = |packet_type| {
+        let mut packet = PRUDPPacket{
+            header: PRUDPHeader::default(),
+            packet_signature: [0; 16],
+            options: Vec::new(),
+            payload: Vec::new(),
+        };
+
+        packet.header.source_port = VirtualPort::new(1, 10);
+        packet.header.types_and_flags.set_types(packet_type);
+        packet
+    };
+
+    let client_address = packet(types::SYN).source_sockaddr(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 2), 1));
+
+    // only a syn starts a connection
+    socket.process_packet(client_address, &packet(types::DATA)).await;
+    assert!(socket.connections().await.is_empty());
+
+    socket.process_packet(client_address, &packet(types::SYN)).await;
+    assert_eq!(socket.connections().await.len(), 1);
+
+    let secure = servers.connect_secure().await;
+    assert_eq!(socket.connections().await.len(), 2);
+
+    // the handshake times out long before the established connection does
+    assert_eq!(socket.evict_idle_at(Instant::now() + Duration::from_secs(11)).await, 1);
+    assert_eq!(socket.connections().await.len(), 1);
+
+    assert_eq!(socket.evict_idle_at(Instant::now() + Duration::from_secs(61)).await, 1);
+    assert!(socket.connections().await.is_empty());
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(secure.is_closed());
+
+    // the evicted connection doesn't count against the ip anymore
+    let secure = servers.connect_secure().await;
+    assert!(!secure.is_closed());
+    assert_eq!(socket.connections().await.len(), 1);
+}
+
+#[tokio::test]
+async fn register_sets_the_pid_of_the_context(){
+    let servers = TestServers::start().await;
+
+    let secure = servers.connect_as(1337).await;
+
+    // the ticket alone doesn't make the client known to the handlers
+    assert_eq!(servers.who_am_i(&secure).await, 0);
+
+    // every address gets filled in, this would grow way past what fits into a string
+    let station_urls = vec![format!("prudp:/{}", "address=;".repeat(100))];
+    let register = secure.invoke::<_, (ResultCode, u32, String)>(secure::PROTOCOL_ID, secure::METHOD_REGISTER, &station_urls, &servers.ctx).await;
+    assert!(matches!(register, Err(Error::ErrorResponse(ErrorCode::Core_InvalidArgument))));
+    assert_eq!(servers.who_am_i(&secure).await, 0);
+
+    assert!(servers.register(&secure).await.is_ok());
+
+    assert_eq!(servers.who_am_i(&secure).await, 1337);
+}
+
+#[tokio::test]
+async fn calls_of_registered_users_are_traced(){
+    capture_traces();
+
+    let servers = TestServers::start().await;
+
+    servers.secure_state.rmc_trace.set_pid(4242, true);
+
+    let secure = servers.connect_as(4242).await;
+
+    // the filter only knows the pid once the client registered, which happens during this call
+    assert_eq!(servers.who_am_i(&secure).await, 0);
+    assert!(servers.register(&secure).await.is_ok());
+    assert_eq!(servers.who_am_i(&secure).await, 4242);
+
+    let traced = traced_calls(4242);
+
+    assert_eq!(traced.len(), 2);
+    assert!(traced[0].contains("protocol=SecureConnection(11) method=Register(1)"));
+    assert!(traced[0].contains(r#"params=[station_urls=["prudp:/address=192.168.0.2;port=5000"]]"#));
+    assert!(traced[1].contains("protocol=WhoAmI(32512)"));
+
+    servers.secure_state.rmc_trace.set_pid(4242, false);
+    servers.who_am_i(&secure).await;
+
+    assert_eq!(traced_calls(4242).len(), 2);
+}
+
+#[tokio::test]
+async fn notifications_reach_registered_users(){
+    let servers = TestServers::start().await;
+
+    let event = NotificationEvent{
+        pid_source: Pid(1),
+        notification_type: NotificationEvent::notification_type(categories::PARTICIPATION_EVENT, participation_subtypes::PARTICIPATED),
+        param_1: 1234,
+        str_param: "joined".to_string(),
+        ..Default::default()
+    };
+
+    // nobody is connected as 1337 yet, so this gets queued
+    servers.notifications.send(1337, event.clone()).await;
+
+    let secure = servers.connect_as(1337).await;
+
+    // registering unticketed connections would leave the user unreachable
+    let unticketed = servers.connect_secure().await;
+    let register = servers.register(&unticketed).await;
+    assert!(matches!(register, Err(Error::ErrorResponse(ErrorCode::Core_AccessDenied))));
+
+    assert!(servers.register(&secure).await.is_ok());
+
+    let next_event = || async {
+        let request = tokio::time::timeout(Duration::from_secs(5), secure.next_request()).await
+            .expect("no notification arrived")
+            .expect("connection closed");
+
+        assert_eq!(request.protocol_id, notifications::PROTOCOL_ID);
+        assert_eq!(request.method_id, notifications::METHOD_PROCESS_NOTIFICATION_EVENT);
+
+        NotificationEvent::deserialize(&mut Cursor::new(&request.rest_of_data), &servers.ctx).unwrap()
+    };
+
+    let queued = next_event().await;
+    assert_eq!(queued.param_1, 1234);
+    assert_eq!(queued.str_param, "joined");
+
+    servers.notifications.send(1337, NotificationEvent{
+        param_1: 5678,
+        ..event
+    }).await;
+
+    assert_eq!(next_event().await.param_1, 5678);
+}
@@ -1,9 +1,10 @@
+use std::future::Future;
 use std::io;
-use std::io::Read;
+use std::io::{ErrorKind, Read, Write};
 use std::marker::PhantomData;
 use std::pin::Pin;
 use bytemuck::Pod;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 #[cfg(target_endian = "little")]
 pub const IS_LITTLE_ENDIAN: bool = true;
@@ -15,7 +16,7 @@ pub const IS_BIG_ENDIAN: bool = !IS_LITTLE_ENDIAN;
 
 pub mod little_endian{
     use std::io;
-    use std::io::Read;
+    use std::io::{Read, Write};
 
     #[inline]
     pub fn read_u16(reader: &mut (impl Read + ?Sized)) -> io::Result<u16>{
@@ -23,7 +24,7 @@ pub mod little_endian{
 
         reader.read_exact(&mut data)?;
 
-        Ok(((data[0] as u16) << 8) | (data[1] as u16))
+        Ok((data[0] as u16) | ((data[1] as u16) << 8))
     }
 
     #[inline]
@@ -33,12 +34,30 @@ pub mod little_endian{
         reader.read_exact(&mut data)?;
 
         Ok(
-            ((data[0] as u32) << 24) |
-                ((data[1] as u32) << 16) |
-                ((data[2] as u32) << 8) |
-                (data[3] as u32)
+            (data[0] as u32) |
+                ((data[1] as u32) << 8) |
+                ((data[2] as u32) << 16) |
+                ((data[3] as u32) << 24)
         )
     }
+
+    #[inline]
+    pub fn write_u16(writer: &mut (impl Write + ?Sized), val: u16) -> io::Result<()>{
+        writer.write_all(&[
+            val as u8,
+            (val >> 8) as u8
+        ])
+    }
+
+    #[inline]
+    pub fn write_u32(writer: &mut (impl Write + ?Sized), val: u32) -> io::Result<()>{
+        writer.write_all(&[
+            val as u8,
+            (val >> 8) as u8,
+            (val >> 16) as u8,
+            (val >> 24) as u8
+        ])
+    }
 }
 
 pub struct StructMultiReadIter<'a, T: Pod + SwapEndian>{
@@ -133,6 +152,213 @@ impl<T: Read> ReadExtensions for T{
 
 
 
+pub trait WriteExtensions: Write{
+    #[inline]
+    fn write_le_u16(&mut self, val: u16) -> io::Result<()>{
+        little_endian::write_u16(self, val)
+    }
+
+    #[inline]
+    fn write_le_u32(&mut self, val: u32) -> io::Result<()>{
+        little_endian::write_u32(self, val)
+    }
+
+    #[inline]
+    fn write_le_struct<T: Pod + SwapEndian>(&mut self, data: T) -> io::Result<()>{
+        let data = if cfg!(not(target_endian = "little")){
+            data.swap_endian()
+        } else {
+            data
+        };
+
+        self.write_all(bytemuck::bytes_of(&data))
+    }
+
+    #[inline]
+    fn write_struct<T: Pod + SwapEndian>(&mut self, swap_endian: bool, data: T) -> io::Result<()>{
+        let data = if swap_endian{
+            data.swap_endian()
+        } else {
+            data
+        };
+
+        self.write_all(bytemuck::bytes_of(&data))
+    }
+
+    #[inline]
+    fn write_struct_multi<T: Pod + SwapEndian>(&mut self, swap_endian: bool, data: &[T]) -> io::Result<()>{
+        // no need to copy everything if we dont have to swap anything
+        if !swap_endian{
+            return self.write_all(bytemuck::cast_slice(data));
+        }
+
+        for elem in data{
+            self.write_struct(swap_endian, *elem)?;
+        }
+
+        Ok(())
+    }
+}
+
+// unlike the read extensions this also works on unsized writers like `dyn Write`
+impl<T: Write + ?Sized> WriteExtensions for T{}
+
+
+
+
+// async counterparts of the read and write extensions, the nex types always use the wire order
+// (little endian) for their length prefixes just like their `RmcSerialize` implementations. The
+// names end in `_async` so both can be in scope at the same time.
+pub trait AsyncReadExtensions: AsyncRead + Unpin + Send{
+    fn read_struct_async<T: Pod + SwapEndian + Send>(&mut self, swap_endian: bool) -> impl Future<Output = io::Result<T>> + Send + '_{
+        async move {
+            let mut data = T::zeroed();
+
+            self.read_exact(bytemuck::bytes_of_mut(&mut data)).await?;
+
+            if swap_endian{
+                Ok(data.swap_endian())
+            } else {
+                Ok(data)
+            }
+        }
+    }
+
+    fn read_le_struct_async<T: Pod + SwapEndian + Send>(&mut self) -> impl Future<Output = io::Result<T>> + Send + '_{
+        self.read_struct_async(cfg!(not(target_endian = "little")))
+    }
+
+    fn read_nex_string_async(&mut self) -> impl Future<Output = io::Result<String>> + Send + '_{
+        async move {
+            let len: u16 = self.read_struct_async(IS_BIG_ENDIAN).await?;
+
+            // the length includes the null terminator
+            let Some(len) = len.checked_sub(1) else {
+                return Ok(String::new());
+            };
+
+            let mut data = vec![0; len as usize];
+            self.read_exact(&mut data).await?;
+
+            let _null: u8 = self.read_struct_async(IS_BIG_ENDIAN).await?;
+
+            String::from_utf8(data).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+        }
+    }
+
+    fn read_nex_buffer_async(&mut self) -> impl Future<Output = io::Result<Vec<u8>>> + Send + '_{
+        async move {
+            let len: u32 = self.read_struct_async(IS_BIG_ENDIAN).await?;
+
+            // dont trust the length for the allocation, the buffer only grows with what arrives
+            let mut data = Vec::new();
+            (&mut *self).take(len as u64).read_to_end(&mut data).await?;
+
+            if data.len() != len as usize{
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+
+            Ok(data)
+        }
+    }
+
+    fn read_nex_qbuffer_async(&mut self) -> impl Future<Output = io::Result<Vec<u8>>> + Send + '_{
+        async move {
+            let len: u16 = self.read_struct_async(IS_BIG_ENDIAN).await?;
+
+            let mut data = vec![0; len as usize];
+            self.read_exact(&mut data).await?;
+
+            Ok(data)
+        }
+    }
+
+    // streams the content of a `Buffer` into the writer without keeping all of it in memory,
+    // returns the amount of bytes copied
+    fn copy_nex_buffer_async<'a, W: AsyncWrite + Unpin + Send + ?Sized>(&'a mut self, writer: &'a mut W) -> impl Future<Output = io::Result<u64>> + Send + 'a{
+        async move {
+            let len: u32 = self.read_struct_async(IS_BIG_ENDIAN).await?;
+
+            let copied = tokio::io::copy(&mut (&mut *self).take(len as u64), writer).await?;
+
+            if copied != len as u64{
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+
+            Ok(copied)
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin + Send + ?Sized> AsyncReadExtensions for T{}
+
+pub trait AsyncWriteExtensions: AsyncWrite + Unpin + Send{
+    fn write_struct_async<T: Pod + SwapEndian + Send>(&mut self, swap_endian: bool, data: T) -> impl Future<Output = io::Result<()>> + Send + '_{
+        async move {
+            let data = if swap_endian{
+                data.swap_endian()
+            } else {
+                data
+            };
+
+            self.write_all(bytemuck::bytes_of(&data)).await
+        }
+    }
+
+    fn write_le_struct_async<T: Pod + SwapEndian + Send>(&mut self, data: T) -> impl Future<Output = io::Result<()>> + Send + '_{
+        self.write_struct_async(cfg!(not(target_endian = "little")), data)
+    }
+
+    fn write_struct_multi_async<'a, T: Pod + SwapEndian + Send + Sync>(&'a mut self, swap_endian: bool, data: &'a [T]) -> impl Future<Output = io::Result<()>> + Send + 'a{
+        async move {
+            // no need to copy everything if we dont have to swap anything
+            if !swap_endian{
+                return self.write_all(bytemuck::cast_slice(data)).await;
+            }
+
+            for elem in data{
+                self.write_struct_async(swap_endian, *elem).await?;
+            }
+
+            Ok(())
+        }
+    }
+
+    fn write_nex_string_async<'a>(&'a mut self, string: &'a str) -> impl Future<Output = io::Result<()>> + Send + 'a{
+        async move {
+            // the length includes the null terminator
+            let len = u16::try_from(string.len() + 1)
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "string too long for a nex string"))?;
+
+            self.write_struct_async(IS_BIG_ENDIAN, len).await?;
+            self.write_all(string.as_bytes()).await?;
+            self.write_all(&[0]).await
+        }
+    }
+
+    fn write_nex_buffer_async<'a>(&'a mut self, data: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a{
+        async move {
+            self.write_struct_async(IS_BIG_ENDIAN, data.len() as u32).await?;
+            self.write_all(data).await
+        }
+    }
+
+    fn write_nex_qbuffer_async<'a>(&'a mut self, data: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a{
+        async move {
+            let len = u16::try_from(data.len())
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "data too long for a qBuffer"))?;
+
+            self.write_struct_async(IS_BIG_ENDIAN, len).await?;
+            self.write_all(data).await
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin + Send + ?Sized> AsyncWriteExtensions for T{}
+
+
+
+
 pub trait SwapEndian: Clone + Copy{
     fn swap_endian(self) -> Self;
 }
@@ -221,4 +447,114 @@ impl<T: SwapEndian, const size: usize> SwapEndian for [T; size]{
 
         self
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use super::{AsyncReadExtensions, AsyncWriteExtensions, ReadExtensions, SwapEndian, WriteExtensions};
+
+    // A big endian host has every value in memory in the reversed byte order and passes
+    // IS_BIG_ENDIAN (true) as swap_endian, we simulate that by swapping the value beforehand
+    fn write_as_big_endian_host<T: bytemuck::Pod + SwapEndian>(val: T) -> Vec<u8>{
+        let in_memory_on_big_endian = val.swap_endian();
+
+        let mut out = Vec::new();
+        out.write_struct(true, in_memory_on_big_endian).unwrap();
+        out
+    }
+
+    fn write_as_little_endian_host<T: bytemuck::Pod + SwapEndian>(val: T) -> Vec<u8>{
+        let mut out = Vec::new();
+        out.write_struct(false, val).unwrap();
+        out
+    }
+
+    #[test]
+    fn write_struct_is_host_independent(){
+        assert_eq!(write_as_big_endian_host(0x1234u16), [0x34, 0x12]);
+        assert_eq!(write_as_little_endian_host(0x1234u16), [0x34, 0x12]);
+
+        assert_eq!(write_as_big_endian_host(0x12345678u32), 0x12345678u32.to_le_bytes());
+        assert_eq!(write_as_little_endian_host(0x12345678u32), 0x12345678u32.to_le_bytes());
+
+        let long = 0x0102030405060708u64;
+        assert_eq!(write_as_big_endian_host(long), write_as_little_endian_host(long));
+
+        let array = [0x0102u16, 0x0304, 0x0506];
+        assert_eq!(write_as_big_endian_host(array), [0x02, 0x01, 0x04, 0x03, 0x06, 0x05]);
+    }
+
+    #[test]
+    fn write_struct_multi_matches_single_writes(){
+        let values = [0x0102u16, 0x0304];
+
+        for swap in [false, true]{
+            let mut multi = Vec::new();
+            multi.write_struct_multi(swap, &values).unwrap();
+
+            let mut single = Vec::new();
+            for v in values{
+                single.write_struct(swap, v).unwrap();
+            }
+
+            assert_eq!(multi, single);
+        }
+    }
+
+    #[test]
+    fn little_endian_round_trip(){
+        let mut data = Vec::new();
+        data.write_le_u16(0x1234).unwrap();
+        data.write_le_u32(0x56789ABC).unwrap();
+        data.write_le_struct(0x0102030405060708u64).unwrap();
+
+        assert_eq!(data, [0x34, 0x12, 0xBC, 0x9A, 0x78, 0x56, 8, 7, 6, 5, 4, 3, 2, 1]);
+
+        let mut cursor = Cursor::new(&data);
+        assert_eq!(cursor.read_le_u16().unwrap(), 0x1234);
+        assert_eq!(cursor.read_le_u32().unwrap(), 0x56789ABC);
+        assert_eq!(cursor.read_le_struct::<u64>().unwrap(), 0x0102030405060708);
+    }
+
+    #[tokio::test]
+    async fn async_struct_swapping(){
+        // simulated big endian host like in write_struct_is_host_independent
+        let mut async_data = Vec::new();
+        async_data.write_struct_async(true, 0x1234u16.swap_endian()).await.unwrap();
+        async_data.write_struct_multi_async(false, &[0x01020304u32, 0x05060708]).await.unwrap();
+
+        assert_eq!(async_data, [0x34, 0x12, 4, 3, 2, 1, 8, 7, 6, 5]);
+
+        let mut reader = &async_data[..];
+        let val: u16 = reader.read_struct_async(false).await.unwrap();
+        assert_eq!(val, 0x1234);
+        let val: u32 = reader.read_le_struct_async().await.unwrap();
+        assert_eq!(val, 0x01020304);
+    }
+
+    #[tokio::test]
+    async fn async_nex_types(){
+        let mut data = Vec::new();
+        data.write_nex_string_async("test").await.unwrap();
+        data.write_nex_buffer_async(&[1, 2, 3]).await.unwrap();
+        data.write_nex_qbuffer_async(&[4, 5]).await.unwrap();
+        data.write_nex_buffer_async(&[6; 100]).await.unwrap();
+
+        assert_eq!(&data[..7], [5, 0, b't', b'e', b's', b't', 0]);
+
+        let mut reader = &data[..];
+        assert_eq!(reader.read_nex_string_async().await.unwrap(), "test");
+        assert_eq!(reader.read_nex_buffer_async().await.unwrap(), [1, 2, 3]);
+        assert_eq!(reader.read_nex_qbuffer_async().await.unwrap(), [4, 5]);
+
+        let mut copied = Vec::new();
+        assert_eq!(reader.copy_nex_buffer_async(&mut copied).await.unwrap(), 100);
+        assert_eq!(copied, [6; 100]);
+        assert!(reader.is_empty());
+
+        // the length says 4 gigabytes but only two bytes follow
+        let mut reader = &[0xFF, 0xFF, 0xFF, 0xFF, 1, 2][..];
+        assert!(reader.read_nex_buffer_async().await.is_err());
+    }
+}
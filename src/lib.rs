@@ -0,0 +1,11 @@
+pub mod endianness;
+pub mod prudp;
+pub mod rmc;
+pub mod protocols;
+pub mod pcap;
+pub mod config;
+pub mod admin;
+pub mod metrics;
+
+#[cfg(test)]
+mod e2e;
@@ -1,42 +1,71 @@
-use std::env::current_dir;
 use std::{env, fs};
+use std::collections::HashMap;
 
 use std::fs::File;
-use std::io::Cursor;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::SocketAddrV4;
+use std::path::PathBuf;
+use std::sync::Arc;
 use chrono::Local;
-use log::{error, info, trace};
+use log::{error, info, warn};
 use once_cell::sync::Lazy;
-use rc4::{KeyInit, Rc4, StreamCipher};
-use rc4::consts::U5;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use std::time::Duration;
 use simplelog::{ColorChoice, CombinedLogger, Config, LevelFilter, TerminalMode, TermLogger, WriteLogger};
-use crate::protocols::auth;
-use crate::protocols::server::RMCProtocolServer;
-use crate::prudp::socket::{Socket, SocketData};
-use crate::prudp::packet::{PRUDPPacket, VirtualPort};
-use crate::prudp::router::Router;
-use crate::rmc::message::RMCMessage;
-use crate::rmc::response::{RMCResponse, RMCResponseResult, send_response};
-use crate::rmc::response::ErrorCode::{Core_InvalidIndex, Core_NotImplemented};
-
-mod endianness;
-mod prudp;
-pub mod rmc;
-mod protocols;
-
-static AUTH_SERVER_PORT: Lazy<u16> = Lazy::new(||{
-    env::var("AUTH_SERVER_PORT")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(10000)
+use splatoon_server_rust::admin::{Admin, AdminSocket};
+use splatoon_server_rust::config;
+use splatoon_server_rust::metrics::exporter::Exporter;
+use splatoon_server_rust::config::{ServerConfig, TitleConfig};
+use splatoon_server_rust::protocols::{protocol_by_name, ServerState};
+use splatoon_server_rust::protocols::accounts::{self, Accounts};
+use splatoon_server_rust::protocols::bans::Bans;
+use splatoon_server_rust::protocols::notifications::{NotificationManager, OfflinePolicy};
+use splatoon_server_rust::protocols::maintenance::{Maintenance, MaintenanceScope, MaintenanceWindow};
+use splatoon_server_rust::protocols::trace::{RmcTrace, TraceFilter};
+use splatoon_server_rust::prudp::capture::{CaptureConfig, PacketCapture};
+use splatoon_server_rust::prudp::rate_limit::RateLimits;
+use splatoon_server_rust::protocols::server::RMCProtocolServer;
+use splatoon_server_rust::prudp::socket::Socket;
+use splatoon_server_rust::prudp::router::Router;
+use splatoon_server_rust::rmc::structures::SerializationContext;
+
+
+static CONFIG_FILE: Lazy<PathBuf> = Lazy::new(||{
+    env::var("CONFIG_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("config.toml"))
 });
 
-static OWN_IP: Lazy<Ipv4Addr> = Lazy::new(||{
-    env::var("SERVER_IP")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .expect("no public ip specified")
-});
+// capturing is off unless CAPTURE_FILE is set
+fn capture_config() -> Option<CaptureConfig>{
+    fn list<T: std::str::FromStr>(name: &str) -> Vec<T>{
+        env::var(name)
+            .map(|s| s.split(',').filter_map(|v| v.trim().parse().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    Some(CaptureConfig{
+        path: env::var("CAPTURE_FILE").ok()?.into(),
+        max_file_size: env::var("CAPTURE_MAX_SIZE").ok().and_then(|s| s.parse().ok()),
+        include_decrypted: env::var("CAPTURE_DECRYPTED").is_ok_and(|s| s == "1" || s == "true"),
+        peer_ips: list("CAPTURE_PEER_IPS"),
+        pids: list("CAPTURE_PIDS"),
+    })
+}
+
+// e.g. RMC_TRACE=all or RMC_TRACE=pid:1337,protocol:11
+fn rmc_trace_filter() -> TraceFilter{
+    let Ok(filter) = env::var("RMC_TRACE") else {
+        return Default::default();
+    };
+
+    filter.parse().unwrap_or_else(|e| {
+        error!("{}, rmc tracing stays off", e);
+        Default::default()
+    })
+}
 
 #[tokio::main]
 async fn main() {
@@ -55,51 +84,277 @@ async fn main() {
     start_servers().await;
 }
 
-async fn auth_server_handle_rmc(packet: PRUDPPacket, rmc_message: RMCMessage){
+// without a config file the auth server gets started the way it always was, only configured
+// through the environment
+fn load_config() -> config::Config{
+    if !CONFIG_FILE.exists() {
+        info!("no config file at {}, using the defaults", CONFIG_FILE.display());
+
+        let mut config = config::Config::default();
+
+        config.apply_env(|name| env::var(name).ok())
+            .and_then(|_| config.validate())
+            .unwrap_or_else(|e| panic!("invalid configuration: {}", e));
+
+        return config;
+    }
+
+    config::Config::load(&CONFIG_FILE).unwrap_or_else(|e| panic!("invalid configuration: {}", e))
+}
+
+// servers of different titles which bind to the same address share one router, all routers share
+// the capture
+async fn get_router(routers: &mut HashMap<SocketAddrV4, (Arc<Router>, JoinHandle<()>)>, addr: SocketAddrV4, rate_limits: &RateLimits, capture: &Arc<PacketCapture>) -> Arc<Router>{
+    if let Some((router, _)) = routers.get(&addr) {
+        return router.clone();
+    }
+
+    let (router, join) =
+        Router::with_capture(addr, capture.clone()).await
+            .unwrap_or_else(|e| panic!("unable to start router on {}: {}", addr, e));
+
+    router.rate_limiter().set_limits(rate_limits.clone());
+
+    // starting the shared capture once is enough
+    if let Some(config) = capture_config().filter(|_| routers.is_empty()) {
+        router.start_capture(config).await
+            .expect("unable to start packet capture");
+    }
+
+    routers.insert(addr, (router.clone(), join));
+
+    router
+}
+
+// per user, the oldest ones get dropped first
+const QUEUED_NOTIFICATIONS: usize = 32;
+
+async fn start_server(title: &TitleConfig, server: &ServerConfig, router: Arc<Router>, accounts: Arc<Accounts>, maintenance: &Arc<Maintenance>, bans: &Arc<Bans>) -> Vec<Socket>{
+    info!("starting {}/{} server on {} (advertised as {})", title.name, server.name, server.bind, server.advertised_address());
+
+    let protocols = server.protocols.iter()
+        .map(|name| protocol_by_name(name).expect("protocols are checked when validating the config"))
+        .collect();
+
+    let serialization_context = SerializationContext::for_version(title.nex_version);
+
+    // events for users who are offline get delivered once they register again
+    let notifications = NotificationManager::new(serialization_context, OfflinePolicy::Queue(QUEUED_NOTIFICATIONS));
+
+    // every server has its own state so nothing leaks over between titles
+    let rmcserver = RMCProtocolServer::new(protocols, Arc::new(ServerState{
+        title: title.name.clone(),
+        realm: title.realm().to_string(),
+        accounts,
+        secure_station_url: title.secure_station_url(),
+        rmc_trace: RmcTrace::new(rmc_trace_filter()),
+        maintenance: maintenance.clone(),
+        bans: bans.clone(),
+        notifications: Some(notifications.clone()),
+        ..Default::default()
+    }), serialization_context);
+
+    // the sockets live as long as the server runs
+    let access_key: &'static str = Box::leak(title.access_key.clone().into_boxed_str());
+    let encryption_key: &'static str = Box::leak(title.encryption_key.clone().into_boxed_str());
+
+    let mut sockets = Vec::new();
+
+    for port in server.virtual_ports() {
+        let socket = rmcserver.listen_with_key(router.clone(), port, access_key, encryption_key)
+            .await.expect("unable to create socket");
+
+        maintenance.register_socket(&title.name, &socket.get_socket_data());
+        bans.register_socket(&socket.get_socket_data());
+        notifications.register_socket(&socket.get_socket_data());
 
+        sockets.push(socket);
+    }
+
+    sockets
+}
+
+async fn shutdown_signal(){
+    #[cfg(unix)]
+    {
+        let mut terminate = signal(SignalKind::terminate()).expect("unable to listen for SIGTERM");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.expect("unable to listen for ctrl-c");
+}
+
+// picks up bans which were added with the cli while the server is running
+async fn watch_bans(bans: Arc<Bans>){
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        match bans.reload() {
+            Ok(new) => {
+                for ban in new {
+                    bans.kick(&ban).await;
+                }
+            }
+            Err(e) => error!("unable to reload bans: {}", e),
+        }
+    }
 }
 
 async fn start_servers(){
-    info!("starting auth server on {}:{}", *OWN_IP, *AUTH_SERVER_PORT);
-
-    let (auth_server_router, auth_router_join) =
-        Router::new(SocketAddrV4::new(*OWN_IP, *AUTH_SERVER_PORT)).await
-            .expect("unable to startauth server");
-
-    info!("setting up endpoints");
-
-    // dont assign it to the name _ as that will make it drop right here and now
-    let rmcserver = RMCProtocolServer::new(Box::new([
-        Box::new(auth::protocol)
-    ]));
-
-    let mut _socket =
-        Socket::new(
-            auth_server_router.clone(),
-            VirtualPort::new(1,10),
-            "6f599f81",
-            Box::new(|_|{
-                Box::pin(
-                    async move {
-                        let rc4: Rc4<U5> = Rc4::new_from_slice( "CD&ML".as_bytes()).unwrap();
-                        let cypher = Box::new(rc4);
-                        let server_cypher: Box<dyn StreamCipher + Send + Sync> = cypher;
-
-                        let rc4: Rc4<U5> = Rc4::new_from_slice( "CD&ML".as_bytes()).unwrap();
-                        let cypher = Box::new(rc4);
-                        let client_cypher: Box<dyn StreamCipher + Send + Sync> = cypher;
-
-                        (true, (server_cypher, client_cypher))
-                    }
-                )
-            }),
-            Box::new(move |packet, socket, connection|{
-                let rmcserver = rmcserver.clone();
-                Box::pin(async move { rmcserver.process_message(packet, &socket, connection).await; })
-            })
-        ).await.expect("unable to create socket");
+    let config = load_config();
+
+    let maintenance = Arc::new(Maintenance::default());
+
+    if let Some(config) = &config.maintenance {
+        let window = MaintenanceWindow{
+            message: config.message.clone(),
+            allowed_pids: config.allowed_pids.iter().copied().collect(),
+            ..Default::default()
+        };
+
+        let scopes = match config.titles.is_empty() {
+            true => vec![MaintenanceScope::Global],
+            false => config.titles.iter().cloned().map(MaintenanceScope::Title).collect(),
+        };
+
+        for scope in scopes {
+            maintenance.start(scope, window.clone()).await;
+        }
+    }
+
+    let bans = Arc::new(Bans::load(&config.bans.file)
+        .unwrap_or_else(|e| panic!("unable to load bans: {}", e)));
+
+    info!("loaded {} bans from {}", bans.list().len(), config.bans.file.display());
+
+    let mut admin = config.admin.as_ref()
+        .map(|a| Admin::new(a.token.clone(), maintenance.clone(), bans.clone()));
+
+    let mut routers = HashMap::new();
+    let capture = Arc::new(PacketCapture::default());
+    let mut sockets = Vec::new();
+    let mut realm_accounts = accounts::read_file(&config.accounts.file)
+        .unwrap_or_else(|e| panic!("unable to load accounts: {}", e));
+
+    // titles of the same realm share their accounts
+    let mut accounts: HashMap<&str, Arc<Accounts>> = HashMap::new();
+
+    for title in &config.titles {
+        let accounts = accounts.entry(title.realm()).or_insert_with(|| {
+            let accounts = Accounts::default();
+
+            for account in realm_accounts.remove(title.realm()).unwrap_or_default() {
+                if !accounts.add(account.clone()) {
+                    warn!("realm {}: username {} is used by more than one account", title.realm(), account.username);
+                }
+            }
+
+            match accounts.is_empty() {
+                true => warn!("realm {} has no accounts in {}, nobody can log in", title.realm(), config.accounts.file.display()),
+                false => info!("loaded {} accounts of realm {} from {}", accounts.len(), title.realm(), config.accounts.file.display()),
+            }
+
+            Arc::new(accounts)
+        }).clone();
+
+        for server in &title.servers {
+            let router = get_router(&mut routers, server.bind, &config.rate_limits, &capture).await;
+
+            let server_sockets = start_server(title, server, router, accounts.clone(), &maintenance, &bans).await;
+
+            if let Some(admin) = &mut admin {
+                for socket in &server_sockets {
+                    admin.add_socket(AdminSocket{
+                        title: title.name.clone(),
+                        server: server.name.clone(),
+                        socket: socket.get_socket_data(),
+                        serialization_context: SerializationContext::for_version(title.nex_version),
+                    });
+                }
+            }
+
+            sockets.extend(server_sockets);
+        }
+    }
+
+    let admin_task = match (admin, &config.admin) {
+        (Some(mut admin), Some(admin_config)) => {
+            for (router, _) in routers.values() {
+                admin.add_router(router.clone());
+            }
+
+            let listener = TcpListener::bind(admin_config.bind).await
+                .unwrap_or_else(|e| panic!("unable to start admin api on {}: {}", admin_config.bind, e));
+
+            if !admin_config.bind.ip().is_loopback() {
+                warn!("the admin api is reachable from other machines on {}", admin_config.bind);
+            }
+
+            info!("admin api listening on {}", admin_config.bind);
+
+            Some(tokio::spawn(Arc::new(admin).serve(listener)))
+        }
+        _ => None,
+    };
+
+    let metrics_task = match &config.metrics {
+        Some(metrics_config) => {
+            let exporter = Exporter::new(routers.values().map(|(router, _)| router.clone()).collect());
+
+            let listener = TcpListener::bind(metrics_config.bind).await
+                .unwrap_or_else(|e| panic!("unable to serve metrics on {}: {}", metrics_config.bind, e));
+
+            info!("serving metrics on http://{}/metrics", metrics_config.bind);
+
+            Some(tokio::spawn(Arc::new(exporter).serve(listener)))
+        }
+        None => None,
+    };
+
+    let bans_task = tokio::spawn(watch_bans(bans));
+
+    shutdown_signal().await;
+
+    info!("shutting down");
+
+    for task in [admin_task, metrics_task, Some(bans_task)].into_iter().flatten() {
+        task.abort();
+    }
+
+    let timeout = Duration::from_secs(config.shutdown_timeout);
+
+    // all routers drain at the same time, otherwise a slow one would eat up the time of the others
+    let drains: Vec<_> = routers.values()
+        .map(|(router, _)| {
+            let router = router.clone();
+            tokio::spawn(async move { router.shutdown(timeout).await })
+        })
+        .collect();
+
+    for drain in drains {
+        if let Err(e) = drain.await {
+            error!("draining a router failed: {}", e);
+        }
+    }
+
+    // the capture is shared by all routers, so it only stops once all of them are done
+    capture.stop();
+
+    drop(sockets);
+
+    for (addr, (_router, join)) in routers {
+        join.await.unwrap_or_else(|e| panic!("router on {} crashed: {}", addr, e));
+    }
 
-    auth_router_join.await.expect("auth server crashed")
+    info!("shutdown complete");
+    log::logger().flush();
 }
 
 
@@ -110,8 +365,8 @@ mod test{
     use std::str::from_utf8;
     use hmac::digest::consts::U5;
     use rc4::{KeyInit, Rc4, StreamCipher};
-    use crate::prudp::packet::PRUDPPacket;
-    use crate::rmc;
+    use splatoon_server_rust::prudp::packet::PRUDPPacket;
+    use splatoon_server_rust::rmc;
 
     fn from_hex_stream(val: &str) -> Result<Vec<u8>, ParseIntError> {
         let res: Result<Vec<u8>, _> = val.as_bytes()
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use log::{error, warn};
+use tokio::io::BufReader;
+use tokio::net::{TcpListener, TcpStream};
+use crate::admin::http::{write_response, Request, READ_TIMEOUT};
+use crate::metrics::{format_labels, write_header, METRICS};
+use crate::prudp::router::Router;
+use crate::prudp::socket::SocketStats;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// name, help and how to get the value out of the stats of a socket
+type SocketGauge = (&'static str, &'static str, fn(&SocketStats) -> usize);
+
+/// Serves the metrics in the prometheus text format on /metrics
+pub struct Exporter{
+    routers: Vec<Arc<Router>>,
+}
+
+impl Exporter{
+    pub fn new(routers: Vec<Arc<Router>>) -> Self{
+        Self{
+            routers,
+        }
+    }
+
+    // the per socket gauges are collected when rendering instead of being kept up to date
+    async fn render_sockets(&self, out: &mut String){
+        let mut sockets = Vec::new();
+
+        for router in &self.routers {
+            let address = router.get_own_address().to_string();
+
+            for socket in router.sockets().await {
+                let port = socket.get_virual_port().get_port_number().to_string();
+
+                sockets.push((format_labels(&["router", "port"], &[&address, &port]), socket.stats().await));
+            }
+        }
+
+        let gauges: [SocketGauge; 3] = [
+            ("prudp_connections", "connections known to a socket including ones which aren't connected yet", |s| s.connections),
+            ("prudp_active_connections", "connected clients of a socket", |s| s.active_connections),
+            ("prudp_reassembly_bytes", "bytes of out of order packets waiting to be processed", |s| s.reassembly_bytes),
+        ];
+
+        for (name, help, value) in gauges {
+            write_header(out, name, help, "gauge");
+
+            for (labels, stats) in &sockets {
+                out.push_str(&format!("{}{} {}\n", name, labels, value(stats)));
+            }
+        }
+    }
+
+    pub async fn render(&self) -> String{
+        let mut out = String::new();
+
+        METRICS.render(&mut out);
+        self.render_sockets(&mut out).await;
+
+        out
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: TcpListener){
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("metrics: unable to accept connection: {}", e);
+                    continue;
+                }
+            };
+
+            tokio::spawn(self.clone().handle_connection(stream));
+        }
+    }
+
+    async fn handle_connection(self: Arc<Self>, stream: TcpStream){
+        let (reader, mut writer) = stream.into_split();
+
+        let Ok(Ok(request)) = tokio::time::timeout(READ_TIMEOUT, Request::read(&mut BufReader::new(reader))).await else {
+            return;
+        };
+
+        let result = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => write_response(&mut writer, 200, CONTENT_TYPE, self.render().await.as_bytes()).await,
+            _ => write_response(&mut writer, 404, CONTENT_TYPE, b"not found\n").await,
+        };
+
+        if let Err(e) = result {
+            warn!("metrics: unable to send response: {}", e);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+pub mod exporter;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+
+// upper bounds in seconds, rmc handlers are expected to take well below a millisecond
+const LATENCY_BUCKETS: [f64; 12] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0];
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// A single value of a metric, what it looks like in the exposition format depends on the type
+pub trait Metric: Default + Send + Sync{
+    const TYPE: &'static str;
+
+    // labels are already formatted, e.g. `{protocol="Authentication"}` or an empty string
+    fn render(&self, name: &str, labels: &str, out: &mut String);
+}
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter{
+    pub fn inc(&self){
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64){
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64{
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Metric for Counter{
+    const TYPE: &'static str = "counter";
+
+    fn render(&self, name: &str, labels: &str, out: &mut String){
+        let _ = writeln!(out, "{}{} {}", name, labels, self.get());
+    }
+}
+
+/// Latency histogram with the buckets in [`LATENCY_BUCKETS`]
+#[derive(Default)]
+pub struct Histogram{
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    // in microseconds so it fits into an atomic
+    sum_micros: AtomicU64,
+}
+
+impl Histogram{
+    pub fn observe(&self, duration: Duration){
+        let secs = duration.as_secs_f64();
+
+        // the buckets are cumulative when rendering, so only the first matching one is counted here
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|b| secs <= *b) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64{
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+// adds another label to already formatted ones
+fn with_label(labels: &str, name: &str, value: &str) -> String{
+    match labels.strip_suffix('}') {
+        Some(labels) => format!("{},{}=\"{}\"}}", labels, name, value),
+        None => format!("{{{}=\"{}\"}}", name, value),
+    }
+}
+
+impl Metric for Histogram{
+    const TYPE: &'static str = "histogram";
+
+    fn render(&self, name: &str, labels: &str, out: &mut String){
+        let mut cumulative = 0;
+
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+
+            let _ = writeln!(out, "{}_bucket{} {}", name, with_label(labels, "le", &bound.to_string()), cumulative);
+        }
+
+        let _ = writeln!(out, "{}_bucket{} {}", name, with_label(labels, "le", "+Inf"), self.count());
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count());
+    }
+}
+
+pub fn escape_label_value(value: &str) -> String{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub fn format_labels(names: &[&str], values: &[impl AsRef<str>]) -> String{
+    if names.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<_> = names.iter().zip(values)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value.as_ref())))
+        .collect();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+pub fn write_header(out: &mut String, name: &str, help: &str, metric_type: &str){
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+/// All values of one metric, told apart by their labels
+pub struct Family<M: Metric>{
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    metrics: RwLock<BTreeMap<Vec<String>, Arc<M>>>,
+}
+
+impl<M: Metric> Family<M>{
+    pub fn new(name: &'static str, help: &'static str, label_names: &'static [&'static str]) -> Self{
+        Self{
+            name,
+            help,
+            label_names,
+            metrics: Default::default(),
+        }
+    }
+
+    // hot paths should keep the returned metric around instead of looking it up every time
+    pub fn with(&self, label_values: &[&str]) -> Arc<M>{
+        debug_assert_eq!(label_values.len(), self.label_names.len());
+
+        let key: Vec<_> = label_values.iter().map(|v| v.to_string()).collect();
+
+        if let Some(metric) = self.metrics.read().unwrap().get(&key) {
+            return metric.clone();
+        }
+
+        self.metrics.write().unwrap().entry(key).or_default().clone()
+    }
+
+    pub fn render(&self, out: &mut String){
+        write_header(out, self.name, self.help, M::TYPE);
+
+        for (values, metric) in self.metrics.read().unwrap().iter() {
+            metric.render(self.name, &format_labels(self.label_names, values), out);
+        }
+    }
+}
+
+/// Everything the server counts, shared by the whole process
+pub struct Metrics{
+    pub datagrams_received: Family<Counter>,
+    pub datagrams_sent: Family<Counter>,
+    pub packet_parse_errors: Family<Counter>,
+    // reliable packets the client sent again because it didn't get our ack in time, the server
+    // itself doesn't resend anything yet
+    pub retransmissions_received: Family<Counter>,
+    pub rate_limited: Family<Counter>,
+    pub rmc_calls: Family<Counter>,
+    pub rmc_latency: Family<Histogram>,
+}
+
+impl Metrics{
+    fn new() -> Self{
+        Self{
+            datagrams_received: Family::new("prudp_datagrams_received_total", "UDP datagrams received by a router", &["router"]),
+            datagrams_sent: Family::new("prudp_datagrams_sent_total", "UDP datagrams sent by a router", &["router"]),
+            packet_parse_errors: Family::new("prudp_packet_parse_errors_total", "PRUDP packets which could not be parsed", &["reason"]),
+            retransmissions_received: Family::new("prudp_retransmissions_received_total", "reliable packets which were received more than once", &["router", "port"]),
+            rate_limited: Family::new("rate_limited_total", "packets and calls which were dropped or rejected by a rate limit", &["limit"]),
+            rmc_calls: Family::new("rmc_calls_total", "RMC calls handled", &["protocol", "method", "result"]),
+            rmc_latency: Family::new("rmc_handler_duration_seconds", "time spent handling RMC calls", &["protocol", "method"]),
+        }
+    }
+
+    pub fn render(&self, out: &mut String){
+        self.datagrams_received.render(out);
+        self.datagrams_sent.render(out);
+        self.packet_parse_errors.render(out);
+        self.retransmissions_received.render(out);
+        self.rate_limited.render(out);
+        self.rmc_calls.render(out);
+        self.rmc_latency.render(out);
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::time::Duration;
+    use super::{Counter, Family, Histogram};
+
+    #[test]
+    fn exposition_format(){
+        let calls: Family<Counter> = Family::new("calls_total", "calls", &["protocol", "result"]);
+
+        calls.with(&["Auth\"entication", "success"]).inc();
+        calls.with(&["Auth\"entication", "success"]).add(2);
+
+        let mut out = String::new();
+        calls.render(&mut out);
+
+        assert_eq!(out, "# HELP calls_total calls\n# TYPE calls_total counter\ncalls_total{protocol=\"Auth\\\"entication\",result=\"success\"} 3\n");
+
+        let latency: Family<Histogram> = Family::new("latency_seconds", "latency", &["protocol"]);
+
+        let histogram = latency.with(&["Authentication"]);
+        histogram.observe(Duration::from_micros(300));
+        histogram.observe(Duration::from_secs(5));
+
+        let mut out = String::new();
+        latency.render(&mut out);
+
+        assert!(out.contains("latency_seconds_bucket{protocol=\"Authentication\",le=\"0.00025\"} 0\n"));
+        assert!(out.contains("latency_seconds_bucket{protocol=\"Authentication\",le=\"0.0005\"} 1\n"));
+        assert!(out.contains("latency_seconds_bucket{protocol=\"Authentication\",le=\"1\"} 1\n"));
+        assert!(out.contains("latency_seconds_bucket{protocol=\"Authentication\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("latency_seconds_sum{protocol=\"Authentication\"} 5.0003\n"));
+        assert!(out.contains("latency_seconds_count{protocol=\"Authentication\"} 2\n"));
+    }
+}
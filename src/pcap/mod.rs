@@ -0,0 +1,41 @@
+use std::io;
+use std::net::SocketAddrV4;
+use std::time::Duration;
+use thiserror::Error;
+
+pub mod reader;
+pub mod writer;
+
+#[derive(Error, Debug)]
+pub enum Error{
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("not a pcap or pcapng file (magic {0:#010x})")]
+    InvalidMagic(u32),
+    #[error("invalid block length {0}")]
+    InvalidBlockLength(u32),
+    #[error("invalid timestamp resolution {0:#04x}")]
+    InvalidTimestampResolution(u8),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A single UDP datagram as it went over the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram{
+    // time since the unix epoch
+    pub timestamp: Duration,
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+    pub payload: Vec<u8>,
+}
+
+pub mod link_types{
+    pub const NULL: u32 = 0;
+    pub const ETHERNET: u32 = 1;
+    pub const RAW: u32 = 101;
+    pub const LINUX_SLL: u32 = 113;
+    pub const USER0: u32 = 147;
+    pub const IPV4: u32 = 228;
+    pub const LINUX_SLL2: u32 = 276;
+}
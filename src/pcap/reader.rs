@@ -0,0 +1,458 @@
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+use log::{trace, warn};
+use crate::endianness::{ReadExtensions, SwapEndian};
+use super::{link_types, Datagram, Error, Result};
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B23C4D;
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const PCAPNG_SIMPLE_PACKET: u32 = 0x00000003;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x00000006;
+
+const IF_TSRESOL: u16 = 9;
+
+// finds the ipv4 packet inside of a captured frame
+fn ipv4_packet(link_type: u32, frame: &[u8]) -> Option<&[u8]>{
+    let packet = match link_type {
+        link_types::NULL => frame.get(4..)?,
+        link_types::ETHERNET => {
+            let mut ether_type = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+            let mut offset = 14;
+
+            // vlan tags
+            while ether_type == 0x8100 {
+                ether_type = u16::from_be_bytes(frame.get(offset + 2..offset + 4)?.try_into().ok()?);
+                offset += 4;
+            }
+
+            if ether_type != 0x0800 {
+                return None;
+            }
+
+            frame.get(offset..)?
+        }
+        link_types::RAW | link_types::IPV4 => frame,
+        link_types::LINUX_SLL => {
+            if frame.get(14..16)? != [0x08, 0x00] {
+                return None;
+            }
+
+            frame.get(16..)?
+        }
+        link_types::LINUX_SLL2 => {
+            if frame.get(0..2)? != [0x08, 0x00] {
+                return None;
+            }
+
+            frame.get(20..)?
+        }
+        _ => return None
+    };
+
+    (packet.first()? >> 4 == 4).then_some(packet)
+}
+
+/// Extracts the addresses and payload of an udp datagram from a captured frame, anything else
+/// (including fragmented ip packets) is ignored.
+pub fn parse_udp(link_type: u32, frame: &[u8]) -> Option<(SocketAddrV4, SocketAddrV4, &[u8])>{
+    let ip = ipv4_packet(link_type, frame)?;
+
+    let header_length = ((ip[0] & 0x0F) as usize) * 4;
+    let total_length = u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?) as usize;
+    let flags_and_offset = u16::from_be_bytes(ip.get(6..8)?.try_into().ok()?);
+
+    if *ip.get(9)? != 17 {
+        return None;
+    }
+
+    // more fragments flag or a fragment offset
+    if flags_and_offset & 0x3FFF != 0 {
+        warn!("skipping fragmented ip packet");
+        return None;
+    }
+
+    let source_ip = Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(12..16)?).ok()?);
+    let destination_ip = Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(16..20)?).ok()?);
+
+    let udp = ip.get(header_length..total_length.min(ip.len()))?;
+
+    let source_port = u16::from_be_bytes(udp.get(0..2)?.try_into().ok()?);
+    let destination_port = u16::from_be_bytes(udp.get(2..4)?.try_into().ok()?);
+    let udp_length = u16::from_be_bytes(udp.get(4..6)?.try_into().ok()?) as usize;
+
+    let payload = udp.get(8..udp_length)?;
+
+    Some((
+        SocketAddrV4::new(source_ip, source_port),
+        SocketAddrV4::new(destination_ip, destination_port),
+        payload
+    ))
+}
+
+// the whole file is in memory already, this makes sure a bogus length doesn't make us allocate way
+// more than that
+fn read_bytes(cursor: &mut Cursor<&[u8]>, length: usize) -> Result<Vec<u8>>{
+    let remaining = cursor.get_ref().len().saturating_sub(cursor.position() as usize);
+
+    if length > remaining {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    let mut data = vec![0; length];
+    cursor.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+fn push_datagram(datagrams: &mut Vec<Datagram>, link_type: u32, timestamp: Duration, frame: &[u8]){
+    let Some((source, destination, payload)) = parse_udp(link_type, frame) else {
+        trace!("skipping non udp frame");
+        return;
+    };
+
+    datagrams.push(Datagram{
+        timestamp,
+        source,
+        destination,
+        payload: payload.to_vec(),
+    });
+}
+
+fn read_pcap(cursor: &mut Cursor<&[u8]>, swap_endian: bool, nanos: bool) -> Result<Vec<Datagram>>{
+    let _version_major: u16 = cursor.read_struct(swap_endian)?;
+    let _version_minor: u16 = cursor.read_struct(swap_endian)?;
+    let _this_zone: i32 = cursor.read_struct(swap_endian)?;
+    let _sigfigs: u32 = cursor.read_struct(swap_endian)?;
+    let _snap_length: u32 = cursor.read_struct(swap_endian)?;
+    let link_type: u32 = cursor.read_struct(swap_endian)?;
+
+    let mut datagrams = Vec::new();
+
+    while (cursor.position() as usize) < cursor.get_ref().len() {
+        let seconds: u32 = cursor.read_struct(swap_endian)?;
+        let fraction: u32 = cursor.read_struct(swap_endian)?;
+        let captured_length: u32 = cursor.read_struct(swap_endian)?;
+        let _original_length: u32 = cursor.read_struct(swap_endian)?;
+
+        let frame = read_bytes(cursor, captured_length as usize)?;
+
+        let timestamp = Duration::from_secs(seconds as u64) + if nanos {
+            Duration::from_nanos(fraction as u64)
+        } else {
+            Duration::from_micros(fraction as u64)
+        };
+
+        push_datagram(&mut datagrams, link_type, timestamp, &frame);
+    }
+
+    Ok(datagrams)
+}
+
+struct Interface{
+    link_type: u32,
+    // units per second
+    resolution: u64,
+}
+
+fn read_interface(body: &[u8], swap_endian: bool) -> Result<Interface>{
+    let mut cursor = Cursor::new(body);
+
+    let link_type: u16 = cursor.read_struct(swap_endian)?;
+    let _reserved: u16 = cursor.read_struct(swap_endian)?;
+    let _snap_length: u32 = cursor.read_struct(swap_endian)?;
+
+    let mut resolution = 1_000_000;
+
+    while (cursor.position() as usize) + 4 <= body.len() {
+        let code: u16 = cursor.read_struct(swap_endian)?;
+        let length: u16 = cursor.read_struct(swap_endian)?;
+
+        if code == 0 {
+            break;
+        }
+
+        let value = read_bytes(&mut cursor, length as usize)?;
+        cursor.seek(SeekFrom::Current((4 - length as i64 % 4) % 4))?;
+
+        if code == IF_TSRESOL && !value.is_empty() {
+            let exponent = (value[0] & 0x7F) as u32;
+
+            let units = if value[0] & 0x80 == 0 {
+                10u64.checked_pow(exponent)
+            } else {
+                2u64.checked_pow(exponent)
+            };
+
+            resolution = units.filter(|&units| units != 0)
+                .ok_or(Error::InvalidTimestampResolution(value[0]))?;
+        }
+    }
+
+    Ok(Interface{
+        link_type: link_type as u32,
+        resolution
+    })
+}
+
+fn read_pcapng(cursor: &mut Cursor<&[u8]>) -> Result<Vec<Datagram>>{
+    let mut datagrams = Vec::new();
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut swap_endian = false;
+
+    while (cursor.position() as usize) < cursor.get_ref().len() {
+        let block_start = cursor.position();
+
+        let block_type: u32 = cursor.read_struct(swap_endian)?;
+
+        if block_type == PCAPNG_SECTION_HEADER {
+            // the byte order can change with every section, so we have to check it before we know
+            // how to read the length
+            cursor.seek(SeekFrom::Current(4))?;
+            let byte_order_magic: u32 = cursor.read_struct(false)?;
+
+            swap_endian = if byte_order_magic == PCAPNG_BYTE_ORDER_MAGIC {
+                false
+            } else if byte_order_magic == PCAPNG_BYTE_ORDER_MAGIC.swap_endian() {
+                true
+            } else {
+                return Err(Error::InvalidMagic(byte_order_magic));
+            };
+
+            interfaces.clear();
+            cursor.seek(SeekFrom::Start(block_start + 4))?;
+        }
+
+        let block_length: u32 = cursor.read_struct(swap_endian)?;
+
+        if block_length < 12 || !block_length.is_multiple_of(4) {
+            return Err(Error::InvalidBlockLength(block_length));
+        }
+
+        let body = read_bytes(cursor, block_length as usize - 12)?;
+        let _trailing_length: u32 = cursor.read_struct(swap_endian)?;
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => interfaces.push(read_interface(&body, swap_endian)?),
+            PCAPNG_ENHANCED_PACKET => {
+                let mut body_cursor = Cursor::new(&body[..]);
+
+                let interface_id: u32 = body_cursor.read_struct(swap_endian)?;
+                let timestamp_high: u32 = body_cursor.read_struct(swap_endian)?;
+                let timestamp_low: u32 = body_cursor.read_struct(swap_endian)?;
+                let captured_length: u32 = body_cursor.read_struct(swap_endian)?;
+                let _original_length: u32 = body_cursor.read_struct(swap_endian)?;
+
+                let Some(interface) = interfaces.get(interface_id as usize) else {
+                    warn!("packet references unknown interface {}", interface_id);
+                    continue;
+                };
+
+                let Some(frame) = body.get(20..20 + captured_length as usize) else {
+                    return Err(Error::InvalidBlockLength(block_length));
+                };
+
+                let units = ((timestamp_high as u64) << 32) | timestamp_low as u64;
+                // the fraction is less than one second, so the nanoseconds always fit into an u64
+                let nanos = (units % interface.resolution) as u128 * 1_000_000_000 / interface.resolution as u128;
+                let timestamp = Duration::from_secs(units / interface.resolution) + Duration::from_nanos(nanos as u64);
+
+                push_datagram(&mut datagrams, interface.link_type, timestamp, frame);
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let Some(interface) = interfaces.first() else {
+                    warn!("simple packet without interface");
+                    continue;
+                };
+
+                // the body starts with the original length of the packet
+                let Some(frame) = body.get(4..) else {
+                    return Err(Error::InvalidBlockLength(block_length));
+                };
+
+                push_datagram(&mut datagrams, interface.link_type, Duration::ZERO, frame);
+            }
+            _ => trace!("skipping pcapng block of type {:#010x}", block_type)
+        }
+    }
+
+    Ok(datagrams)
+}
+
+/// Reads all udp datagrams from a pcap or pcapng capture
+pub fn read_datagrams(reader: &mut impl Read) -> Result<Vec<Datagram>>{
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let mut cursor = Cursor::new(&data[..]);
+
+    let magic: u32 = cursor.read_struct(false)?;
+
+    match magic {
+        PCAPNG_SECTION_HEADER => {
+            cursor.set_position(0);
+            read_pcapng(&mut cursor)
+        }
+        PCAP_MAGIC_MICROS => read_pcap(&mut cursor, false, false),
+        PCAP_MAGIC_NANOS => read_pcap(&mut cursor, false, true),
+        _ if magic.swap_endian() == PCAP_MAGIC_MICROS => read_pcap(&mut cursor, true, false),
+        _ if magic.swap_endian() == PCAP_MAGIC_NANOS => read_pcap(&mut cursor, true, true),
+        _ => Err(Error::InvalidMagic(magic))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::time::Duration;
+    use crate::pcap::Error;
+    use super::read_datagrams;
+
+    fn ethernet_udp_frame(payload: &[u8]) -> Vec<u8>{
+        let mut frame = vec![0; 12];
+        frame.extend([0x08, 0x00]);
+
+        let total_length = 20 + 8 + payload.len() as u16;
+        frame.extend([0x45, 0, (total_length >> 8) as u8, total_length as u8, 0, 0, 0x40, 0, 64, 17, 0, 0]);
+        frame.extend([10, 0, 0, 1]);
+        frame.extend([10, 0, 0, 2]);
+
+        let udp_length = 8 + payload.len() as u16;
+        frame.extend(1234u16.to_be_bytes());
+        frame.extend(10000u16.to_be_bytes());
+        frame.extend(udp_length.to_be_bytes());
+        frame.extend([0, 0]);
+        frame.extend(payload);
+
+        frame
+    }
+
+    fn check(datagrams: &[super::Datagram], timestamp: Duration){
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].source, SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1234));
+        assert_eq!(datagrams[0].destination, SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 10000));
+        assert_eq!(datagrams[0].payload, [0xEA, 0xD0, 1]);
+        assert_eq!(datagrams[0].timestamp, timestamp);
+    }
+
+    #[test]
+    fn classic_pcap(){
+        let frame = ethernet_udp_frame(&[0xEA, 0xD0, 1]);
+
+        // written by a big endian machine
+        let mut file = Vec::new();
+        file.extend(0xA1B2C3D4u32.to_be_bytes());
+        file.extend(2u16.to_be_bytes());
+        file.extend(4u16.to_be_bytes());
+        file.extend([0; 8]);
+        file.extend(65535u32.to_be_bytes());
+        file.extend(1u32.to_be_bytes());
+
+        file.extend(100u32.to_be_bytes());
+        file.extend(5u32.to_be_bytes());
+        file.extend((frame.len() as u32).to_be_bytes());
+        file.extend((frame.len() as u32).to_be_bytes());
+        file.extend(&frame);
+
+        let datagrams = read_datagrams(&mut Cursor::new(file)).unwrap();
+        check(&datagrams, Duration::from_secs(100) + Duration::from_micros(5));
+    }
+
+    #[test]
+    fn pcapng(){
+        let frame = ethernet_udp_frame(&[0xEA, 0xD0, 1]);
+
+        let mut file = Vec::new();
+
+        // section header
+        file.extend(0x0A0D0D0Au32.to_le_bytes());
+        file.extend(28u32.to_le_bytes());
+        file.extend(0x1A2B3C4Du32.to_le_bytes());
+        file.extend(1u16.to_le_bytes());
+        file.extend(0u16.to_le_bytes());
+        file.extend((-1i64).to_le_bytes());
+        file.extend(28u32.to_le_bytes());
+
+        // interface with nanosecond timestamps
+        file.extend(1u32.to_le_bytes());
+        file.extend(28u32.to_le_bytes());
+        file.extend(1u16.to_le_bytes());
+        file.extend(0u16.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend([9, 0, 1, 0, 9, 0, 0, 0]);
+        file.extend(28u32.to_le_bytes());
+
+        // some block we don't know
+        file.extend(0x0BAD0000u32.to_le_bytes());
+        file.extend(16u32.to_le_bytes());
+        file.extend([1, 2, 3, 4]);
+        file.extend(16u32.to_le_bytes());
+
+        let padded_length = (frame.len() + 3) / 4 * 4;
+        let block_length = 32 + padded_length as u32;
+        let timestamp = 100_000_000_005u64;
+
+        file.extend(6u32.to_le_bytes());
+        file.extend(block_length.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend(((timestamp >> 32) as u32).to_le_bytes());
+        file.extend((timestamp as u32).to_le_bytes());
+        file.extend((frame.len() as u32).to_le_bytes());
+        file.extend((frame.len() as u32).to_le_bytes());
+        file.extend(&frame);
+        file.extend(vec![0; padded_length - frame.len()]);
+        file.extend(block_length.to_le_bytes());
+
+        let datagrams = read_datagrams(&mut Cursor::new(file)).unwrap();
+        check(&datagrams, Duration::from_secs(100) + Duration::from_nanos(5));
+    }
+
+    #[test]
+    fn broken_pcapng(){
+        let mut section_header = Vec::new();
+        section_header.extend(0x0A0D0D0Au32.to_le_bytes());
+        section_header.extend(28u32.to_le_bytes());
+        section_header.extend(0x1A2B3C4Du32.to_le_bytes());
+        section_header.extend(1u16.to_le_bytes());
+        section_header.extend(0u16.to_le_bytes());
+        section_header.extend((-1i64).to_le_bytes());
+        section_header.extend(28u32.to_le_bytes());
+
+        let interface = |resolution: u8| {
+            let mut block = Vec::new();
+            block.extend(1u32.to_le_bytes());
+            block.extend(28u32.to_le_bytes());
+            block.extend(1u16.to_le_bytes());
+            block.extend(0u16.to_le_bytes());
+            block.extend(0u32.to_le_bytes());
+            block.extend([9, 0, 1, 0, resolution, 0, 0, 0]);
+            block.extend(28u32.to_le_bytes());
+            block
+        };
+
+        // 2^64 units per second don't fit into an u64
+        let mut file = section_header.clone();
+        file.extend(interface(0x80 | 64));
+        assert!(matches!(read_datagrams(&mut Cursor::new(file)), Err(Error::InvalidTimestampResolution(0xC0))));
+
+        // a simple packet block which is too short to have the original length
+        let mut file = section_header.clone();
+        file.extend(interface(6));
+        file.extend(3u32.to_le_bytes());
+        file.extend(12u32.to_le_bytes());
+        file.extend(12u32.to_le_bytes());
+        assert!(matches!(read_datagrams(&mut Cursor::new(file)), Err(Error::InvalidBlockLength(12))));
+
+        // a block which claims to be much longer than the file
+        let mut file = section_header;
+        file.extend(6u32.to_le_bytes());
+        file.extend(0xFFFFFFF0u32.to_le_bytes());
+        assert!(matches!(read_datagrams(&mut Cursor::new(file)), Err(Error::Io(_))));
+    }
+}
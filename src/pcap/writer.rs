@@ -0,0 +1,201 @@
+use std::io;
+use std::io::Write;
+use std::net::SocketAddrV4;
+use std::time::Duration;
+use crate::endianness::WriteExtensions;
+use super::{link_types, Datagram};
+
+const SECTION_HEADER: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const ENHANCED_PACKET: u32 = 0x00000006;
+
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const EPB_FLAGS: u16 = 2;
+
+// the interface the real datagrams get written to
+const DATAGRAM_INTERFACE: u32 = 0;
+// the interface for annotations like decrypted payloads, readers skip it as it isn't ip
+const ANNOTATION_INTERFACE: u32 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction{
+    Inbound,
+    Outbound,
+}
+
+fn padding(len: usize) -> usize{
+    (4 - len % 4) % 4
+}
+
+fn ipv4_udp_packet(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Vec<u8>{
+    let udp_length = 8 + payload.len() as u16;
+    let total_length = 20 + udp_length;
+
+    let mut packet = Vec::with_capacity(total_length as usize);
+
+    packet.extend([0x45, 0]);
+    packet.extend(total_length.to_be_bytes());
+    // no identification and don't fragment
+    packet.extend([0, 0, 0x40, 0]);
+    packet.extend([64, 17, 0, 0]);
+    packet.extend(source.ip().octets());
+    packet.extend(destination.ip().octets());
+
+    let checksum = !packet.chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]) as u32)
+        .fold(0u32, |sum, v| {
+            let sum = sum + v;
+            (sum & 0xFFFF) + (sum >> 16)
+        }) as u16;
+
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    packet.extend(source.port().to_be_bytes());
+    packet.extend(destination.port().to_be_bytes());
+    packet.extend(udp_length.to_be_bytes());
+    // the udp checksum is optional over ipv4
+    packet.extend([0, 0]);
+    packet.extend(payload);
+
+    packet
+}
+
+/// Writes datagrams into a pcapng file which wireshark and our own reader understand
+pub struct PcapngWriter<W: Write>{
+    writer: W,
+    written: u64,
+}
+
+impl<W: Write> PcapngWriter<W>{
+    pub fn new(writer: W) -> io::Result<Self>{
+        let mut this = Self{
+            writer,
+            written: 0,
+        };
+
+        let mut section = Vec::new();
+        section.write_le_u32(0x1A2B3C4D)?;
+        section.write_le_u16(1)?;
+        section.write_le_u16(0)?;
+        // unknown section length
+        section.write_le_struct(-1i64)?;
+        this.write_block(SECTION_HEADER, &section)?;
+
+        for link_type in [link_types::RAW, link_types::USER0] {
+            let mut interface = Vec::new();
+            interface.write_le_u16(link_type as u16)?;
+            interface.write_le_u16(0)?;
+            interface.write_le_u32(0)?;
+            this.write_block(INTERFACE_DESCRIPTION, &interface)?;
+        }
+
+        Ok(this)
+    }
+
+    // the amount of bytes written so far, used for rotating files
+    pub fn written(&self) -> u64{
+        self.written
+    }
+
+    pub fn flush(&mut self) -> io::Result<()>{
+        self.writer.flush()
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()>{
+        let length = 12 + body.len() as u32;
+
+        self.writer.write_le_u32(block_type)?;
+        self.writer.write_le_u32(length)?;
+        self.writer.write_all(body)?;
+        self.writer.write_le_u32(length)?;
+
+        self.written += length as u64;
+
+        Ok(())
+    }
+
+    fn write_packet(&mut self, interface: u32, timestamp: Duration, direction: Direction, data: &[u8], comment: Option<&str>) -> io::Result<()>{
+        let micros = timestamp.as_micros() as u64;
+
+        let mut body = Vec::with_capacity(20 + data.len() + 32);
+        body.write_le_u32(interface)?;
+        body.write_le_u32((micros >> 32) as u32)?;
+        body.write_le_u32(micros as u32)?;
+        body.write_le_u32(data.len() as u32)?;
+        body.write_le_u32(data.len() as u32)?;
+        body.write_all(data)?;
+        body.extend(vec![0; padding(data.len())]);
+
+        body.write_le_u16(EPB_FLAGS)?;
+        body.write_le_u16(4)?;
+        body.write_le_u32(match direction {
+            Direction::Inbound => 0b01,
+            Direction::Outbound => 0b10,
+        })?;
+
+        if let Some(comment) = comment {
+            body.write_le_u16(OPT_COMMENT)?;
+            body.write_le_u16(comment.len() as u16)?;
+            body.write_all(comment.as_bytes())?;
+            body.extend(vec![0; padding(comment.len())]);
+        }
+
+        body.write_le_u16(OPT_END)?;
+        body.write_le_u16(0)?;
+
+        self.write_block(ENHANCED_PACKET, &body)
+    }
+
+    pub fn write_datagram(&mut self, datagram: &Datagram, direction: Direction) -> io::Result<()>{
+        let packet = ipv4_udp_packet(datagram.source, datagram.destination, &datagram.payload);
+
+        self.write_packet(DATAGRAM_INTERFACE, datagram.timestamp, direction, &packet, None)
+    }
+
+    // writes data which didn't go over the wire like this (e.g. decrypted payloads) next to the
+    // datagrams, the comment should say what it is
+    pub fn write_annotation(&mut self, timestamp: Duration, direction: Direction, data: &[u8], comment: &str) -> io::Result<()>{
+        self.write_packet(ANNOTATION_INTERFACE, timestamp, direction, data, Some(comment))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::time::Duration;
+    use crate::pcap::Datagram;
+    use crate::pcap::reader::read_datagrams;
+    use super::{Direction, PcapngWriter};
+
+    #[test]
+    fn written_captures_can_be_read(){
+        let datagrams = [
+            Datagram{
+                timestamp: Duration::from_micros(1_700_000_000_000_001),
+                source: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1234),
+                destination: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 10000),
+                payload: vec![0xEA, 0xD0, 1],
+            },
+            Datagram{
+                timestamp: Duration::from_micros(1_700_000_000_000_002),
+                source: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 10000),
+                destination: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1234),
+                payload: vec![0; 1001],
+            },
+        ];
+
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+
+        writer.write_datagram(&datagrams[0], Direction::Inbound).unwrap();
+        writer.write_annotation(datagrams[0].timestamp, Direction::Inbound, &[1, 2, 3], "decrypted").unwrap();
+        writer.write_datagram(&datagrams[1], Direction::Outbound).unwrap();
+
+        assert_eq!(writer.written() as usize, writer.writer.len());
+
+        let read = read_datagrams(&mut Cursor::new(writer.writer)).unwrap();
+
+        assert_eq!(read, datagrams);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use rand::random;
+use serde::Deserialize;
+use thiserror::Error;
+
+// how long a client has to connect to the secure server after logging in
+const TICKET_LIFETIME: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Error)]
+pub enum Error{
+    #[error("unable to read account file {path}: {source}")]
+    Io{
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("invalid account file {path}: {source}")]
+    Parse{
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Account{
+    pub pid: u32,
+    // what the console logs in with, for nex accounts this is the pid as a string
+    pub username: String,
+    // has to match the token in the AuthenticationInfo of the login, there is no account server
+    // to check the tokens against yet so this is a fixed secret per account
+    pub token: String,
+}
+
+impl Account{
+    // compares in constant time so the token can't be guessed byte by byte, an account without a
+    // token can't log in at all
+    pub fn token_matches(&self, token: &str) -> bool{
+        !self.token.is_empty() &&
+            self.token.len() == token.len() &&
+            self.token.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+/// Reads the accounts of every realm from a json file mapping the realm to its accounts, a
+/// missing file means there are no accounts.
+pub fn read_file(path: &Path) -> Result<HashMap<String, Vec<Account>>>{
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(source) => return Err(Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+    };
+
+    serde_json::from_slice(&content).map_err(|source| Error::Parse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+struct Ticket{
+    pid: u32,
+    expires: Instant,
+}
+
+/// The accounts of a realm and the tickets handed out to them by the auth server, shared with the
+/// secure server which redeems the tickets.
+///
+/// Everything is kept in memory, there is no account server to sync with yet.
+#[derive(Default)]
+pub struct Accounts{
+    accounts: RwLock<HashMap<String, Account>>,
+    tickets: Mutex<HashMap<[u8; 16], Ticket>>,
+}
+
+impl Accounts{
+    // returns false if there already is an account with the username
+    pub fn add(&self, account: Account) -> bool{
+        let mut accounts = self.accounts.write().unwrap();
+
+        if accounts.contains_key(&account.username) {
+            return false;
+        }
+
+        accounts.insert(account.username.clone(), account);
+        true
+    }
+
+    pub fn len(&self) -> usize{
+        self.accounts.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.len() == 0
+    }
+
+    pub fn find(&self, username: &str) -> Option<Account>{
+        self.accounts.read().unwrap().get(username).cloned()
+    }
+
+    pub fn issue_ticket(&self, pid: u32) -> Vec<u8>{
+        self.issue_ticket_at(pid, Instant::now())
+    }
+
+    fn issue_ticket_at(&self, pid: u32, now: Instant) -> Vec<u8>{
+        let ticket: [u8; 16] = random();
+
+        let mut tickets = self.tickets.lock().unwrap();
+
+        // unredeemed tickets would pile up otherwise
+        tickets.retain(|_, t| t.expires > now);
+
+        tickets.insert(ticket, Ticket{
+            pid,
+            expires: now + TICKET_LIFETIME,
+        });
+
+        ticket.to_vec()
+    }
+
+    // tickets can only be used once, returns the pid the ticket was issued to
+    pub fn redeem_ticket(&self, ticket: &[u8]) -> Option<u32>{
+        self.redeem_ticket_at(ticket, Instant::now())
+    }
+
+    fn redeem_ticket_at(&self, ticket: &[u8], now: Instant) -> Option<u32>{
+        let ticket: [u8; 16] = ticket.try_into().ok()?;
+
+        let ticket = self.tickets.lock().unwrap().remove(&ticket)?;
+
+        (ticket.expires > now).then_some(ticket.pid)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::time::{Duration, Instant};
+    use super::{read_file, Account, Accounts};
+
+    #[test]
+    fn tickets(){
+        let accounts = Accounts::default();
+
+        assert!(accounts.add(Account{
+            pid: 1337,
+            username: "1337".to_string(),
+            token: "secret".to_string(),
+        }));
+        assert!(!accounts.add(Account{
+            pid: 1338,
+            username: "1337".to_string(),
+            token: "secret".to_string(),
+        }));
+        assert_eq!(accounts.find("1337").unwrap().pid, 1337);
+        assert!(accounts.find("1338").is_none());
+
+        let now = Instant::now();
+
+        let ticket = accounts.issue_ticket_at(1337, now);
+        assert_eq!(accounts.redeem_ticket_at(&ticket, now), Some(1337));
+        assert_eq!(accounts.redeem_ticket_at(&ticket, now), None);
+
+        let ticket = accounts.issue_ticket_at(1337, now);
+        assert_eq!(accounts.redeem_ticket_at(&ticket, now + Duration::from_secs(121)), None);
+
+        assert_eq!(accounts.redeem_ticket_at(b"not a ticket", now), None);
+    }
+
+    #[test]
+    fn tokens(){
+        let mut account = Account{
+            pid: 1337,
+            username: "1337".to_string(),
+            token: "secret".to_string(),
+        };
+
+        assert!(account.token_matches("secret"));
+        assert!(!account.token_matches("secreT"));
+        assert!(!account.token_matches("secret2"));
+        assert!(!account.token_matches(""));
+
+        account.token.clear();
+        assert!(!account.token_matches(""));
+    }
+
+    #[test]
+    fn account_file(){
+        let path = std::env::temp_dir().join(format!("accounts-test-{}.json", std::process::id()));
+
+        assert!(read_file(&path).unwrap().is_empty());
+
+        std::fs::write(&path, r#"{"splatoon": [{"pid": 1337, "username": "1337", "token": "secret"}]}"#).unwrap();
+        let realms = read_file(&path).unwrap();
+        assert_eq!(realms["splatoon"][0].pid, 1337);
+        assert_eq!(realms["splatoon"][0].token, "secret");
+
+        std::fs::write(&path, r#"{"splatoon": [{"pid": 1337, "username": "1337"}]}"#).unwrap();
+        assert!(read_file(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
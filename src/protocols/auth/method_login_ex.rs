@@ -1,9 +1,14 @@
 use std::io::Cursor;
 use log::{error, info};
-use crate::rmc::message::RMCMessage;
-use crate::rmc::response::{ErrorCode, RMCResponse, RMCResponseResult};
-use crate::rmc::structures::{string, any, RmcSerialize};
-use crate::rmc::structures::any::Any;
+use crate::protocols::bans::Subject;
+use crate::protocols::RmcContext;
+use crate::rmc::response::{ErrorCode, RMCResponseResult};
+use crate::rmc::structures::RmcSerialize;
+use crate::rmc::structures::any::{Any, AnyData};
+use crate::rmc::structures::buffer::Buffer;
+use crate::rmc::structures::connection_data::ConnectionData;
+use crate::rmc::structures::date_time::DateTime;
+use crate::rmc::structures::result_code::ResultCode;
 
 pub fn login_ex(name: &str) -> RMCResponseResult{
     // todo: figure out how the AuthenticationInfo struct works, parse it and validate login info
@@ -12,29 +17,71 @@ pub fn login_ex(name: &str) -> RMCResponseResult{
     unreachable!()
 }
 
-pub fn login_ex_raw_params(rmcmessage: &RMCMessage) -> RMCResponseResult{
-    let mut reader = Cursor::new(&rmcmessage.rest_of_data);
+// returns the result, the pid, the ticket for the secure server, where to find the secure server
+// and a message for the client
+pub async fn login_ex_raw_params(ctx: &mut RmcContext<'_>, params: &[u8]) -> Result<(ResultCode, u32, Buffer, ConnectionData, String), ErrorCode>{
+    let mut reader = Cursor::new(params);
 
-    let Ok(str) =  String::deserialize(&mut reader) else {
-        error!("error reading packet");
-        return rmcmessage.error_result_with_code(ErrorCode::Core_InvalidArgument);
-    };
+    let str = String::deserialize(&mut reader, &ctx.serialization_context)
+        .inspect_err(|e| error!("error reading packet: {}", e))?;
+
+    let any = Any::deserialize(&mut reader, &ctx.serialization_context)
+        .inspect_err(|e| error!("error reading packet: {}", e))?;
 
-    let Ok(any) =  Any::deserialize(&mut reader) else {
-        error!("error reading packet");
-        return rmcmessage.error_result_with_code(ErrorCode::Core_InvalidArgument);
+    let AnyData::AuthenticationInfo(authentication_info) = any.data else {
+        error!("error reading packet: invalid structure type: {}", any.name);
+        return Err(ErrorCode::Core_InvalidArgument);
     };
 
-    match any.name.as_ref(){
-        "AuthenticationInfo" => {
+    // the username is the pid of the user
+    let pid = str.parse().ok();
+
+    // the console id is in the token which isn't parsed yet, so console bans can't match here
+    let ban = ctx.state.bans.find(&Subject{
+        pid,
+        username: Some(&str),
+        console_id: None,
+        ip: Some(*ctx.connection.sock_addr.regular_socket_addr.ip()),
+    });
+
+    if let Some(ban) = ban {
+        info!("rejected login of {} due to ban {} ({})", str, ban.id, ban.reason);
+        return Err(ban.error_code());
+    }
+
+    if let Some(maintenance) = ctx.state.maintenance.blocks(&ctx.state.title, pid) {
+        info!("rejected login of {} due to maintenance ({})", str, maintenance.message.as_deref().unwrap_or("no message"));
+        return Err(ErrorCode::Authentication_UnderMaintenance);
+    }
+
+    let Some(account) = ctx.state.accounts.find(&str) else {
+        info!("rejected login of unknown user {}", str);
+        return Err(ErrorCode::RendezVous_InvalidUsername);
+    };
 
-        }
-        v => {
-            error!("error reading packet: invalid structure type: {}", v);
-            return rmcmessage.error_result_with_code(ErrorCode::Core_InvalidArgument);
-        }
+    if !account.token_matches(&authentication_info.token) {
+        info!("rejected login of {} due to an invalid token", str);
+        return Err(ErrorCode::RendezVous_InvalidPassword);
     }
 
-    //login_ex(&str)
-    rmcmessage.error_result_with_code(ErrorCode::Authentication_UnderMaintenance)
-}
\ No newline at end of file
+    let Some(secure_station_url) = ctx.state.secure_station_url.clone() else {
+        error!("there is no secure server to send {} to", str);
+        return Err(ErrorCode::RendezVous_InvalidConfiguration);
+    };
+
+    let ticket = ctx.state.accounts.issue_ticket(account.pid);
+
+    info!("{} logged in as {}", str, account.pid);
+
+    Ok((
+        ResultCode::SUCCESS,
+        account.pid,
+        Buffer(ticket),
+        ConnectionData{
+            regular_protocols: secure_station_url,
+            current_utc_time: DateTime::now(),
+            ..Default::default()
+        },
+        format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+    ))
+}
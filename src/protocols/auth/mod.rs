@@ -1,14 +1,70 @@
 mod method_login_ex;
 
-use log::{error, info};
-use crate::define_protocol;
-use crate::protocols::auth::method_login_ex::{login_ex, login_ex_raw_params};
-use crate::rmc::message::RMCMessage;
-use crate::rmc::response::{ErrorCode, RMCResponse, RMCResponseResult};
+use std::io::Cursor;
+use log::error;
+use crate::protocols::{HandlerFuture, Protocol, RmcContext};
+use crate::protocols::auth::method_login_ex::login_ex_raw_params;
+use crate::rmc::response::ErrorCode;
+use crate::rmc::structures::{RmcSerialize, SerializationContext};
+use crate::rmc::structures::any::Any;
 
+pub const PROTOCOL_ID: u16 = 10;
 
-define_protocol!{
-    10 => {
-        0x02 => login_ex_raw_params
+pub const METHOD_LOGIN: u32 = 0x01;
+pub const METHOD_LOGIN_EX: u32 = 0x02;
+pub const METHOD_REQUEST_TICKET: u32 = 0x03;
+pub const METHOD_GET_PID: u32 = 0x04;
+pub const METHOD_GET_NAME: u32 = 0x05;
+pub const METHOD_LOGIN_WITH_PARAM: u32 = 0x06;
+
+pub struct AuthenticationProtocol;
+
+impl Protocol for AuthenticationProtocol{
+    fn id(&self) -> u16 {
+        PROTOCOL_ID
+    }
+
+    fn name(&self) -> &'static str {
+        "Authentication"
+    }
+
+    fn method_name(&self, method_id: u32) -> Option<&'static str> {
+        Some(match method_id {
+            METHOD_LOGIN => "Login",
+            METHOD_LOGIN_EX => "LoginEx",
+            METHOD_REQUEST_TICKET => "RequestTicket",
+            METHOD_GET_PID => "GetPID",
+            METHOD_GET_NAME => "GetName",
+            METHOD_LOGIN_WITH_PARAM => "LoginWithParam",
+            _ => return None,
+        })
+    }
+
+    // only the username of a login gets shown, the rest carries the credentials
+    fn describe_params(&self, method_id: u32, params: &[u8], ctx: &SerializationContext) -> Option<String> {
+        let login = match method_id {
+            METHOD_LOGIN_EX => <(String, Any)>::deserialize(&mut Cursor::new(params), ctx).ok(),
+            _ => None,
+        };
+
+        Some(match login {
+            Some((username, any)) => format!("username={:?} {}=<redacted>", username, any.name),
+            None => format!("{} bytes <redacted>", params.len()),
+        })
+    }
+
+    fn handle<'a>(&'a self, ctx: &'a mut RmcContext<'_>, method_id: u32, params: &'a [u8]) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            match method_id {
+                METHOD_LOGIN_EX => {
+                    let result = login_ex_raw_params(ctx, params).await;
+                    ctx.respond(method_id, result)
+                }
+                _ => {
+                    error!("invalid method id sent to protocol {}: {:?}", PROTOCOL_ID, method_id);
+                    ctx.error(ErrorCode::Core_NotImplemented)
+                }
+            }
+        })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,492 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::info;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::prudp::socket::{ConnectionData, SocketData};
+use crate::rmc::response::ErrorCode;
+
+#[derive(Debug, Error)]
+pub enum Error{
+    #[error("unable to access ban file {path}: {source}")]
+    Io{
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("invalid ban file {path}: {source}")]
+    Parse{
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("{0} bans can't be enforced, the console id of a login isn't known")]
+    UnsupportedTarget(BanTarget),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An ipv4 network, a single address is a /32
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange{
+    network: Ipv4Addr,
+    prefix: u8,
+}
+
+impl IpRange{
+    fn mask(&self) -> u32{
+        u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool{
+        u32::from(ip) & self.mask() == u32::from(self.network) & self.mask()
+    }
+}
+
+impl FromStr for IpRange{
+    type Err = String;
+
+    // accepts "10.0.0.0/8" or a single address
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid ip range {:?}", s);
+
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, prefix.parse().map_err(|_| invalid())?),
+            None => (s, 32),
+        };
+
+        if prefix > 32 {
+            return Err(invalid());
+        }
+
+        Ok(Self{
+            network: network.parse().map_err(|_| invalid())?,
+            prefix,
+        })
+    }
+}
+
+impl TryFrom<String> for IpRange{
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<IpRange> for String{
+    fn from(value: IpRange) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for IpRange{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Who a ban applies to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanTarget{
+    Pid(u32),
+    // the name used to log in, for nex accounts this is the pid as a string
+    Username(String),
+    // can't be added, the token the console id is in isn't parsed yet
+    ConsoleId(String),
+    IpRange(IpRange),
+}
+
+impl Display for BanTarget{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BanTarget::Pid(pid) => write!(f, "pid {}", pid),
+            BanTarget::Username(name) => write!(f, "username {}", name),
+            BanTarget::ConsoleId(id) => write!(f, "console {}", id),
+            BanTarget::IpRange(range) => write!(f, "ip range {}", range),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban{
+    pub id: u64,
+    pub target: BanTarget,
+    pub reason: String,
+    // who issued the ban, e.g. the name of a moderator
+    pub issuer: String,
+    // unix timestamps
+    pub created: u64,
+    // permanent if not set
+    pub expires: Option<u64>,
+}
+
+fn unix_now() -> u64{
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+impl Ban{
+    pub fn is_expired(&self) -> bool{
+        self.expires.is_some_and(|expires| expires <= unix_now())
+    }
+
+    pub fn is_permanent(&self) -> bool{
+        self.expires.is_none()
+    }
+
+    // what logins and registrations of banned users get back
+    pub fn error_code(&self) -> ErrorCode{
+        match self.is_permanent() {
+            true => ErrorCode::RendezVous_AccountDisabled,
+            false => ErrorCode::RendezVous_AccountTemporarilyDisabled,
+        }
+    }
+
+    pub fn applies_to(&self, subject: &Subject) -> bool{
+        match &self.target {
+            BanTarget::Pid(pid) => subject.pid == Some(*pid),
+            BanTarget::Username(name) => subject.username == Some(name.as_str()),
+            BanTarget::ConsoleId(id) => subject.console_id == Some(id.as_str()),
+            BanTarget::IpRange(range) => subject.ip.is_some_and(|ip| range.contains(ip)),
+        }
+    }
+
+    // whether a live connection belongs to whoever is banned, connections only know their pid and
+    // address so username and console bans only take effect on the next login
+    pub fn applies_to_connection(&self, connection: &ConnectionData) -> bool{
+        self.applies_to(&Subject{
+            pid: connection.authenticated_pid(),
+            ip: Some(*connection.sock_addr.regular_socket_addr.ip()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Everything known about someone trying to get in, unknown parts never match
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Subject<'a>{
+    pub pid: Option<u32>,
+    pub username: Option<&'a str>,
+    pub console_id: Option<&'a str>,
+    pub ip: Option<Ipv4Addr>,
+}
+
+/// Ban records shared by all servers of the process, kept in a json file if a path is given.
+///
+/// Changes are written immediately. The file is read again before every change so that edits made
+/// with the ban cli while the server is running don't get lost.
+#[derive(Default)]
+pub struct Bans{
+    path: Option<PathBuf>,
+    records: RwLock<Vec<Ban>>,
+    // bans other processes added which were picked up while changing the file, the next reload
+    // reports them so that they still get enforced
+    unreported: Mutex<Vec<Ban>>,
+    // the sockets of every title so that banned users can be kicked
+    sockets: Mutex<Vec<Weak<SocketData>>>,
+}
+
+fn read_file(path: &Path) -> Result<Vec<Ban>>{
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => return Err(Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+    };
+
+    serde_json::from_slice(&content).map_err(|source| Error::Parse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// held while the file gets read, changed and written again so that the server and the cli can't
+// overwrite each other's changes, the lock is released when the file is closed
+fn lock_file(path: &Path) -> Result<File>{
+    let lock_path = path.with_extension("lock");
+    let io_error = |source| Error::Io {
+        path: lock_path.clone(),
+        source,
+    };
+
+    let file = File::options().create(true).truncate(false).write(true).open(&lock_path).map_err(io_error)?;
+    file.lock().map_err(io_error)?;
+
+    Ok(file)
+}
+
+// writes to a temporary file first so that a crash can't leave a half written file behind
+fn write_file(path: &Path, records: &[Ban]) -> Result<()>{
+    let io_error = |source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let content = serde_json::to_vec_pretty(records).expect("bans are always serializable");
+
+    let temp_path = path.with_extension("tmp");
+
+    std::fs::write(&temp_path, content).map_err(io_error)?;
+    std::fs::rename(&temp_path, path).map_err(io_error)
+}
+
+impl Bans{
+    // a missing file is the same as an empty one
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self>{
+        let path = path.into();
+        let records = read_file(&path)?;
+
+        Ok(Self{
+            path: Some(path),
+            records: RwLock::new(records),
+            unreported: Default::default(),
+            sockets: Default::default(),
+        })
+    }
+
+    pub fn register_socket(&self, socket: &Arc<SocketData>){
+        let mut sockets = self.sockets.lock().unwrap();
+
+        sockets.retain(|s| s.strong_count() != 0);
+        sockets.push(Arc::downgrade(socket));
+    }
+
+    // replaces the records with the ones in the file, the bans which weren't known yet are
+    // remembered for the next reload
+    fn sync(&self, path: &Path, records: &mut Vec<Ban>) -> Result<()>{
+        let file = read_file(path)?;
+
+        self.unreported.lock().unwrap().extend(file.iter().filter(|b| !records.contains(b)).cloned());
+
+        *records = file;
+
+        Ok(())
+    }
+
+    // picks up changes other processes made to the file and returns the bans which are new
+    pub fn reload(&self) -> Result<Vec<Ban>>{
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+
+        let mut records = self.records.write().unwrap();
+
+        self.sync(path, &mut records)?;
+
+        let new = std::mem::take(&mut *self.unreported.lock().unwrap());
+
+        // the ban may have been lifted again in the meantime
+        Ok(new.into_iter().filter(|b| !b.is_expired() && records.contains(b)).collect())
+    }
+
+    fn modify<T>(&self, change: impl FnOnce(&mut Vec<Ban>) -> T) -> Result<T>{
+        let mut records = self.records.write().unwrap();
+
+        let _lock = self.path.as_deref().map(lock_file).transpose()?;
+
+        if let Some(path) = &self.path {
+            self.sync(path, &mut records)?;
+        }
+
+        let result = change(&mut records);
+
+        // nobody has to see expired bans in the file
+        records.retain(|b| !b.is_expired());
+
+        if let Some(path) = &self.path {
+            write_file(path, &records)?;
+        }
+
+        Ok(result)
+    }
+
+    // use `kick` afterwards to get rid of the sessions the ban applies to
+    pub fn add(&self, target: BanTarget, reason: String, issuer: String, duration: Option<Duration>) -> Result<Ban>{
+        if let BanTarget::ConsoleId(_) = target {
+            return Err(Error::UnsupportedTarget(target));
+        }
+
+        let ban = self.modify(|records| {
+            let created = unix_now();
+
+            let ban = Ban{
+                id: records.iter().map(|b| b.id).max().unwrap_or(0) + 1,
+                target,
+                reason,
+                issuer,
+                created,
+                expires: duration.map(|d| created + d.as_secs()),
+            };
+
+            records.push(ban.clone());
+
+            ban
+        })?;
+
+        info!("{} banned {} ({})", ban.issuer, ban.target, ban.reason);
+
+        Ok(ban)
+    }
+
+    pub fn remove(&self, id: u64) -> Result<Option<Ban>>{
+        let removed = self.modify(|records| {
+            let position = records.iter().position(|b| b.id == id)?;
+            Some(records.remove(position))
+        })?;
+
+        if let Some(ban) = &removed {
+            info!("lifted ban of {}", ban.target);
+        }
+
+        Ok(removed)
+    }
+
+    // every ban which hasn't expired yet
+    pub fn list(&self) -> Vec<Ban>{
+        self.records.read().unwrap().iter()
+            .filter(|b| !b.is_expired())
+            .cloned()
+            .collect()
+    }
+
+    // the ban keeping the subject out, permanent bans win over temporary ones
+    pub fn find(&self, subject: &Subject) -> Option<Ban>{
+        self.records.read().unwrap().iter()
+            .filter(|b| !b.is_expired() && b.applies_to(subject))
+            .max_by_key(|b| (b.is_permanent(), b.expires))
+            .cloned()
+    }
+
+    // disconnects every live session the ban applies to and returns how many there were
+    pub async fn kick(&self, ban: &Ban) -> usize{
+        let sockets: Vec<_> = self.sockets.lock().unwrap().iter()
+            .filter_map(|s| s.upgrade())
+            .collect();
+
+        let mut disconnected = 0;
+
+        for socket in sockets {
+            disconnected += socket.disconnect_where(|c| ban.applies_to_connection(c)).await;
+        }
+
+        if disconnected != 0 {
+            info!("kicked {} sessions of {}", disconnected, ban.target);
+        }
+
+        disconnected
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use super::{BanTarget, Bans, Error, IpRange, Subject};
+
+    #[test]
+    fn persisted_bans(){
+        let path = std::env::temp_dir().join(format!("bans-test-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains(Ipv4Addr::new(10, 1, 2, 3)));
+        assert!(!range.contains(Ipv4Addr::new(11, 0, 0, 0)));
+        assert!("0.0.0.0/0".parse::<IpRange>().unwrap().contains(Ipv4Addr::new(1, 2, 3, 4)));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+
+        let bans = Bans::load(&path).unwrap();
+
+        let console = bans.add(BanTarget::ConsoleId("1234".to_string()), "cheating".to_string(), "mod".to_string(), None);
+        assert!(matches!(console, Err(Error::UnsupportedTarget(_))));
+
+        let temporary = bans.add(BanTarget::Pid(1337), "cheating".to_string(), "mod".to_string(), Some(Duration::from_secs(60))).unwrap();
+        bans.add(BanTarget::IpRange(range), "abuse".to_string(), "mod".to_string(), None).unwrap();
+
+        let found = bans.find(&Subject{
+            pid: Some(1337),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(found, temporary);
+        assert!(!found.is_permanent());
+
+        // the permanent ban wins
+        let found = bans.find(&Subject{
+            pid: Some(1337),
+            ip: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            ..Default::default()
+        }).unwrap();
+        assert!(found.is_permanent());
+
+        assert!(bans.find(&Subject{
+            username: Some("1337"),
+            ip: Some(Ipv4Addr::new(192, 168, 0, 1)),
+            ..Default::default()
+        }).is_none());
+
+        // another process (e.g. the cli) adds a ban
+        let other = Bans::load(&path).unwrap();
+        assert_eq!(other.list().len(), 2);
+        other.add(BanTarget::Username("someone".to_string()), "spam".to_string(), "cli".to_string(), None).unwrap();
+
+        let new = bans.reload().unwrap();
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].id, 3);
+
+        // changing the file picks up what the cli added, it still has to be reported
+        other.add(BanTarget::Pid(42), "spam".to_string(), "cli".to_string(), None).unwrap();
+        bans.add(BanTarget::Pid(43), "spam".to_string(), "mod".to_string(), None).unwrap();
+
+        let new = bans.reload().unwrap();
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].target, BanTarget::Pid(42));
+        assert!(bans.reload().unwrap().is_empty());
+
+        assert!(bans.remove(temporary.id).unwrap().is_some());
+        assert!(bans.remove(temporary.id).unwrap().is_none());
+
+        let reloaded = Bans::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("lock"));
+
+        assert_eq!(reloaded.list().len(), 4);
+        assert!(reloaded.list().iter().all(|b| b.id != temporary.id));
+    }
+
+    #[test]
+    fn concurrent_changes(){
+        let path = std::env::temp_dir().join(format!("bans-concurrent-test-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // e.g. the server and the cli changing the file at the same time
+        let threads: Vec<_> = (0..2).map(|_| {
+            let path = path.clone();
+
+            std::thread::spawn(move || {
+                let bans = Bans::load(&path).unwrap();
+
+                for pid in 0..20 {
+                    bans.add(BanTarget::Pid(pid), "spam".to_string(), "mod".to_string(), None).unwrap();
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let bans = Bans::load(&path).unwrap().list();
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("lock"));
+
+        assert_eq!(bans.len(), 40);
+        assert!(bans.iter().enumerate().all(|(i, b)| b.id == i as u64 + 1));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::SystemTime;
+use log::info;
+use crate::prudp::socket::SocketData;
+
+/// One maintenance, either for everything or for a single title
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaintenanceWindow{
+    pub message: Option<String>,
+    // the maintenance ends by itself at this point
+    pub until: Option<SystemTime>,
+    // these pids (e.g. testers) can still log in
+    pub allowed_pids: HashSet<u32>,
+    // disconnect everyone who isn't allowed in when the maintenance starts
+    pub drain: bool,
+}
+
+impl MaintenanceWindow{
+    pub fn is_over(&self) -> bool{
+        self.until.is_some_and(|until| until <= SystemTime::now())
+    }
+
+    pub fn allows(&self, pid: Option<u32>) -> bool{
+        pid.is_some_and(|pid| self.allowed_pids.contains(&pid))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MaintenanceScope{
+    Global,
+    Title(String),
+}
+
+impl MaintenanceScope{
+    fn covers(&self, title: &str) -> bool{
+        match self {
+            MaintenanceScope::Global => true,
+            MaintenanceScope::Title(t) => t == title,
+        }
+    }
+}
+
+/// Runtime switch for rejecting new logins, shared by all servers of the process
+#[derive(Default)]
+pub struct Maintenance{
+    windows: RwLock<HashMap<MaintenanceScope, MaintenanceWindow>>,
+    // the sockets of every title so that they can be drained
+    sockets: Mutex<Vec<(String, Weak<SocketData>)>>,
+}
+
+impl Maintenance{
+    pub fn register_socket(&self, title: &str, socket: &Arc<SocketData>){
+        let mut sockets = self.sockets.lock().unwrap();
+
+        sockets.retain(|(_, s)| s.strong_count() != 0);
+        sockets.push((title.to_string(), Arc::downgrade(socket)));
+    }
+
+    // starts (or replaces) the maintenance and returns how many connections got drained
+    pub async fn start(&self, scope: MaintenanceScope, window: MaintenanceWindow) -> usize{
+        info!("starting maintenance for {:?}: {:?}", scope, window);
+
+        let drain = window.drain.then(|| window.clone());
+
+        self.windows.write().unwrap().insert(scope.clone(), window);
+
+        let Some(window) = drain else {
+            return 0;
+        };
+
+        let sockets: Vec<_> = self.sockets.lock().unwrap().iter()
+            .filter(|(title, _)| scope.covers(title))
+            .filter_map(|(_, s)| s.upgrade())
+            .collect();
+
+        let mut drained = 0;
+
+        for socket in sockets {
+            drained += socket.disconnect_where(|c| !window.allows(c.authenticated_pid())).await;
+        }
+
+        info!("drained {} connections for maintenance", drained);
+
+        drained
+    }
+
+    // returns false if there was no maintenance for the scope
+    pub fn end(&self, scope: &MaintenanceScope) -> bool{
+        let ended = self.windows.write().unwrap().remove(scope).is_some();
+
+        if ended {
+            info!("ended maintenance for {:?}", scope);
+        }
+
+        ended
+    }
+
+    // every maintenance which hasn't ended yet
+    pub fn windows(&self) -> Vec<(MaintenanceScope, MaintenanceWindow)>{
+        self.windows.read().unwrap().iter()
+            .filter(|(_, w)| !w.is_over())
+            .map(|(s, w)| (s.clone(), w.clone()))
+            .collect()
+    }
+
+    // the maintenance which applies to the title, a maintenance of the title itself wins over a
+    // global one
+    pub fn active(&self, title: &str) -> Option<MaintenanceWindow>{
+        let windows = self.windows.read().unwrap();
+
+        windows.get(&MaintenanceScope::Title(title.to_string()))
+            .filter(|w| !w.is_over())
+            .or_else(|| windows.get(&MaintenanceScope::Global).filter(|w| !w.is_over()))
+            .cloned()
+    }
+
+    // returns the maintenance keeping the pid out, if there is one
+    pub fn blocks(&self, title: &str, pid: Option<u32>) -> Option<MaintenanceWindow>{
+        self.active(title).filter(|w| !w.allows(pid))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::time::{Duration, SystemTime};
+    use super::{Maintenance, MaintenanceScope, MaintenanceWindow};
+
+    #[tokio::test]
+    async fn scopes_and_whitelist(){
+        let maintenance = Maintenance::default();
+
+        assert!(maintenance.blocks("splatoon", Some(1)).is_none());
+
+        maintenance.start(MaintenanceScope::Title("splatoon".to_string()), MaintenanceWindow{
+            allowed_pids: [1].into(),
+            ..Default::default()
+        }).await;
+
+        assert!(maintenance.blocks("splatoon", Some(1)).is_none());
+        assert!(maintenance.blocks("splatoon", Some(2)).is_some());
+        assert!(maintenance.blocks("splatoon", None).is_some());
+        assert!(maintenance.blocks("other", Some(2)).is_none());
+
+        // the title specific maintenance wins over the global one
+        maintenance.start(MaintenanceScope::Global, MaintenanceWindow::default()).await;
+        assert!(maintenance.blocks("splatoon", Some(1)).is_none());
+        assert!(maintenance.blocks("other", Some(1)).is_some());
+
+        assert!(maintenance.end(&MaintenanceScope::Global));
+        assert!(!maintenance.end(&MaintenanceScope::Global));
+
+        // ends by itself
+        maintenance.start(MaintenanceScope::Title("splatoon".to_string()), MaintenanceWindow{
+            until: Some(SystemTime::now() - Duration::from_secs(1)),
+            ..Default::default()
+        }).await;
+
+        assert!(maintenance.blocks("splatoon", Some(2)).is_none());
+        assert!(maintenance.windows().is_empty());
+    }
+}
@@ -1,5 +1,171 @@
 pub mod auth;
 pub mod server;
+pub mod notifications;
+pub mod secure;
+pub mod trace;
+pub mod maintenance;
+pub mod bans;
+pub mod accounts;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use log::error;
+use crate::prudp::socket::{ConnectionData, SocketData};
+use crate::protocols::notifications::NotificationManager;
+use crate::protocols::maintenance::Maintenance;
+use crate::protocols::bans::Bans;
+use crate::protocols::accounts::Accounts;
+use crate::protocols::trace::RmcTrace;
+use crate::rmc::message::RMCMessage;
+use crate::rmc::response::{ErrorCode, RMCResponse, RMCResponseResult};
+use crate::rmc::structures::{RmcSerialize, SerializationContext};
+
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output=RMCResponseResult> + Send + 'a>>;
+
+/// A protocol which can be registered on an [`RMCProtocolServer`](server::RMCProtocolServer).
+///
+/// Unlike the functions generated by [`define_protocol!`] the handler is async and gets access to
+/// the connection of the caller as well as the state shared by the whole server.
+pub trait Protocol: Send + Sync{
+    fn id(&self) -> u16;
+    fn name(&self) -> &'static str;
+    // only used for logging, so protocols don't have to know the names of all of their methods
+    fn method_name(&self, _method_id: u32) -> Option<&'static str>{
+        None
+    }
+    // a readable form of the parameters for the rmc trace, which falls back to a hex preview
+    // without this. Anything secret like login tokens has to be left out
+    fn describe_params(&self, _method_id: u32, _params: &[u8], _ctx: &SerializationContext) -> Option<String>{
+        None
+    }
+    fn handle<'a>(&'a self, ctx: &'a mut RmcContext<'_>, method_id: u32, params: &'a [u8]) -> HandlerFuture<'a>;
+}
+
+// every protocol which can be enabled by name (e.g. from the config file)
+pub const PROTOCOL_NAMES: &[&str] = &["Authentication", "SecureConnection"];
+
+pub fn protocol_by_name(name: &str) -> Option<Box<dyn Protocol>>{
+    Some(match name {
+        "Authentication" => Box::new(auth::AuthenticationProtocol),
+        "SecureConnection" => Box::new(secure::SecureConnectionProtocol),
+        _ => return None,
+    })
+}
+
+/// State shared between all connections of a server
+#[derive(Default)]
+pub struct ServerState{
+    pub title: String,
+    // the account namespace of the title, titles sharing a realm share their accounts
+    pub realm: String,
+    // shared by the auth and secure servers of a realm
+    pub accounts: Arc<Accounts>,
+    // the station url of the secure server the auth server sends clients to after logging in
+    pub secure_station_url: Option<String>,
+    pub notifications: Option<Arc<NotificationManager>>,
+    pub rmc_trace: RmcTrace,
+    // shared by all servers so that a maintenance covers all of them
+    pub maintenance: Arc<Maintenance>,
+    pub bans: Arc<Bans>,
+}
+
+/// Everything a protocol handler knows about the call it is handling
+pub struct RmcContext<'a>{
+    pub socket: Arc<SocketData>,
+    pub connection: &'a mut ConnectionData,
+    pub state: Arc<ServerState>,
+    pub serialization_context: SerializationContext,
+    pub call_id: u32,
+}
+
+impl<'a> RmcContext<'a>{
+    pub fn pid(&self) -> Option<u32>{
+        self.connection.active_connection_data.as_ref().and_then(|a| a.pid)
+    }
+
+    pub fn station_urls(&self) -> &[String]{
+        self.connection.active_connection_data.as_ref().map(|a| &a.station_urls[..]).unwrap_or(&[])
+    }
+
+    pub fn success(&self, method_id: u32, data: Vec<u8>) -> RMCResponseResult{
+        RMCResponseResult::Success {
+            call_id: self.call_id,
+            method_id,
+            data
+        }
+    }
+
+    pub fn error(&self, error_code: ErrorCode) -> RMCResponseResult{
+        RMCResponseResult::Error {
+            call_id: self.call_id,
+            error_code
+        }
+    }
+
+    // turns the result of a handler into the response, the value gets serialized as the return
+    // values of the method (use a tuple for methods with multiple ones)
+    pub fn respond<T: RmcSerialize>(&self, method_id: u32, result: Result<T, ErrorCode>) -> RMCResponseResult{
+        let value = match result {
+            Ok(v) => v,
+            Err(e) => return self.error(e),
+        };
+
+        let mut data = Vec::new();
+
+        if let Err(e) = value.serialize(&mut data, &self.serialization_context) {
+            error!("unable to serialize response: {}", e);
+            return self.error(ErrorCode::Core_Exception);
+        }
+
+        self.success(method_id, data)
+    }
+}
+
+/// Wraps a function generated by [`define_protocol!`] so that it can be registered next to
+/// protocols implementing [`Protocol`] directly
+pub struct FnProtocol<F: Fn(&RMCMessage) -> Option<RMCResponse> + Send + Sync>{
+    id: u16,
+    name: &'static str,
+    func: F,
+}
+
+impl<F: Fn(&RMCMessage) -> Option<RMCResponse> + Send + Sync> FnProtocol<F>{
+    pub fn new(id: u16, name: &'static str, func: F) -> Self{
+        Self{
+            id,
+            name,
+            func
+        }
+    }
+}
+
+impl<F: Fn(&RMCMessage) -> Option<RMCResponse> + Send + Sync> Protocol for FnProtocol<F>{
+    fn id(&self) -> u16 {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn handle<'a>(&'a self, ctx: &'a mut RmcContext<'_>, method_id: u32, params: &'a [u8]) -> HandlerFuture<'a> {
+        let message = RMCMessage{
+            protocol_id: self.id,
+            call_id: ctx.call_id,
+            method_id,
+            rest_of_data: params.to_vec(),
+        };
+
+        let result = match (self.func)(&message){
+            Some(response) => response.response_result,
+            None => ctx.error(ErrorCode::Core_NotImplemented)
+        };
+
+        Box::pin(async move { result })
+    }
+}
+
 #[macro_export]
 macro_rules! define_protocol {
     ($id:literal => {$($func_id:literal => $func:path),*} ) => {
@@ -24,4 +190,4 @@ macro_rules! define_protocol {
             })
         }
     };
-}
\ No newline at end of file
+}
@@ -0,0 +1,222 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Weak};
+use log::{error, info, warn};
+use tokio::sync::Mutex;
+use crate::prudp::socket::{ConnectionData, SocketData};
+use crate::rmc::message::{send_request, RMCMessage};
+use crate::rmc::structures::{RmcSerialize, SerializationContext};
+use crate::rmc::structures::pid::Pid;
+
+pub const PROTOCOL_ID: u16 = 14;
+
+pub const METHOD_PROCESS_NOTIFICATION_EVENT: u32 = 1;
+
+// the notification type is made up of `category * 1000 + subtype`
+pub mod categories {
+    pub const PARTICIPATION_EVENT: u32 = 3;
+    pub const OWNERSHIP_CHANGE_EVENT: u32 = 4;
+    pub const GATHERING_UNREGISTERED: u32 = 109;
+    pub const HOST_CHANGE_EVENT: u32 = 110;
+}
+
+pub mod participation_subtypes {
+    pub const PARTICIPATED: u32 = 1;
+    pub const CANCEL_PARTICIPATION: u32 = 2;
+    pub const DISCONNECTED: u32 = 7;
+    pub const ENDED: u32 = 8;
+}
+
+#[derive(RmcSerialize, Debug, Clone, Default)]
+#[rmc(version = 0)]
+pub struct NotificationEvent{
+    pub pid_source: Pid,
+    pub notification_type: u32,
+    pub param_1: u32,
+    pub param_2: u32,
+    pub str_param: String,
+    pub param_3: u32,
+}
+
+impl NotificationEvent{
+    pub const fn notification_type(category: u32, subtype: u32) -> u32{
+        category * 1000 + subtype
+    }
+}
+
+/// What to do with events for users which currently don't have a live connection
+#[derive(Debug, Copy, Clone)]
+pub enum OfflinePolicy{
+    Drop,
+    // keeps up to the given amount of events per user and delivers them once the user registers
+    // again, the oldest events get dropped first
+    Queue(usize),
+}
+
+/// Delivers events to the users connected to the sockets of a secure server, users show up once
+/// they registered their connection
+pub struct NotificationManager{
+    // a server with multiple virtual ports has a socket for each of them
+    sockets: std::sync::Mutex<Vec<Weak<SocketData>>>,
+    serialization_context: SerializationContext,
+    policy: OfflinePolicy,
+    queued_events: Mutex<HashMap<u32, VecDeque<NotificationEvent>>>,
+}
+
+impl NotificationManager{
+    pub fn new(serialization_context: SerializationContext, policy: OfflinePolicy) -> Arc<Self>{
+        Arc::new(Self{
+            sockets: Default::default(),
+            serialization_context,
+            policy,
+            queued_events: Default::default(),
+        })
+    }
+
+    pub fn register_socket(&self, socket: &Arc<SocketData>){
+        let mut sockets = self.sockets.lock().unwrap();
+
+        sockets.retain(|s| s.strong_count() != 0);
+        sockets.push(Arc::downgrade(socket));
+    }
+
+    async fn find_connection(&self, pid: u32) -> Option<(Arc<SocketData>, Arc<Mutex<ConnectionData>>)>{
+        let sockets: Vec<_> = self.sockets.lock().unwrap().iter()
+            .filter_map(|s| s.upgrade())
+            .collect();
+
+        for socket in sockets {
+            if let Some(connection) = socket.find_connection_by_pid(pid).await {
+                return Some((socket, connection));
+            }
+        }
+
+        None
+    }
+
+    // Sends the event to the live connection of the target pid or applies the offline policy.
+    //
+    // This locks the connection of the target so it must not be called while holding that lock,
+    // use `dispatch` from inside of rmc handlers instead.
+    pub async fn send(&self, target_pid: u32, event: NotificationEvent){
+        let Some((socket, connection)) = self.find_connection(target_pid).await else {
+            self.handle_offline(target_pid, event).await;
+            return;
+        };
+
+        let mut connection = connection.lock().await;
+
+        // the pid index may be outdated if the connection got reused
+        if connection.active_connection_data.as_ref().and_then(|a| a.pid) != Some(target_pid) {
+            drop(connection);
+            self.handle_offline(target_pid, event).await;
+            return;
+        }
+
+        let mut rest_of_data = Vec::new();
+
+        if let Err(e) = event.serialize(&mut rest_of_data, &self.serialization_context){
+            error!("unable to serialize notification event: {}", e);
+            return;
+        }
+
+        let message = RMCMessage{
+            protocol_id: PROTOCOL_ID,
+            call_id: 0,
+            method_id: METHOD_PROCESS_NOTIFICATION_EVENT,
+            rest_of_data,
+        };
+
+        send_request(&socket, &mut connection, message).await;
+    }
+
+    // same as send but doesn't wait for the event to be delivered
+    pub fn dispatch(self: &Arc<Self>, target_pid: u32, event: NotificationEvent){
+        let manager = self.clone();
+
+        tokio::spawn(async move {
+            manager.send(target_pid, event).await;
+        });
+    }
+
+    // Has to be called after a user registered their connection to deliver queued events. Like
+    // `send` this locks the connection of the user, use `dispatch_user_connected` from inside of
+    // rmc handlers instead.
+    pub async fn user_connected(&self, pid: u32){
+        let Some(events) = self.queued_events.lock().await.remove(&pid) else {
+            return;
+        };
+
+        info!("delivering {} queued notifications to {}", events.len(), pid);
+
+        for event in events {
+            self.send(pid, event).await;
+        }
+    }
+
+    pub fn dispatch_user_connected(self: &Arc<Self>, pid: u32){
+        let manager = self.clone();
+
+        tokio::spawn(async move {
+            manager.user_connected(pid).await;
+        });
+    }
+
+    async fn handle_offline(&self, target_pid: u32, event: NotificationEvent){
+        match self.policy {
+            OfflinePolicy::Drop => {
+                info!("dropping notification for offline user {}", target_pid);
+            }
+            OfflinePolicy::Queue(max_events) => {
+                let mut queued_events = self.queued_events.lock().await;
+                let queue = queued_events.entry(target_pid).or_default();
+
+                if queue.len() >= max_events {
+                    warn!("notification queue of {} is full, dropping oldest event", target_pid);
+                    queue.pop_front();
+                }
+
+                queue.push_back(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{NexVersion, RmcSerialize, SerializationContext};
+    use crate::rmc::structures::pid::Pid;
+    use super::{categories, participation_subtypes, NotificationEvent};
+
+    #[test]
+    fn notification_event_round_trip(){
+        let ctx = SerializationContext::default();
+
+        let event = NotificationEvent{
+            pid_source: Pid(1337),
+            notification_type: NotificationEvent::notification_type(categories::PARTICIPATION_EVENT, participation_subtypes::PARTICIPATED),
+            param_1: 1,
+            param_2: 2,
+            str_param: "test".to_string(),
+            param_3: 3,
+        };
+
+        let mut data = Vec::new();
+        event.serialize(&mut data, &ctx).unwrap();
+
+        // header + 4 u32s + string + u32
+        assert_eq!(data.len(), 5 + 16 + 2 + 5 + 4);
+
+        let read = NotificationEvent::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+
+        assert_eq!(read.pid_source, Pid(1337));
+        assert_eq!(read.notification_type, 3001);
+        assert_eq!(read.str_param, "test");
+        assert_eq!(read.param_3, 3);
+
+        // titles with 64 bit pids get the wider pid
+        let mut data = Vec::new();
+        event.serialize(&mut data, &SerializationContext::for_version(NexVersion::new(4, 0, 0))).unwrap();
+        assert_eq!(data.len(), 5 + 4 + 16 + 2 + 5 + 4);
+    }
+}
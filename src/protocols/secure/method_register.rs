@@ -0,0 +1,110 @@
+use std::io::Cursor;
+use std::net::SocketAddrV4;
+use log::{error, info};
+use crate::protocols::bans::Subject;
+use crate::protocols::RmcContext;
+use crate::rmc::response::ErrorCode;
+use crate::rmc::structures::result_code::ResultCode;
+use crate::rmc::structures::RmcSerialize;
+
+// real station urls are around 150 characters, anything much longer is made up
+const MAX_STATION_URL_LENGTH: usize = 1024;
+
+// replaces the address and port of a station url with the ones we actually see the client from
+fn public_station_url(url: &str, public_addr: SocketAddrV4) -> String{
+    let Some((scheme, params)) = url.split_once(":/") else {
+        return url.to_string();
+    };
+
+    let params: Vec<String> = params.split(';')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some(("address", _)) => format!("address={}", public_addr.ip()),
+            Some(("port", _)) => format!("port={}", public_addr.port()),
+            _ => p.to_string(),
+        })
+        .collect();
+
+    format!("{}:/{}", scheme, params.join(";"))
+}
+
+// returns the result, the connection id and the public station url of the client
+pub async fn register_raw_params(ctx: &mut RmcContext<'_>, params: &[u8]) -> Result<(ResultCode, u32, String), ErrorCode>{
+    let mut reader = Cursor::new(params);
+
+    let station_urls = Vec::<String>::deserialize(&mut reader, &ctx.serialization_context)
+        .inspect_err(|e| error!("error reading packet: {}", e))?;
+
+    let Some(first_url) = station_urls.first() else {
+        error!("client tried to register without any station urls");
+        return Err(ErrorCode::Core_InvalidArgument);
+    };
+
+    if station_urls.iter().any(|url| url.len() > MAX_STATION_URL_LENGTH) {
+        error!("client tried to register with a station url longer than {} bytes", MAX_STATION_URL_LENGTH);
+        return Err(ErrorCode::Core_InvalidArgument);
+    }
+
+    let Some(pid) = ctx.connection.active_connection_data.as_ref().and_then(|a| a.ticket_pid) else {
+        error!("{} tried to register without a ticket", ctx.connection.sock_addr.regular_socket_addr);
+        return Err(ErrorCode::Core_AccessDenied);
+    };
+
+    let ban = ctx.state.bans.find(&Subject{
+        pid: Some(pid),
+        ip: Some(*ctx.connection.sock_addr.regular_socket_addr.ip()),
+        ..Default::default()
+    });
+
+    if let Some(ban) = ban {
+        info!("rejected register of {} due to ban {} ({})", ctx.connection.sock_addr.regular_socket_addr, ban.id, ban.reason);
+        return Err(ban.error_code());
+    }
+
+    if let Some(maintenance) = ctx.state.maintenance.blocks(&ctx.state.title, Some(pid)) {
+        info!("rejected register of {} due to maintenance ({})", ctx.connection.sock_addr.regular_socket_addr, maintenance.message.as_deref().unwrap_or("no message"));
+        return Err(ErrorCode::RendezVous_GameServerMaintenance);
+    }
+
+    let public_url = public_station_url(first_url, ctx.connection.sock_addr.regular_socket_addr);
+
+    // filling in the addresses makes the url longer than what the client sent
+    if public_url.len() > MAX_STATION_URL_LENGTH {
+        error!("public station url of {} would be longer than {} bytes", pid, MAX_STATION_URL_LENGTH);
+        return Err(ErrorCode::Core_InvalidArgument);
+    }
+    let connection_id = ctx.connection.id as u32;
+
+    let Some(active_connection) = ctx.connection.active_connection_data.as_mut() else {
+        return Err(ErrorCode::Core_InvalidArgument);
+    };
+
+    info!("registered station urls of {}: {:?}", pid, station_urls);
+
+    active_connection.station_urls = station_urls;
+
+    ctx.socket.register_pid(ctx.connection, pid).await;
+
+    // the connection is only unlocked again once this call is done
+    if let Some(notifications) = &ctx.state.notifications {
+        notifications.dispatch_user_connected(pid);
+    }
+
+    Ok((ResultCode::SUCCESS, connection_id, public_url))
+}
+
+#[cfg(test)]
+mod test{
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use super::public_station_url;
+
+    #[test]
+    fn public_url_uses_observed_address(){
+        let url = public_station_url(
+            "prudp:/address=192.168.0.2;port=5000;natf=0;natm=0;type=2",
+            SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 1234)
+        );
+
+        assert_eq!(url, "prudp:/address=1.2.3.4;port=1234;natf=0;natm=0;type=2");
+    }
+}
@@ -0,0 +1,68 @@
+mod method_register;
+
+use std::io::Cursor;
+use log::error;
+use crate::protocols::{HandlerFuture, Protocol, RmcContext};
+use crate::protocols::secure::method_register::register_raw_params;
+use crate::rmc::response::ErrorCode;
+use crate::rmc::structures::{RmcSerialize, SerializationContext};
+
+pub const PROTOCOL_ID: u16 = 11;
+
+pub const METHOD_REGISTER: u32 = 0x01;
+pub const METHOD_REQUEST_CONNECTION_DATA: u32 = 0x02;
+pub const METHOD_REQUEST_URLS: u32 = 0x03;
+pub const METHOD_REGISTER_EX: u32 = 0x04;
+pub const METHOD_TEST_CONNECTIVITY: u32 = 0x05;
+pub const METHOD_UPDATE_URLS: u32 = 0x06;
+pub const METHOD_REPLACE_URL: u32 = 0x07;
+pub const METHOD_SEND_REPORT: u32 = 0x08;
+
+pub struct SecureConnectionProtocol;
+
+impl Protocol for SecureConnectionProtocol{
+    fn id(&self) -> u16 {
+        PROTOCOL_ID
+    }
+
+    fn name(&self) -> &'static str {
+        "SecureConnection"
+    }
+
+    fn method_name(&self, method_id: u32) -> Option<&'static str> {
+        Some(match method_id {
+            METHOD_REGISTER => "Register",
+            METHOD_REQUEST_CONNECTION_DATA => "RequestConnectionData",
+            METHOD_REQUEST_URLS => "RequestURLs",
+            METHOD_REGISTER_EX => "RegisterEx",
+            METHOD_TEST_CONNECTIVITY => "TestConnectivity",
+            METHOD_UPDATE_URLS => "UpdateURLs",
+            METHOD_REPLACE_URL => "ReplaceURL",
+            METHOD_SEND_REPORT => "SendReport",
+            _ => return None,
+        })
+    }
+
+    fn describe_params(&self, method_id: u32, params: &[u8], ctx: &SerializationContext) -> Option<String> {
+        match method_id {
+            METHOD_REGISTER => Vec::<String>::deserialize(&mut Cursor::new(params), ctx).ok()
+                .map(|station_urls| format!("station_urls={:?}", station_urls)),
+            _ => None,
+        }
+    }
+
+    fn handle<'a>(&'a self, ctx: &'a mut RmcContext<'_>, method_id: u32, params: &'a [u8]) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            match method_id {
+                METHOD_REGISTER => {
+                    let result = register_raw_params(ctx, params).await;
+                    ctx.respond(method_id, result)
+                }
+                _ => {
+                    error!("invalid method id sent to protocol {}: {:?}", PROTOCOL_ID, method_id);
+                    ctx.error(ErrorCode::Core_NotImplemented)
+                }
+            }
+        })
+    }
+}
@@ -1,42 +1,190 @@
 use std::io::Cursor;
 use std::sync::Arc;
-use log::error;
-use crate::prudp::packet::PRUDPPacket;
-use crate::prudp::socket::{ConnectionData, SocketData};
-use crate::rmc::message::RMCMessage;
+use std::time::Instant;
+use log::{error, trace, warn};
+use crate::protocols::{Protocol, RmcContext, ServerState};
+use crate::protocols::trace::CallTrace;
+use crate::metrics::METRICS;
+use rc4::{KeyInit, Rc4, StreamCipher};
+use rc4::consts::U5;
+use crate::prudp::packet::{PRUDPPacket, VirtualPort};
+use crate::prudp::router;
+use crate::prudp::router::Router;
+use crate::prudp::socket::{ConnectionData, Socket, SocketData};
+use crate::rmc::message::{Error, RMCMessage};
 use crate::rmc::response::{RMCResponse, RMCResponseResult, send_response};
-use crate::rmc::response::ErrorCode::Core_NotImplemented;
+use crate::rmc::response::ErrorCode::{Core_NotImplemented, RendezVous_LimitExceeded};
+use crate::rmc::structures::{RmcSerialize, SerializationContext};
+use crate::rmc::structures::buffer::Buffer;
 
-type ContainedProtocolList = Box<[Box<dyn Fn(&RMCMessage) -> Option<RMCResponse> + Send + Sync>]>;
+// the rc4 key used by most titles including splatoon
+pub const DEFAULT_ENCRYPTION_KEY: &str = "CD&ML";
 
-pub struct RMCProtocolServer(ContainedProtocolList);
+type ContainedProtocolList = Box<[Box<dyn Protocol>]>;
+
+// The payload of a connect to the secure server is the ticket handed out by LoginEx followed by
+// check data. The check data is encrypted with the session key of the ticket, our tickets don't
+// have one, so it isn't looked at.
+fn read_ticket(payload: &[u8], state: &ServerState, ctx: &SerializationContext) -> Option<u32>{
+    let ticket = Buffer::deserialize(&mut Cursor::new(payload), ctx).ok()?;
+
+    state.accounts.redeem_ticket(&ticket.0)
+}
+
+pub struct RMCProtocolServer{
+    protocols: ContainedProtocolList,
+    state: Arc<ServerState>,
+    serialization_context: SerializationContext,
+}
 
 impl RMCProtocolServer{
-    pub fn new(protocols: ContainedProtocolList) -> Arc<Self>{
-        Arc::new(Self(protocols))
+    pub fn new(protocols: ContainedProtocolList, state: Arc<ServerState>, serialization_context: SerializationContext) -> Arc<Self>{
+        Arc::new(Self{
+            protocols,
+            state,
+            serialization_context
+        })
     }
 
-    pub async fn process_message(&self, packet: PRUDPPacket, socket: &SocketData, connection: &mut ConnectionData){
-        let Ok(rmc) = RMCMessage::new(&mut Cursor::new(&packet.payload)) else {
-            error!("error reading rmc message");
-            return;
-        };
+    pub fn get_state(&self) -> Arc<ServerState>{
+        self.state.clone()
+    }
+
+    // creates a socket on the router which hands all rmc messages it receives to this server
+    pub async fn listen(self: &Arc<Self>, router: Arc<Router>, port: VirtualPort, access_key: &'static str) -> Result<Socket, router::Error>{
+        self.listen_with_key(router, port, access_key, DEFAULT_ENCRYPTION_KEY).await
+    }
+
+    // same as `listen` for titles which don't use the default rc4 key, the key has to be 5 bytes long
+    pub async fn listen_with_key(self: &Arc<Self>, router: Arc<Router>, port: VirtualPort, access_key: &'static str, encryption_key: &'static str) -> Result<Socket, router::Error>{
+        let rmcserver = self.clone();
+        let state = self.state.clone();
+        let serialization_context = self.serialization_context;
 
-        println!("recieved rmc message: {{ protocol: {}, method: {}}}", rmc.protocol_id, rmc.method_id);
+        Socket::new(
+            router,
+            port,
+            access_key,
+            Box::new(move |packet|{
+                let state = state.clone();
 
-        for proto in &self.0 {
-            if let Some(response) = proto(&rmc) {
-                send_response(&packet, &socket, connection, response).await;
-                return;
+                Box::pin(
+                    async move {
+                        let rc4: Rc4<U5> = Rc4::new_from_slice(encryption_key.as_bytes()).unwrap();
+                        let cypher = Box::new(rc4);
+                        let server_cypher: Box<dyn StreamCipher + Send + Sync> = cypher;
+
+                        let rc4: Rc4<U5> = Rc4::new_from_slice(encryption_key.as_bytes()).unwrap();
+                        let cypher = Box::new(rc4);
+                        let client_cypher: Box<dyn StreamCipher + Send + Sync> = cypher;
+
+                        // connections to the auth server don't carry a ticket
+                        if packet.payload.is_empty() {
+                            return (true, (server_cypher, client_cypher), None);
+                        }
+
+                        match read_ticket(&packet.payload, &state, &serialization_context) {
+                            Some(pid) => (true, (server_cypher, client_cypher), Some(pid)),
+                            None => {
+                                warn!("rejected connection with an invalid ticket");
+                                (false, (server_cypher, client_cypher), None)
+                            }
+                        }
+                    }
+                )
+            }),
+            Box::new(move |packet, socket, connection|{
+                let rmcserver = rmcserver.clone();
+                Box::pin(async move { rmcserver.process_message(packet, socket, connection).await; })
+            })
+        ).await
+    }
+
+    pub async fn process_message(&self, packet: PRUDPPacket, socket: Arc<SocketData>, connection: &mut ConnectionData){
+        for message in RMCMessage::read_all(&packet.payload){
+            match message {
+                Ok(rmc) => self.process_request(&packet, &socket, connection, rmc).await,
+                Err(e @ Error::NotARequest { .. }) => {
+                    trace!("ignoring rmc message: {}", e);
+                }
+                Err(e) => {
+                    error!("error reading rmc message: {}", e);
+
+                    if let Some((protocol_id, call_id, error_code)) = e.failed_call() {
+                        send_response(&packet, &socket, connection, RMCResponse{
+                            protocol_id,
+                            response_result: RMCResponseResult::Error {
+                                call_id,
+                                error_code
+                            }
+                        }).await;
+                    }
+                }
             }
         }
+    }
+
+    async fn process_request(&self, packet: &PRUDPPacket, socket: &Arc<SocketData>, connection: &mut ConnectionData, rmc: RMCMessage){
+        trace!("recieved rmc message: {{ protocol: {}, method: {} }}", rmc.protocol_id, rmc.method_id);
 
-        send_response(&packet, &socket, connection, RMCResponse{
-            protocol_id: rmc.protocol_id as u8,
-            response_result: RMCResponseResult::Error {
+        let started = Instant::now();
+
+        let proto = self.protocols.iter().find(|p| p.id() == rmc.protocol_id);
+
+        let connection_ip = *connection.sock_addr.regular_socket_addr.ip();
+
+        let mut ctx = RmcContext{
+            socket: socket.clone(),
+            connection: &mut *connection,
+            state: self.state.clone(),
+            serialization_context: self.serialization_context,
+            call_id: rmc.call_id,
+        };
+
+        let allowed = socket.rate_limiter().allow_rmc_call(ctx.pid(), connection_ip);
+
+        let response_result = match proto {
+            _ if !allowed => ctx.error(RendezVous_LimitExceeded),
+            Some(proto) => proto.handle(&mut ctx, rmc.method_id, &rmc.rest_of_data).await,
+            None => ctx.error(Core_NotImplemented),
+        };
+
+        let latency = started.elapsed();
+
+        // the ids come from the client, using them as labels would let anyone create as many
+        // series as they want
+        let protocol_name = proto.map(|p| p.name()).unwrap_or("unknown");
+        let method_name = proto.and_then(|p| p.method_name(rmc.method_id)).unwrap_or("unknown");
+        let result = match &response_result {
+            RMCResponseResult::Success { .. } => "success",
+            RMCResponseResult::Error { error_code, .. } => error_code.name(),
+        };
+
+        METRICS.rmc_calls.with(&[protocol_name, method_name, result]).inc();
+        METRICS.rmc_latency.with(&[protocol_name, method_name]).observe(latency);
+
+        // the pid may have only been set by this call (e.g. secure register)
+        let pid = ctx.pid();
+
+        if self.state.rmc_trace.is_traced(pid, rmc.protocol_id) {
+            CallTrace{
+                connection_id: connection.id,
+                pid,
+                protocol_id: rmc.protocol_id,
+                protocol_name: proto.map(|p| p.name()),
+                method_id: rmc.method_id,
+                method_name: proto.and_then(|p| p.method_name(rmc.method_id)),
                 call_id: rmc.call_id,
-                error_code: Core_NotImplemented
-            }
+                params: &rmc.rest_of_data,
+                params_description: proto.and_then(|p| p.describe_params(rmc.method_id, &rmc.rest_of_data, &self.serialization_context)),
+                result: &response_result,
+                latency,
+            }.log();
+        }
+
+        send_response(packet, socket, connection, RMCResponse{
+            protocol_id: rmc.protocol_id,
+            response_result
         }).await;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::Duration;
+use log::info;
+use crate::rmc::response::RMCResponseResult;
+
+// log target of the trace lines, this makes it possible to send them to their own file
+pub const TARGET: &str = "rmc_trace";
+
+// how many bytes of the parameters get logged when the protocol can't describe them
+const PARAM_PREVIEW: usize = 32;
+
+/// Which calls get traced, everything in here can be changed while the server is running
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter{
+    pub all: bool,
+    pub pids: HashSet<u32>,
+    pub protocols: HashSet<u16>,
+}
+
+impl TraceFilter{
+    pub fn matches(&self, pid: Option<u32>, protocol_id: u16) -> bool{
+        self.all ||
+            self.protocols.contains(&protocol_id) ||
+            pid.is_some_and(|pid| self.pids.contains(&pid))
+    }
+}
+
+impl FromStr for TraceFilter{
+    type Err = String;
+
+    // accepts "all" or a comma separated list of "pid:<pid>" and "protocol:<id>"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = TraceFilter::default();
+
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let invalid = || format!("invalid trace filter entry: {}", part);
+
+            match part.split_once(':') {
+                None if part == "all" => filter.all = true,
+                Some(("pid", pid)) => {
+                    filter.pids.insert(pid.parse().map_err(|_| invalid())?);
+                }
+                Some(("protocol", id)) => {
+                    filter.protocols.insert(id.parse().map_err(|_| invalid())?);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+/// Runtime switch for the per call trace log
+#[derive(Default)]
+pub struct RmcTrace{
+    filter: RwLock<TraceFilter>,
+}
+
+impl RmcTrace{
+    pub fn new(filter: TraceFilter) -> Self{
+        Self{
+            filter: RwLock::new(filter),
+        }
+    }
+
+    pub fn filter(&self) -> TraceFilter{
+        self.filter.read().unwrap().clone()
+    }
+
+    pub fn set_filter(&self, filter: TraceFilter){
+        *self.filter.write().unwrap() = filter;
+    }
+
+    pub fn set_all(&self, enabled: bool){
+        self.filter.write().unwrap().all = enabled;
+    }
+
+    pub fn set_pid(&self, pid: u32, enabled: bool){
+        let mut filter = self.filter.write().unwrap();
+
+        if enabled {
+            filter.pids.insert(pid);
+        } else {
+            filter.pids.remove(&pid);
+        }
+    }
+
+    pub fn set_protocol(&self, protocol_id: u16, enabled: bool){
+        let mut filter = self.filter.write().unwrap();
+
+        if enabled {
+            filter.protocols.insert(protocol_id);
+        } else {
+            filter.protocols.remove(&protocol_id);
+        }
+    }
+
+    pub fn is_traced(&self, pid: Option<u32>, protocol_id: u16) -> bool{
+        self.filter.read().unwrap().matches(pid, protocol_id)
+    }
+}
+
+/// Everything about a single call which ends up in the trace
+pub struct CallTrace<'a>{
+    pub connection_id: u64,
+    pub pid: Option<u32>,
+    pub protocol_id: u16,
+    pub protocol_name: Option<&'static str>,
+    pub method_id: u32,
+    pub method_name: Option<&'static str>,
+    pub call_id: u32,
+    pub params: &'a [u8],
+    // what the protocol made of the parameters, see `Protocol::describe_params`
+    pub params_description: Option<String>,
+    pub result: &'a RMCResponseResult,
+    pub latency: Duration,
+}
+
+fn summarize_params(params: &[u8]) -> String{
+    let mut summary = format!("{} bytes", params.len());
+
+    if !params.is_empty() {
+        summary.push(' ');
+
+        for byte in params.iter().take(PARAM_PREVIEW) {
+            let _ = write!(summary, "{:02x}", byte);
+        }
+
+        if params.len() > PARAM_PREVIEW {
+            summary.push_str("..");
+        }
+    }
+
+    summary
+}
+
+impl CallTrace<'_>{
+    pub fn line(&self) -> String{
+        let pid = match self.pid {
+            Some(pid) => pid.to_string(),
+            None => "-".to_string(),
+        };
+
+        let result = match self.result {
+            RMCResponseResult::Success { data, .. } => format!("success ({} bytes)", data.len()),
+            RMCResponseResult::Error { error_code, .. } => format!("{:#}", error_code),
+        };
+
+        format!(
+            "connection={} pid={} protocol={}({}) method={}({}) call_id={} params=[{}] result={} latency={}us",
+            self.connection_id,
+            pid,
+            self.protocol_name.unwrap_or("unknown"),
+            self.protocol_id,
+            self.method_name.unwrap_or("unknown"),
+            self.method_id,
+            self.call_id,
+            self.params_description.clone().unwrap_or_else(|| summarize_params(self.params)),
+            result,
+            self.latency.as_micros()
+        )
+    }
+
+    pub fn log(&self){
+        info!(target: TARGET, "{}", self.line());
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::time::Duration;
+    use crate::protocols::auth::{self, AuthenticationProtocol};
+    use crate::protocols::Protocol;
+    use crate::rmc::response::{ErrorCode, RMCResponseResult};
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use crate::rmc::structures::any::{Any, AnyData};
+    use crate::rmc::structures::authentication_info::AuthenticationInfo;
+    use super::{CallTrace, RmcTrace, TraceFilter};
+
+    fn hex(data: &[u8]) -> String{
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn filter(){
+        let filter: TraceFilter = "pid:1337, protocol:11".parse().unwrap();
+
+        assert!(filter.matches(Some(1337), 10));
+        assert!(filter.matches(None, 11));
+        assert!(!filter.matches(Some(1), 10));
+        assert!("pid:abc".parse::<TraceFilter>().is_err());
+
+        let trace = RmcTrace::default();
+        assert!(!trace.is_traced(Some(1), 10));
+
+        trace.set_pid(1, true);
+        assert!(trace.is_traced(Some(1), 10));
+
+        trace.set_pid(1, false);
+        trace.set_all(true);
+        assert!(trace.is_traced(None, 10));
+    }
+
+    #[test]
+    fn trace_line(){
+        let result = RMCResponseResult::Error {
+            call_id: 5,
+            error_code: ErrorCode::Core_NotImplemented,
+        };
+
+        let trace = CallTrace{
+            connection_id: 3,
+            pid: None,
+            protocol_id: 10,
+            protocol_name: Some("Authentication"),
+            method_id: 7,
+            method_name: None,
+            call_id: 5,
+            params: &[0xAB; 33],
+            params_description: None,
+            result: &result,
+            latency: Duration::from_micros(42),
+        };
+
+        assert_eq!(
+            trace.line(),
+            format!(
+                "connection=3 pid=- protocol=Authentication(10) method=unknown(7) call_id=5 params=[33 bytes {}..] result={:#} latency=42us",
+                "ab".repeat(32),
+                ErrorCode::Core_NotImplemented
+            )
+        );
+
+        // the token of a login must not end up in the log
+        let ctx = SerializationContext::default();
+        let mut params = Vec::new();
+        ("1337".to_string(), Any{
+            name: "AuthenticationInfo".to_string(),
+            data: AnyData::AuthenticationInfo(AuthenticationInfo{
+                token: "secret".to_string(),
+                ..Default::default()
+            }),
+        }).serialize(&mut params, &ctx).unwrap();
+
+        let trace = CallTrace{
+            method_id: auth::METHOD_LOGIN_EX,
+            params: &params,
+            params_description: AuthenticationProtocol.describe_params(auth::METHOD_LOGIN_EX, &params, &ctx),
+            ..trace
+        };
+
+        assert!(trace.line().contains(" params=[username=\"1337\" AuthenticationInfo=<redacted>] "));
+        assert!(!trace.line().contains(&hex(b"secret")));
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{error, info};
+use crate::pcap::Datagram;
+use crate::pcap::writer::{Direction, PcapngWriter};
+
+/// What to capture and where to put it
+#[derive(Debug, Clone, Default)]
+pub struct CaptureConfig{
+    pub path: PathBuf,
+    // once a file gets bigger than this a new one is started next to it
+    pub max_file_size: Option<u64>,
+    // also write the payloads after decryption, these contain the plain rmc messages
+    pub include_decrypted: bool,
+    // only capture traffic of these peers, everything is captured if both of these are empty
+    pub peer_ips: Vec<Ipv4Addr>,
+    // pids are only known after the client registered with the secure server, so whatever happened
+    // before that on this connection is not captured
+    pub pids: Vec<u32>,
+}
+
+impl CaptureConfig{
+    // the first file uses the configured path, the ones after it get a number before the extension
+    fn file_path(&self, index: u32) -> PathBuf{
+        if index == 0 {
+            return self.path.clone();
+        }
+
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+
+        let name = match self.path.extension() {
+            Some(ext) => format!("{}.{}.{}", stem, index, ext.to_string_lossy()),
+            None => format!("{}.{}", stem, index),
+        };
+
+        self.path.with_file_name(name)
+    }
+}
+
+struct CaptureFile{
+    writer: PcapngWriter<BufWriter<File>>,
+    index: u32,
+    records: usize,
+}
+
+struct Capture{
+    config: CaptureConfig,
+    pid_peers: Mutex<HashSet<SocketAddrV4>>,
+    file: Mutex<CaptureFile>,
+}
+
+fn open_file(path: &Path) -> io::Result<PcapngWriter<BufWriter<File>>>{
+    info!("writing packet capture to {}", path.display());
+
+    PcapngWriter::new(BufWriter::new(File::create(path)?))
+}
+
+fn now() -> Duration{
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+impl Capture{
+    fn wants(&self, peer: SocketAddrV4) -> bool{
+        if self.config.peer_ips.is_empty() && self.config.pids.is_empty() {
+            return true;
+        }
+
+        self.config.peer_ips.contains(peer.ip()) ||
+            self.pid_peers.lock().unwrap().contains(&peer)
+    }
+
+    fn write(&self, write: impl FnOnce(&mut PcapngWriter<BufWriter<File>>) -> io::Result<()>) -> io::Result<()>{
+        let mut file = self.file.lock().unwrap();
+
+        // every file gets at least one record, even if the headers alone are over the limit
+        if file.records != 0 && self.config.max_file_size.is_some_and(|max| file.writer.written() >= max) {
+            file.writer.flush()?;
+
+            let index = file.index + 1;
+            file.writer = open_file(&self.config.file_path(index))?;
+            file.index = index;
+            file.records = 0;
+        }
+
+        write(&mut file.writer)?;
+        file.records += 1;
+
+        // flush every time so that the capture is complete even if the server crashes
+        file.writer.flush()
+    }
+}
+
+/// Opt-in recording of everything a router sends and receives, the files are written in pcapng
+/// so they can be opened in wireshark or fed into pcap_replay.
+///
+/// Writing happens inline on the caller, this is meant for debugging and not for running all the
+/// time on a busy server.
+#[derive(Default)]
+pub struct PacketCapture{
+    active: RwLock<Option<Arc<Capture>>>,
+}
+
+impl PacketCapture{
+    pub(crate) fn start(&self, config: CaptureConfig, known_pids: impl IntoIterator<Item=(u32, SocketAddrV4)>) -> io::Result<()>{
+        let writer = open_file(&config.file_path(0))?;
+
+        let pid_peers = known_pids.into_iter()
+            .filter(|(pid, _)| config.pids.contains(pid))
+            .map(|(_, addr)| addr)
+            .collect();
+
+        *self.active.write().unwrap() = Some(Arc::new(Capture{
+            config,
+            pid_peers: Mutex::new(pid_peers),
+            file: Mutex::new(CaptureFile{
+                writer,
+                index: 0,
+                records: 0,
+            }),
+        }));
+
+        Ok(())
+    }
+
+    pub fn stop(&self){
+        if self.active.write().unwrap().take().is_some() {
+            info!("packet capture stopped");
+        }
+    }
+
+    pub fn is_active(&self) -> bool{
+        self.active.read().unwrap().is_some()
+    }
+
+    fn capture(&self) -> Option<Arc<Capture>>{
+        self.active.read().unwrap().clone()
+    }
+
+    fn record(&self, capture: &Capture, write: impl FnOnce(&mut PcapngWriter<BufWriter<File>>) -> io::Result<()>){
+        if let Err(e) = capture.write(write) {
+            error!("unable to write packet capture, stopping it: {}", e);
+            self.stop();
+        }
+    }
+
+    // called whenever a pid gets linked to a connection so that the pid filter can match its address
+    pub(crate) fn pid_registered(&self, pid: u32, peer: SocketAddrV4){
+        let Some(capture) = self.capture() else {
+            return;
+        };
+
+        if capture.config.pids.contains(&pid) {
+            capture.pid_peers.lock().unwrap().insert(peer);
+        }
+    }
+
+    pub(crate) fn datagram(&self, direction: Direction, local: SocketAddrV4, peer: SocketAddrV4, data: &[u8]){
+        let Some(capture) = self.capture() else {
+            return;
+        };
+
+        if !capture.wants(peer) {
+            return;
+        }
+
+        let (source, destination) = match direction {
+            Direction::Inbound => (peer, local),
+            Direction::Outbound => (local, peer),
+        };
+
+        let datagram = Datagram{
+            timestamp: now(),
+            source,
+            destination,
+            payload: data.to_vec(),
+        };
+
+        self.record(&capture, |w| w.write_datagram(&datagram, direction));
+    }
+
+    pub(crate) fn decrypted_payload(&self, direction: Direction, peer: SocketAddrV4, pid: Option<u32>, payload: &[u8]){
+        let Some(capture) = self.capture() else {
+            return;
+        };
+
+        if !capture.config.include_decrypted || !capture.wants(peer) {
+            return;
+        }
+
+        let comment = match pid {
+            Some(pid) => format!("decrypted payload, peer {} pid {}", peer, pid),
+            None => format!("decrypted payload, peer {}", peer),
+        };
+
+        self.record(&capture, |w| w.write_annotation(now(), direction, payload, &comment));
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::fs::File;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use crate::pcap::reader::read_datagrams;
+    use crate::pcap::writer::Direction;
+    use super::{CaptureConfig, PacketCapture};
+
+    #[test]
+    fn filters_and_rotates(){
+        let dir = std::env::temp_dir().join(format!("capture-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let local = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 10000);
+        let wanted = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 1234);
+        let by_pid = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 3), 1234);
+        let other = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 4), 1234);
+
+        let config = CaptureConfig{
+            path: dir.join("capture.pcapng"),
+            max_file_size: Some(1),
+            include_decrypted: true,
+            peer_ips: vec![*wanted.ip()],
+            pids: vec![1337],
+        };
+
+        let capture = PacketCapture::default();
+        capture.start(config.clone(), []).unwrap();
+
+        capture.datagram(Direction::Inbound, local, wanted, &[1]);
+        capture.datagram(Direction::Inbound, local, other, &[2]);
+        capture.datagram(Direction::Inbound, local, by_pid, &[3]);
+        capture.pid_registered(1337, by_pid);
+        capture.decrypted_payload(Direction::Inbound, by_pid, Some(1337), &[4]);
+        capture.datagram(Direction::Outbound, local, by_pid, &[5]);
+        capture.stop();
+
+        let read: Vec<_> = (0..3)
+            .map(|i| read_datagrams(&mut File::open(config.file_path(i)).unwrap()).unwrap())
+            .collect();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // every record goes into its own file because of the tiny size limit, the decrypted
+        // payload doesn't show up as a datagram
+        assert_eq!(read[0].len(), 1);
+        assert_eq!(read[0][0].source, wanted);
+        assert_eq!(read[0][0].payload, [1]);
+        assert!(read[1].is_empty());
+        assert_eq!(read[2].len(), 1);
+        assert_eq!(read[2][0].source, local);
+        assert_eq!(read[2][0].destination, by_pid);
+    }
+}
@@ -0,0 +1,635 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::io::Cursor;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+use log::{error, trace, warn};
+use rand::random;
+use rc4::{KeyInit, Rc4, StreamCipher};
+use rc4::consts::U5;
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::sync::{oneshot, Mutex};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use crate::prudp::packet;
+use crate::prudp::packet::{PRUDPHeader, PRUDPPacket, VirtualPort};
+use crate::prudp::packet::flags::{ACK, NEED_ACK, RELIABLE};
+use crate::prudp::packet::PacketOption::{ConnectionSignature, FragmentId, InitialSequenceId, MaximumSubstreamId, SupportedFunctions};
+use crate::prudp::packet::types::{CONNECT, DATA, DISCONNECT, SYN};
+use crate::rmc::message;
+use crate::rmc::message::{MessageHeader, RMCMessage};
+use crate::rmc::response::{ErrorCode, RMCResponse, RMCResponseResult};
+use crate::rmc::structures;
+use crate::rmc::structures::{RmcSerialize, SerializationContext};
+use crate::rmc::structures::buffer::Buffer;
+
+const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_TRANSMISSIONS: usize = 10;
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Cipher = Box<dyn StreamCipher + Send + Sync>;
+
+#[derive(Error, Debug)]
+pub enum Error{
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Packet(#[from] packet::Error),
+    #[error("{0}")]
+    Message(#[from] message::Error),
+    #[error("{0}")]
+    Serialization(#[from] structures::Error),
+    #[error("the server didn't send a connection signature")]
+    MissingConnectionSignature,
+    #[error("the server didn't respond in time")]
+    Timeout,
+    #[error("the connection has been closed")]
+    Disconnected,
+    #[error("the server responded with {0:#}")]
+    ErrorResponse(ErrorCode),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// the ciphers for connections which use the default key (e.g. the auth server)
+pub fn default_ciphers() -> (Cipher, Cipher){
+    let client_encryption: Rc4<U5> = Rc4::new_from_slice("CD&ML".as_bytes()).unwrap();
+    let server_decryption: Rc4<U5> = Rc4::new_from_slice("CD&ML".as_bytes()).unwrap();
+
+    (Box::new(client_encryption), Box::new(server_decryption))
+}
+
+struct SendState{
+    sequence_id: u16,
+    encryption: Cipher,
+}
+
+struct ReceiveState{
+    sequence_id: u16,
+    queue: VecDeque<PRUDPPacket>,
+    decryption: Cipher,
+}
+
+struct Shared{
+    socket: UdpSocket,
+    server_addr: SocketAddrV4,
+    local_port: VirtualPort,
+    remote_port: VirtualPort,
+    access_key: &'static str,
+    session_id: u8,
+    // the signature we sent in our connect, the server signs everything with this
+    own_signature: [u8; 16],
+    // the signature the server sent us in its syn response, we sign everything with this
+    server_signature: [u8; 16],
+    send_state: Mutex<SendState>,
+    pending_acks: Mutex<HashMap<u16, oneshot::Sender<()>>>,
+    pending_calls: Mutex<HashMap<u32, oneshot::Sender<RMCResponseResult>>>,
+    call_id_counter: AtomicU32,
+    closed: AtomicBool,
+}
+
+/// PRUDP client connection which talks to a server the same way a console would
+///
+/// Only the parts of PRUDP which our own server implements are supported, so no fragmentation,
+/// compression or unreliable data.
+pub struct Connection{
+    shared: Arc<Shared>,
+    requests: Mutex<Receiver<RMCMessage>>,
+    receive_task: JoinHandle<()>,
+}
+
+fn base_packet(local_port: VirtualPort, remote_port: VirtualPort, packet_type: u8) -> PRUDPPacket{
+    let mut packet = PRUDPPacket{
+        header: PRUDPHeader::default(),
+        packet_signature: [0; 16],
+        payload: Vec::new(),
+        options: Vec::new(),
+    };
+
+    packet.header.source_port = local_port;
+    packet.header.destination_port = remote_port;
+    packet.header.types_and_flags.set_types(packet_type);
+
+    packet
+}
+
+fn to_bytes(packet: &PRUDPPacket) -> Vec<u8>{
+    let mut vec = Vec::new();
+    packet.write_to(&mut vec).expect("somehow failed to convert backet to bytes");
+    vec
+}
+
+// sends the packet until we get a packet of the same type with the ack flag back, used for the
+// handshake when there is no receive task yet
+async fn handshake(socket: &UdpSocket, server_addr: SocketAddrV4, packet: &PRUDPPacket, access_key: &str, expected_signature: Option<[u8; 16]>) -> Result<PRUDPPacket>{
+    let bytes = to_bytes(packet);
+    let packet_type = packet.header.types_and_flags.get_types();
+
+    for _ in 0..MAX_TRANSMISSIONS {
+        socket.send_to(&bytes, server_addr).await?;
+
+        let wait_for_response = async {
+            let mut buffer = vec![0u8; 65507];
+
+            loop {
+                let len = socket.recv(&mut buffer).await?;
+
+                let response = match PRUDPPacket::new(&mut Cursor::new(&buffer[..len])){
+                    Ok(p) => p,
+                    Err(e) => {
+                        warn!("got invalid packet during handshake: {}", e);
+                        continue;
+                    }
+                };
+
+                let flags = response.header.types_and_flags.get_flags();
+
+                if response.header.types_and_flags.get_types() != packet_type || (flags & ACK) == 0 {
+                    trace!("ignoring unexpected packet during handshake: {:?}", response.header);
+                    continue;
+                }
+
+                if response.calculate_signature_value(access_key, None, expected_signature) != response.packet_signature {
+                    warn!("got handshake response with invalid signature");
+                    continue;
+                }
+
+                return Ok::<_, Error>(response);
+            }
+        };
+
+        match timeout(RETRANSMIT_INTERVAL, wait_for_response).await {
+            Ok(result) => return result,
+            Err(_) => trace!("retransmitting handshake packet"),
+        }
+    }
+
+    Err(Error::Timeout)
+}
+
+impl Connection{
+    /// Connects to the given virtual port of a router, the ciphers are used for encrypting what
+    /// we send and decrypting what we receive respectively.
+    pub async fn connect(server_addr: SocketAddrV4, remote_port: VirtualPort, access_key: &'static str, ciphers: (Cipher, Cipher)) -> Result<Self>{
+        Self::connect_with_payload(server_addr, remote_port, access_key, ciphers, Vec::new()).await
+    }
+
+    /// Same as [`Connection::connect`] for the secure server, which wants the ticket LoginEx
+    /// returned
+    pub async fn connect_with_ticket(server_addr: SocketAddrV4, remote_port: VirtualPort, access_key: &'static str, ciphers: (Cipher, Cipher), ticket: &[u8]) -> Result<Self>{
+        let ctx = SerializationContext::default();
+        let mut payload = Vec::new();
+
+        // the check data would be encrypted with the session key of the ticket, our server
+        // doesn't look at it
+        (Buffer(ticket.to_vec()), Buffer(Vec::new())).serialize(&mut payload, &ctx)?;
+
+        Self::connect_with_payload(server_addr, remote_port, access_key, ciphers, payload).await
+    }
+
+    async fn connect_with_payload(server_addr: SocketAddrV4, remote_port: VirtualPort, access_key: &'static str, (encryption, decryption): (Cipher, Cipher), payload: Vec<u8>) -> Result<Self>{
+        let bind_ip = if server_addr.ip().is_loopback() { Ipv4Addr::LOCALHOST } else { Ipv4Addr::UNSPECIFIED };
+        let socket = UdpSocket::bind(SocketAddrV4::new(bind_ip, 0)).await?;
+
+        let local_port = VirtualPort::new(15, remote_port.get_stream_type());
+
+        let mut syn = base_packet(local_port, remote_port, SYN);
+        syn.header.types_and_flags.set_flag(NEED_ACK);
+        syn.options = vec![
+            SupportedFunctions(0x04),
+            ConnectionSignature([0; 16]),
+            MaximumSubstreamId(0),
+        ];
+        syn.set_sizes();
+        syn.calculate_and_assign_signature(access_key, None, None);
+
+        let syn_response = handshake(&socket, server_addr, &syn, access_key, None).await?;
+
+        let Some(server_signature) = syn_response.options.iter().find_map(|o| match o {
+            ConnectionSignature(s) => Some(*s),
+            _ => None
+        }) else {
+            return Err(Error::MissingConnectionSignature);
+        };
+
+        let own_signature: [u8; 16] = random();
+        let session_id: u8 = random();
+
+        let mut connect = base_packet(local_port, remote_port, CONNECT);
+        connect.header.types_and_flags.set_flag(RELIABLE | NEED_ACK);
+        connect.header.session_id = session_id;
+        connect.header.sequence_id = 1;
+        connect.options = vec![
+            SupportedFunctions(0x04),
+            ConnectionSignature(own_signature),
+            InitialSequenceId(0),
+            MaximumSubstreamId(0),
+        ];
+        connect.payload = payload;
+        connect.set_sizes();
+        connect.calculate_and_assign_signature(access_key, None, Some(server_signature));
+
+        handshake(&socket, server_addr, &connect, access_key, Some(own_signature)).await?;
+
+        let shared = Arc::new(Shared{
+            socket,
+            server_addr,
+            local_port,
+            remote_port,
+            access_key,
+            session_id,
+            own_signature,
+            server_signature,
+            send_state: Mutex::new(SendState{
+                // the connect packet used sequence id 1
+                sequence_id: 2,
+                encryption,
+            }),
+            pending_acks: Default::default(),
+            pending_calls: Default::default(),
+            call_id_counter: AtomicU32::new(1),
+            closed: AtomicBool::new(false),
+        });
+
+        let (request_sender, requests) = channel(100);
+
+        let receive_task = tokio::spawn(shared.clone().receive_loop(ReceiveState{
+            sequence_id: 1,
+            queue: VecDeque::new(),
+            decryption,
+        }, request_sender));
+
+        Ok(Self{
+            shared,
+            requests: Mutex::new(requests),
+            receive_task,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr>{
+        self.shared.socket.local_addr()
+    }
+
+    pub fn local_port(&self) -> VirtualPort{
+        self.shared.local_port
+    }
+
+    // true once the server closed the connection (or stopped responding to the socket)
+    pub fn is_closed(&self) -> bool{
+        self.shared.closed.load(Ordering::Relaxed)
+    }
+
+    /// Calls a method on the server and waits for its response
+    pub async fn call(&self, protocol_id: u16, method_id: u32, params: Vec<u8>) -> Result<RMCResponseResult>{
+        if self.is_closed() {
+            return Err(Error::Disconnected);
+        }
+
+        let call_id = self.shared.call_id_counter.fetch_add(1, Ordering::Relaxed);
+
+        let message = RMCMessage{
+            protocol_id,
+            call_id,
+            method_id,
+            rest_of_data: params,
+        };
+
+        let (sender, receiver) = oneshot::channel();
+        self.shared.pending_calls.lock().await.insert(call_id, sender);
+
+        let result = async {
+            self.shared.send_reliable(message.to_data()?).await?;
+
+            match timeout(CALL_TIMEOUT, receiver).await {
+                Ok(Ok(result)) => Ok(result),
+                Ok(Err(_)) => Err(Error::Disconnected),
+                Err(_) => Err(Error::Timeout),
+            }
+        }.await;
+
+        if result.is_err() {
+            self.shared.pending_calls.lock().await.remove(&call_id);
+        }
+
+        result
+    }
+
+    /// Same as `call` but takes care of (de)serializing the parameters and return values, error
+    /// responses get turned into `Error::ErrorResponse`.
+    pub async fn invoke<P: RmcSerialize, R: RmcSerialize>(&self, protocol_id: u16, method_id: u32, params: &P, ctx: &SerializationContext) -> Result<R>{
+        let mut data = Vec::new();
+        params.serialize(&mut data, ctx)?;
+
+        match self.call(protocol_id, method_id, data).await? {
+            RMCResponseResult::Success { data, .. } => Ok(R::deserialize(&mut Cursor::new(data), ctx)?),
+            RMCResponseResult::Error { error_code, .. } => Err(Error::ErrorResponse(error_code)),
+        }
+    }
+
+    /// Closes the connection and waits for the server to acknowledge that
+    pub async fn disconnect(self) -> Result<()>{
+        let (bytes, sequence_id, mut ack_receiver) = {
+            let mut send_state = self.shared.send_state.lock().await;
+
+            let sequence_id = send_state.sequence_id;
+            send_state.sequence_id = send_state.sequence_id.wrapping_add(1);
+
+            let mut packet = base_packet(self.shared.local_port, self.shared.remote_port, DISCONNECT);
+            packet.header.types_and_flags.set_flag(RELIABLE | NEED_ACK);
+            packet.header.sequence_id = sequence_id;
+
+            let (sender, receiver) = oneshot::channel();
+            self.shared.pending_acks.lock().await.insert(sequence_id, sender);
+
+            (self.shared.sign_and_serialize(packet), sequence_id, receiver)
+        };
+
+        self.shared.send_until_acknowledged(&bytes, sequence_id, &mut ack_receiver).await
+    }
+
+    /// Waits for the next call the server makes to us (e.g. notifications)
+    pub async fn next_request(&self) -> Option<RMCMessage>{
+        self.requests.lock().await.recv().await
+    }
+
+    /// Answers a call the server made to us
+    pub async fn respond(&self, response: RMCResponse) -> Result<()>{
+        self.shared.send_reliable(response.to_data()).await
+    }
+}
+
+impl Drop for Connection{
+    fn drop(&mut self) {
+        self.receive_task.abort();
+    }
+}
+
+impl Shared{
+    fn sign_and_serialize(&self, mut packet: PRUDPPacket) -> Vec<u8>{
+        packet.header.session_id = self.session_id;
+        packet.set_sizes();
+        packet.calculate_and_assign_signature(self.access_key, None, Some(self.server_signature));
+
+        to_bytes(&packet)
+    }
+
+    async fn send_reliable(&self, mut payload: Vec<u8>) -> Result<()>{
+        let (bytes, sequence_id, mut ack_receiver) = {
+            let mut send_state = self.send_state.lock().await;
+
+            let sequence_id = send_state.sequence_id;
+            send_state.sequence_id = send_state.sequence_id.wrapping_add(1);
+
+            // the server decrypts in sequence order so we have to encrypt in that order as well
+            send_state.encryption.apply_keystream(&mut payload);
+
+            let mut packet = base_packet(self.local_port, self.remote_port, DATA);
+            packet.header.types_and_flags.set_flag(RELIABLE | NEED_ACK);
+            packet.header.sequence_id = sequence_id;
+            packet.options.push(FragmentId(0));
+            packet.payload = payload;
+
+            let (sender, receiver) = oneshot::channel();
+            self.pending_acks.lock().await.insert(sequence_id, sender);
+
+            (self.sign_and_serialize(packet), sequence_id, receiver)
+        };
+
+        self.send_until_acknowledged(&bytes, sequence_id, &mut ack_receiver).await
+    }
+
+    async fn send_until_acknowledged(&self, bytes: &[u8], sequence_id: u16, ack_receiver: &mut oneshot::Receiver<()>) -> Result<()>{
+        for _ in 0..MAX_TRANSMISSIONS {
+            self.socket.send_to(bytes, self.server_addr).await?;
+
+            match timeout(RETRANSMIT_INTERVAL, &mut *ack_receiver).await {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(_)) => return Err(Error::Disconnected),
+                Err(_) => trace!("retransmitting packet {}", sequence_id),
+            }
+        }
+
+        self.pending_acks.lock().await.remove(&sequence_id);
+
+        Err(Error::Timeout)
+    }
+
+    async fn receive_loop(self: Arc<Self>, mut state: ReceiveState, requests: Sender<RMCMessage>){
+        let mut buffer = vec![0u8; 65507];
+
+        loop {
+            let len = match self.socket.recv(&mut buffer).await {
+                Ok(len) => len,
+                Err(e) => {
+                    error!("client connection stopped receiving: {}", e);
+                    break;
+                }
+            };
+
+            let mut stream = Cursor::new(&buffer[..len]);
+
+            while (stream.position() as usize) < len {
+                let packet = match PRUDPPacket::new(&mut stream){
+                    Ok(p) => p,
+                    Err(e) => {
+                        warn!("got invalid packet from server: {}", e);
+                        break;
+                    }
+                };
+
+                if packet.calculate_signature_value(self.access_key, None, Some(self.own_signature)) != packet.packet_signature {
+                    warn!("got packet with invalid signature from server");
+                    continue;
+                }
+
+                if !self.process_packet(&mut state, &requests, packet).await {
+                    self.close().await;
+                    return;
+                }
+            }
+        }
+
+        self.close().await;
+    }
+
+    // returns false once the connection got closed by the server
+    async fn process_packet(&self, state: &mut ReceiveState, requests: &Sender<RMCMessage>, packet: PRUDPPacket) -> bool{
+        let flags = packet.header.types_and_flags.get_flags();
+
+        if (flags & ACK) != 0 {
+            if let Some(sender) = self.pending_acks.lock().await.remove(&packet.header.sequence_id) {
+                let _ = sender.send(());
+            }
+            return true;
+        }
+
+        match packet.header.types_and_flags.get_types() {
+            DATA => {
+                if (flags & NEED_ACK) != 0 {
+                    let ack = packet.base_acknowledgement_packet();
+
+                    if let Err(e) = self.socket.send_to(&self.sign_and_serialize(ack), self.server_addr).await {
+                        error!("unable to send acknowledgement: {}", e);
+                    }
+                }
+
+                if (flags & RELIABLE) == 0 {
+                    warn!("ignoring unreliable data packet");
+                    return true;
+                }
+
+                match state.queue.binary_search_by_key(&packet.header.sequence_id, |p| p.header.sequence_id) {
+                    Ok(_) => trace!("received packet twice"),
+                    Err(_) if packet.header.sequence_id.wrapping_sub(state.sequence_id) > u16::MAX / 2 => trace!("received old packet again"),
+                    Err(position) => state.queue.insert(position, packet),
+                }
+
+                while state.queue.front().is_some_and(|p| p.header.sequence_id == state.sequence_id) {
+                    let mut packet = state.queue.pop_front().expect("we just checked that there is a packet");
+                    state.sequence_id = state.sequence_id.wrapping_add(1);
+
+                    state.decryption.apply_keystream(&mut packet.payload);
+
+                    if let Err(e) = self.process_payload(&packet.payload, requests).await {
+                        error!("unable to read rmc data from server: {}", e);
+                    }
+                }
+
+                true
+            }
+            DISCONNECT => {
+                if (flags & NEED_ACK) != 0 {
+                    let ack = packet.base_acknowledgement_packet();
+
+                    if let Err(e) = self.socket.send_to(&self.sign_and_serialize(ack), self.server_addr).await {
+                        error!("unable to acknowledge disconnect: {}", e);
+                    }
+                }
+
+                false
+            }
+            other => {
+                trace!("ignoring packet of type {} from server", other);
+                true
+            }
+        }
+    }
+
+    async fn process_payload(&self, payload: &[u8], requests: &Sender<RMCMessage>) -> Result<()>{
+        let mut cursor = Cursor::new(payload);
+
+        while (cursor.position() as usize) < payload.len() {
+            let start = cursor.position();
+            let header = MessageHeader::read(&mut cursor)?;
+            cursor.set_position(start);
+
+            if header.is_request {
+                let request = RMCMessage::new(&mut cursor)?;
+
+                if requests.send(request).await.is_err() {
+                    warn!("dropping request from server as nobody is listening");
+                }
+            } else {
+                let response = RMCResponse::new(&mut cursor)?;
+
+                let call_id = match &response.response_result {
+                    RMCResponseResult::Success { call_id, .. } |
+                    RMCResponseResult::Error { call_id, .. } => *call_id
+                };
+
+                match self.pending_calls.lock().await.remove(&call_id) {
+                    Some(sender) => { let _ = sender.send(response.response_result); }
+                    None => warn!("got response for unknown call {}", call_id),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // makes everyone waiting on this connection fail instead of waiting for their timeout
+    async fn close(&self){
+        self.closed.store(true, Ordering::Relaxed);
+        self.pending_acks.lock().await.clear();
+        self.pending_calls.lock().await.clear();
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use crate::protocols::{HandlerFuture, Protocol, RmcContext};
+    use crate::protocols::server::RMCProtocolServer;
+    use crate::prudp::packet::VirtualPort;
+    use crate::prudp::router::Router;
+    use crate::prudp::socket::Socket;
+    use crate::rmc::response::{ErrorCode, RMCResponseResult};
+    use crate::rmc::structures::SerializationContext;
+    use super::{default_ciphers, Connection, Error};
+
+    struct EchoProtocol;
+
+    impl Protocol for EchoProtocol{
+        fn id(&self) -> u16 {
+            0x1234
+        }
+
+        fn name(&self) -> &'static str {
+            "Echo"
+        }
+
+        fn handle<'a>(&'a self, ctx: &'a mut RmcContext<'_>, method_id: u32, params: &'a [u8]) -> HandlerFuture<'a> {
+            Box::pin(async move {
+                match method_id {
+                    1 => ctx.success(method_id, params.to_vec()),
+                    _ => ctx.error(ErrorCode::Core_NotImplemented)
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn calls_over_loopback(){
+        let (router, _) = Router::new(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+
+        let rmcserver = RMCProtocolServer::new(Box::new([
+            Box::new(EchoProtocol)
+        ]), Default::default(), SerializationContext::default());
+
+        let _socket = Socket::new(
+            router.clone(),
+            VirtualPort::new(1, 10),
+            "6f599f81",
+            Box::new(|_|{
+                Box::pin(async move { (true, default_ciphers(), None) })
+            }),
+            Box::new(move |packet, socket, connection|{
+                let rmcserver = rmcserver.clone();
+                Box::pin(async move { rmcserver.process_message(packet, socket, connection).await; })
+            })
+        ).await.unwrap();
+
+        let connection = Connection::connect(router.get_own_address(), VirtualPort::new(1, 10), "6f599f81", default_ciphers()).await.unwrap();
+
+        // more than one call makes sure that both rc4 streams stay in sync
+        for i in 0..3u8 {
+            let result = connection.call(0x1234, 1, vec![i; 10]).await.unwrap();
+            assert!(matches!(result, RMCResponseResult::Success { method_id: 1, data, .. } if data == [i; 10]));
+        }
+
+        let ctx = SerializationContext::default();
+
+        let echoed: String = connection.invoke(0x1234, 1, &"hello".to_string(), &ctx).await.unwrap();
+        assert_eq!(echoed, "hello");
+
+        let error = connection.invoke::<(), ()>(0x1234, 2, &(), &ctx).await;
+        assert!(matches!(error, Err(Error::ErrorResponse(ErrorCode::Core_NotImplemented))));
+
+        let error = connection.invoke::<(), ()>(0x4321, 1, &(), &ctx).await;
+        assert!(matches!(error, Err(Error::ErrorResponse(ErrorCode::Core_NotImplemented))));
+    }
+}
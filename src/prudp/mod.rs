@@ -1,5 +1,8 @@
 pub mod packet;
 pub mod router;
 pub mod socket;
+pub mod client;
+pub mod capture;
+pub mod rate_limit;
 mod auth_module;
 mod sockaddr;
\ No newline at end of file
@@ -9,7 +9,7 @@ use log::{error, trace, warn};
 use md5::{Md5, Digest};
 use thiserror::Error;
 use v_byte_macros::{EnumTryInto, SwapEndian};
-use crate::endianness::{IS_BIG_ENDIAN, IS_LITTLE_ENDIAN, ReadExtensions};
+use crate::endianness::{IS_BIG_ENDIAN, IS_LITTLE_ENDIAN, ReadExtensions, WriteExtensions};
 use crate::prudp::packet::flags::ACK;
 use crate::prudp::packet::PacketOption::{ConnectionSignature, FragmentId, InitialSequenceId, MaximumSubstreamId, SupportedFunctions};
 use crate::prudp::sockaddr::PRUDPSockAddr;
@@ -33,6 +33,19 @@ pub enum Error {
     },
 }
 
+impl Error {
+    // short name of the variant, used as a metric label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::IO(_) => "io",
+            Error::InvalidMagic(_) => "invalid_magic",
+            Error::InvalidVersion(_) => "invalid_version",
+            Error::InvalidOptionId(_) => "invalid_option_id",
+            Error::InvalidOptionSize { .. } => "invalid_option_size",
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[repr(transparent)]
@@ -92,7 +105,7 @@ impl Debug for TypesFlags {
 }
 
 #[repr(transparent)]
-#[derive(PartialEq, Eq, Copy, Clone, Pod, Zeroable, SwapEndian, Hash)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Pod, Zeroable, SwapEndian, Hash)]
 pub struct VirtualPort(pub(crate) u8);
 
 impl VirtualPort {
@@ -203,7 +216,7 @@ impl PacketOption{
         match self {
             SupportedFunctions(v) => {
                 stream.write_all(&[0, size_of_val(v) as u8])?;
-                stream.write_all(&v.to_le_bytes())?;
+                stream.write_struct(IS_BIG_ENDIAN, *v)?;
             }
             ConnectionSignature(v) => {
                 stream.write_all(&[1, size_of_val(v) as u8])?;
@@ -211,15 +224,15 @@ impl PacketOption{
             }
             FragmentId(v) => {
                 stream.write_all(&[2, size_of_val(v) as u8])?;
-                stream.write_all(&v.to_le_bytes())?;
+                stream.write_struct(IS_BIG_ENDIAN, *v)?;
             }
             InitialSequenceId(v) => {
                 stream.write_all(&[3, size_of_val(v) as u8])?;
-                stream.write_all(&v.to_le_bytes())?;
+                stream.write_struct(IS_BIG_ENDIAN, *v)?;
             }
             MaximumSubstreamId(v) => {
                 stream.write_all(&[4, size_of_val(v) as u8])?;
-                stream.write_all(&v.to_le_bytes())?;
+                stream.write_struct(IS_BIG_ENDIAN, *v)?;
             }
         }
 
@@ -400,7 +413,10 @@ impl PRUDPPacket {
         let access_key_sum: u32 = access_key_bytes.iter().map(|v| *v as u32).sum();
         let access_key_sum_bytes: [u8; 4] = access_key_sum.to_le_bytes();
 
-        let header_data: [u8; 8] = bytemuck::bytes_of(&self.header)[0x6..].try_into().unwrap();
+        let mut header_bytes = Vec::with_capacity(size_of::<PRUDPHeader>());
+        header_bytes.write_struct(IS_BIG_ENDIAN, self.header).expect("vec should always automatically be able to extend");
+
+        let header_data: [u8; 8] = header_bytes[0x6..].try_into().unwrap();
 
         let option_bytes = self.generate_options_bytes();
 
@@ -458,7 +474,7 @@ impl PRUDPPacket {
     }
 
     pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()>{
-        writer.write_all(bytemuck::bytes_of(&self.header))?;
+        writer.write_struct(IS_BIG_ENDIAN, self.header)?;
         writer.write_all(&self.packet_signature)?;
 
         for option in &self.options{
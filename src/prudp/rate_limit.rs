@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::Ipv4Addr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use log::warn;
+use serde::Deserialize;
+use crate::metrics::METRICS;
+
+// maps get cleaned up once they have this many entries, so spoofed floods can't grow them forever
+const PRUNE_THRESHOLD: usize = 4096;
+
+fn default_true() -> bool{
+    true
+}
+
+fn default_handshakes_per_second() -> f64{
+    5.0
+}
+
+fn default_handshake_burst() -> u32{
+    20
+}
+
+fn default_max_connections_per_ip() -> usize{
+    16
+}
+
+fn default_max_connections_per_socket() -> usize{
+    4096
+}
+
+fn default_rmc_calls_per_second() -> f64{
+    20.0
+}
+
+fn default_rmc_burst() -> u32{
+    100
+}
+
+fn default_auto_ban_violations() -> u32{
+    50
+}
+
+fn default_auto_ban_window() -> u64{
+    60
+}
+
+fn default_auto_ban_duration() -> u64{
+    300
+}
+
+/// How much a single client may do, everything over these limits gets dropped (or rejected with an
+/// error where there is an rmc call to reply to)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimits{
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    // SYN and CONNECT packets per source ip
+    #[serde(default = "default_handshakes_per_second")]
+    pub handshakes_per_second: f64,
+    #[serde(default = "default_handshake_burst")]
+    pub handshake_burst: u32,
+    // connections a single ip can have open on one socket
+    #[serde(default = "default_max_connections_per_ip")]
+    pub max_connections_per_ip: usize,
+    #[serde(default = "default_max_connections_per_socket")]
+    pub max_connections_per_socket: usize,
+    // rmc calls per pid, or per ip for connections which don't have a pid yet
+    #[serde(default = "default_rmc_calls_per_second")]
+    pub rmc_calls_per_second: f64,
+    #[serde(default = "default_rmc_burst")]
+    pub rmc_burst: u32,
+    // an ip which goes over any of the limits this often within the window (in seconds) gets
+    // ignored completely for the duration (in seconds)
+    #[serde(default = "default_auto_ban_violations")]
+    pub auto_ban_violations: u32,
+    #[serde(default = "default_auto_ban_window")]
+    pub auto_ban_window: u64,
+    #[serde(default = "default_auto_ban_duration")]
+    pub auto_ban_duration: u64,
+}
+
+impl Default for RateLimits{
+    fn default() -> Self {
+        Self{
+            enabled: default_true(),
+            handshakes_per_second: default_handshakes_per_second(),
+            handshake_burst: default_handshake_burst(),
+            max_connections_per_ip: default_max_connections_per_ip(),
+            max_connections_per_socket: default_max_connections_per_socket(),
+            rmc_calls_per_second: default_rmc_calls_per_second(),
+            rmc_burst: default_rmc_burst(),
+            auto_ban_violations: default_auto_ban_violations(),
+            auto_ban_window: default_auto_ban_window(),
+            auto_ban_duration: default_auto_ban_duration(),
+        }
+    }
+}
+
+struct TokenBucket{
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket{
+    fn full(burst: u32, now: Instant) -> Self{
+        Self{
+            tokens: burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, burst: u32, now: Instant){
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        self.last_refill = now;
+    }
+
+    fn take(&mut self, rate: f64, burst: u32, now: Instant) -> bool{
+        self.refill(rate, burst, now);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn is_full(&mut self, rate: f64, burst: u32, now: Instant) -> bool{
+        self.refill(rate, burst, now);
+
+        self.tokens >= burst as f64
+    }
+}
+
+fn take_token<K: Hash + Eq>(buckets: &Mutex<HashMap<K, TokenBucket>>, key: K, rate: f64, burst: u32, now: Instant) -> bool{
+    let mut buckets = buckets.lock().unwrap();
+
+    // full buckets are the same as no bucket at all
+    if buckets.len() >= PRUNE_THRESHOLD {
+        buckets.retain(|_, b| !b.is_full(rate, burst, now));
+    }
+
+    buckets.entry(key)
+        .or_insert_with(|| TokenBucket::full(burst, now))
+        .take(rate, burst, now)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum RmcKey{
+    Pid(u32),
+    Ip(Ipv4Addr),
+}
+
+struct Violations{
+    count: u32,
+    window_start: Instant,
+}
+
+/// Keeps track of what every client did recently, shared by a router and all of its sockets.
+///
+/// Does nothing until limits are set so that servers which are embedded somewhere (e.g. the tests)
+/// aren't limited.
+#[derive(Default)]
+pub struct RateLimiter{
+    limits: RwLock<Option<RateLimits>>,
+    handshakes: Mutex<HashMap<Ipv4Addr, TokenBucket>>,
+    rmc_calls: Mutex<HashMap<RmcKey, TokenBucket>>,
+    violations: Mutex<HashMap<Ipv4Addr, Violations>>,
+    bans: Mutex<HashMap<Ipv4Addr, Instant>>,
+}
+
+impl RateLimiter{
+    pub fn set_limits(&self, limits: RateLimits){
+        *self.limits.write().unwrap() = limits.enabled.then_some(limits);
+    }
+
+    fn limits(&self) -> Option<RateLimits>{
+        self.limits.read().unwrap().clone()
+    }
+
+    pub fn is_banned(&self, ip: Ipv4Addr) -> bool{
+        self.is_banned_at(ip, Instant::now())
+    }
+
+    fn is_banned_at(&self, ip: Ipv4Addr, now: Instant) -> bool{
+        let mut bans = self.bans.lock().unwrap();
+
+        match bans.get(&ip) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                bans.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // every ip which is banned right now and how long the ban still lasts
+    pub fn bans(&self) -> Vec<(Ipv4Addr, Duration)>{
+        let now = Instant::now();
+
+        let mut bans: Vec<_> = self.bans.lock().unwrap().iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(ip, until)| (*ip, *until - now))
+            .collect();
+
+        bans.sort();
+        bans
+    }
+
+    // returns false if the ip wasn't banned
+    pub fn unban(&self, ip: Ipv4Addr) -> bool{
+        self.violations.lock().unwrap().remove(&ip);
+        self.bans.lock().unwrap().remove(&ip).is_some()
+    }
+
+    fn violation(&self, limits: &RateLimits, ip: Ipv4Addr, limit: &str, now: Instant){
+        METRICS.rate_limited.with(&[limit]).inc();
+
+        let window = Duration::from_secs(limits.auto_ban_window);
+
+        let mut violations = self.violations.lock().unwrap();
+
+        if violations.len() >= PRUNE_THRESHOLD {
+            violations.retain(|_, v| now.saturating_duration_since(v.window_start) < window);
+        }
+
+        let entry = violations.entry(ip).or_insert(Violations{
+            count: 0,
+            window_start: now,
+        });
+
+        if now.saturating_duration_since(entry.window_start) >= window {
+            entry.count = 0;
+            entry.window_start = now;
+        }
+
+        entry.count += 1;
+
+        if entry.count < limits.auto_ban_violations {
+            return;
+        }
+
+        violations.remove(&ip);
+        drop(violations);
+
+        warn!("{} went over the rate limits {} times, ignoring it for {}s", ip, limits.auto_ban_violations, limits.auto_ban_duration);
+
+        self.bans.lock().unwrap().insert(ip, now + Duration::from_secs(limits.auto_ban_duration));
+    }
+
+    // for SYN and CONNECT packets
+    pub fn allow_handshake(&self, ip: Ipv4Addr) -> bool{
+        self.allow_handshake_at(ip, Instant::now())
+    }
+
+    fn allow_handshake_at(&self, ip: Ipv4Addr, now: Instant) -> bool{
+        let Some(limits) = self.limits() else {
+            return true;
+        };
+
+        if take_token(&self.handshakes, ip, limits.handshakes_per_second, limits.handshake_burst, now) {
+            return true;
+        }
+
+        self.violation(&limits, ip, "handshake", now);
+        false
+    }
+
+    // called before a new connection is created with the amount of connections there already are
+    pub fn allow_connection(&self, ip: Ipv4Addr, ip_connections: usize, socket_connections: usize) -> bool{
+        let Some(limits) = self.limits() else {
+            return true;
+        };
+
+        // a full socket isn't the fault of this ip, so it doesn't count as violation
+        if socket_connections >= limits.max_connections_per_socket {
+            METRICS.rate_limited.with(&["connections_per_socket"]).inc();
+            return false;
+        }
+
+        if ip_connections >= limits.max_connections_per_ip {
+            self.violation(&limits, ip, "connections_per_ip", Instant::now());
+            return false;
+        }
+
+        true
+    }
+
+    pub fn allow_rmc_call(&self, pid: Option<u32>, ip: Ipv4Addr) -> bool{
+        self.allow_rmc_call_at(pid, ip, Instant::now())
+    }
+
+    fn allow_rmc_call_at(&self, pid: Option<u32>, ip: Ipv4Addr, now: Instant) -> bool{
+        let Some(limits) = self.limits() else {
+            return true;
+        };
+
+        let key = match pid {
+            Some(pid) => RmcKey::Pid(pid),
+            None => RmcKey::Ip(ip),
+        };
+
+        if take_token(&self.rmc_calls, key, limits.rmc_calls_per_second, limits.rmc_burst, now) {
+            return true;
+        }
+
+        self.violation(&limits, ip, "rmc", now);
+        false
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+    use super::{RateLimiter, RateLimits};
+
+    #[test]
+    fn buckets_and_auto_bans(){
+        let limiter = RateLimiter::default();
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let other = Ipv4Addr::new(10, 0, 0, 2);
+        let now = Instant::now();
+
+        // nothing is limited without limits
+        assert!((0..100).all(|_| limiter.allow_handshake_at(ip, now)));
+
+        limiter.set_limits(RateLimits{
+            handshakes_per_second: 1.0,
+            handshake_burst: 2,
+            max_connections_per_ip: 2,
+            rmc_calls_per_second: 1.0,
+            rmc_burst: 1,
+            auto_ban_violations: 3,
+            ..Default::default()
+        });
+
+        assert!(limiter.allow_handshake_at(ip, now));
+        assert!(limiter.allow_handshake_at(ip, now));
+        assert!(!limiter.allow_handshake_at(ip, now));
+        assert!(limiter.allow_handshake_at(other, now));
+
+        // refilled
+        assert!(limiter.allow_handshake_at(ip, now + Duration::from_secs(1)));
+
+        assert!(limiter.allow_connection(ip, 1, 100));
+        assert!(!limiter.allow_connection(ip, 2, 100));
+        assert!(!limiter.allow_connection(other, 0, 4096));
+
+        // pids get their own bucket no matter where they come from
+        assert!(limiter.allow_rmc_call_at(Some(1), ip, now));
+        assert!(limiter.allow_rmc_call_at(None, ip, now));
+        assert!(!limiter.is_banned_at(ip, now));
+        assert!(!limiter.allow_rmc_call_at(Some(1), other, now));
+
+        // the third violation of ip
+        assert!(!limiter.allow_rmc_call_at(None, ip, now));
+        assert!(limiter.is_banned_at(ip, now));
+        assert!(!limiter.is_banned_at(ip, now + Duration::from_secs(301)));
+
+        limiter.set_limits(RateLimits{
+            auto_ban_violations: 1,
+            ..Default::default()
+        });
+
+        assert!(!limiter.allow_connection(other, 16, 0));
+        assert_eq!(limiter.bans().len(), 1);
+        assert!(limiter.unban(other));
+        assert!(limiter.bans().is_empty());
+    }
+}
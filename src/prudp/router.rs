@@ -1,4 +1,4 @@
-use std::{env, io, thread};
+use std::{io, thread};
 use std::cell::OnceCell;
 use std::io::Cursor;
 use std::marker::PhantomData;
@@ -9,27 +9,32 @@ use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, OnceLock};
 use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::task::JoinHandle;
-use once_cell::sync::Lazy;
 use log::{error, info, trace, warn};
 use thiserror::Error;
 use tokio::io::Join;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{watch, RwLock};
+use crate::metrics::{Counter, METRICS};
+use crate::pcap::writer::Direction;
 use crate::prudp::auth_module::AuthModule;
+use crate::prudp::capture::{CaptureConfig, PacketCapture};
+use crate::prudp::rate_limit::RateLimiter;
 use crate::prudp::socket::{Socket, SocketData};
 use crate::prudp::packet::{PRUDPPacket, VirtualPort};
+use crate::prudp::packet::flags::ACK;
+use crate::prudp::packet::types::{CONNECT, SYN};
 use crate::prudp::router::Error::VirtualPortTaken;
 use crate::prudp::sockaddr::PRUDPSockAddr;
 
-static SERVER_DATAGRAMS: Lazy<u8> = Lazy::new(||{
-    env::var("SERVER_DATAGRAM_COUNT").ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(1)
-});
-
 pub struct Router {
     endpoints: RwLock<[Option<Arc<SocketData>>; 16]>,
     running: AtomicBool,
     socket: Arc<UdpSocket>,
+    capture: Arc<PacketCapture>,
+    rate_limiter: Arc<RateLimiter>,
+    stop: watch::Sender<bool>,
+    datagrams_received: Arc<Counter>,
+    datagrams_sent: Arc<Counter>,
     //pub auth_module: Arc<dyn AuthModule>
     _no_outside_construction: PhantomData<()>
 }
@@ -45,12 +50,18 @@ impl Router {
 
     }
     async fn process_prudp_packets<'a>(self: Arc<Self>, socket: Arc<UdpSocket>, addr: SocketAddrV4, udp_message: Vec<u8>){
+        if self.rate_limiter.is_banned(*addr.ip()) {
+            trace!("ignoring datagram from banned address {}", addr);
+            return;
+        }
+
         let mut stream = Cursor::new(&udp_message);
 
         while stream.position() as usize != udp_message.len() {
             let packet = match PRUDPPacket::new(&mut stream){
                 Ok(p) => p,
                 Err(e) => {
+                    METRICS.packet_parse_errors.with(&[e.kind()]).inc();
                     error!("Somebody({}) is fucking with the servers or their connection is bad (reason: {})", addr, e);
                     break;
                 },
@@ -58,6 +69,14 @@ impl Router {
 
             trace!("got valid prudp packet from someone({}): \n{:?}", addr, packet);
 
+            let packet_type = packet.header.types_and_flags.get_types();
+            let is_ack = (packet.header.types_and_flags.get_flags() & ACK) != 0;
+
+            if (packet_type == SYN || packet_type == CONNECT) && !is_ack && !self.rate_limiter.allow_handshake(*addr.ip()) {
+                trace!("too many handshakes from {}, dropping packet", addr);
+                continue;
+            }
+
             let connection = packet.source_sockaddr(addr);
 
             let endpoints = self.endpoints.read().await;
@@ -81,12 +100,18 @@ impl Router {
     async fn server_thread_send_entry(self: Arc<Self>, socket: Arc<UdpSocket>){
         info!("starting datagram thread");
 
+        let mut stop = self.stop.subscribe();
+
         while self.running.load(Ordering::Relaxed) {
             // yes we actually allow the max udp to be read lol
             let mut msg_buffer = vec![0u8; 65507];
 
-            let (len, addr) = socket.recv_from(&mut msg_buffer)
-                .await.expect("Datagram thread crashed due to unexpected error from recv_from");
+            let (len, addr) = tokio::select! {
+                result = socket.recv_from(&mut msg_buffer) => {
+                    result.expect("Datagram thread crashed due to unexpected error from recv_from")
+                }
+                _ = stop.wait_for(|stop| *stop) => break,
+            };
 
             let V4(addr) = addr else {
                 error!("somehow got ipv6 packet...? ignoring");
@@ -95,19 +120,39 @@ impl Router {
 
             let current_msg = &msg_buffer[0..len];
 
+            self.datagrams_received.inc();
+
+            self.capture.datagram(Direction::Inbound, self.get_own_address(), addr, current_msg);
+
             tokio::spawn(self.clone().process_prudp_packets(socket.clone(), addr, current_msg.to_vec()));
         }
+
+        info!("datagram thread stopped");
     }
     
     pub async fn new(addr: SocketAddrV4) -> io::Result<(Arc<Self>, JoinHandle<()>)>{
+        Self::with_capture(addr, Default::default()).await
+    }
+
+    // routers which share a capture write to the same file, this way a pid registered on one of
+    // them also gets captured on the others
+    pub async fn with_capture(addr: SocketAddrV4, capture: Arc<PacketCapture>) -> io::Result<(Arc<Self>, JoinHandle<()>)>{
         trace!("starting router on {}", addr);
 
         let socket = Arc::new(UdpSocket::bind(addr).await?);
 
+        // the actual address in case an ephemeral port was requested
+        let address = socket.local_addr()?.to_string();
+
         let own_impl = Router {
             endpoints: Default::default(),
             running: AtomicBool::new(true),
             socket: socket.clone(),
+            capture,
+            rate_limiter: Default::default(),
+            stop: watch::channel(false).0,
+            datagrams_received: METRICS.datagrams_received.with(&[&address]),
+            datagrams_sent: METRICS.datagrams_sent.with(&[&address]),
             _no_outside_construction: Default::default()
         };
 
@@ -148,6 +193,10 @@ impl Router {
         self.endpoints.write().await[virtual_port.get_port_number() as usize] = None;
     }
 
+    pub async fn sockets(&self) -> Vec<Arc<SocketData>>{
+        self.endpoints.read().await.iter().flatten().cloned().collect()
+    }
+
     // returns Some(()) i
     pub(crate) async fn add_socket(&self, socket: Arc<SocketData>) -> Result<(), Error>{
         let mut endpoints = self.endpoints.write().await;
@@ -163,6 +212,75 @@ impl Router {
         Ok(())
     }
 
+    // Stops accepting connections, disconnects everyone once their current call is done and then
+    // stops receiving. Returns false if not every connection could be disconnected in time, the
+    // router is stopped either way. The capture keeps running as it may be shared with other
+    // routers.
+    pub async fn shutdown(&self, timeout: Duration) -> bool{
+        info!("shutting down router on {}", self.get_own_address());
+
+        let endpoints = self.sockets().await;
+
+        for endpoint in &endpoints {
+            endpoint.stop_accepting();
+        }
+
+        let drain = async {
+            let mut disconnected = 0;
+
+            for endpoint in &endpoints {
+                disconnected += endpoint.disconnect_all().await;
+            }
+
+            disconnected
+        };
+
+        let drained = match tokio::time::timeout(timeout, drain).await {
+            Ok(disconnected) => {
+                info!("disconnected {} connections", disconnected);
+                true
+            }
+            Err(_) => {
+                warn!("not all connections could be disconnected within {:?}", timeout);
+                false
+            }
+        };
+
+        self.running.store(false, Ordering::Relaxed);
+        self.stop.send_replace(true);
+
+        drained
+    }
+
+    pub fn is_running(&self) -> bool{
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn datagrams_sent(&self) -> &Arc<Counter>{
+        &self.datagrams_sent
+    }
+
+    // limits are shared by every socket of the router
+    pub fn rate_limiter(&self) -> &Arc<RateLimiter>{
+        &self.rate_limiter
+    }
+
+    pub fn capture(&self) -> &Arc<PacketCapture>{
+        &self.capture
+    }
+
+    // starts writing everything this router sends and receives to disk, replaces the capture which
+    // is currently running if there is one
+    pub async fn start_capture(&self, config: CaptureConfig) -> io::Result<()>{
+        let mut known_pids = Vec::new();
+
+        for endpoint in self.sockets().await {
+            known_pids.extend(endpoint.pid_addresses().await);
+        }
+
+        self.capture.start(config, known_pids)
+    }
+
     pub fn get_own_address(&self) -> SocketAddrV4{
         match self.socket.local_addr().expect("unable to get socket address"){
             SocketAddr::V4(v4) => v4,
@@ -1,11 +1,16 @@
 use std::array;
 use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::Entry;
 use std::future::Future;
+use std::io;
 use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddrV4};
 use std::ops::Deref;
 use std::pin::Pin;
 use tokio::net::UdpSocket;
-use std::sync::{Arc};
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use tokio::sync::{Mutex, MutexGuard, RwLock};
 use hmac::{Hmac, Mac};
 use log::{error, info, trace, warn};
@@ -18,15 +23,25 @@ use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::task::JoinHandle;
 use tokio_stream::wrappers::ReceiverStream;
-use crate::prudp::packet::{flags, PacketOption, PRUDPPacket, types, VirtualPort};
+use crate::metrics::{Counter, METRICS};
+use crate::pcap::writer::Direction;
+use crate::prudp::capture::PacketCapture;
+use crate::prudp::rate_limit::RateLimiter;
+use crate::prudp::packet::{flags, PacketOption, PRUDPHeader, PRUDPPacket, types, VirtualPort};
 use crate::prudp::packet::flags::{ACK, HAS_SIZE, MULTI_ACK, NEED_ACK, RELIABLE};
 use crate::prudp::packet::PacketOption::{ConnectionSignature, MaximumSubstreamId, SupportedFunctions};
-use crate::prudp::packet::types::{CONNECT, DATA, PING, SYN};
+use crate::prudp::packet::types::{CONNECT, DATA, DISCONNECT, PING, SYN};
 use crate::prudp::router::{Error, Router};
 use crate::prudp::sockaddr::PRUDPSockAddr;
 use rc4::KeyInit;
 
 
+// connections which didn't finish the handshake in time get dropped, established ones get more
+// time as clients ping every few seconds while connected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const EVICTION_INTERVAL: Duration = Duration::from_secs(5);
+
 // due to the way this is designed crashing the router thread causes deadlock, sorry ;-;
 // (maybe i will fix that some day)
 
@@ -37,16 +52,35 @@ pub struct Socket {
 }
 
 
-type OnConnectHandlerFn = Box<dyn Fn(PRUDPPacket) -> Pin<Box<dyn Future<Output=(bool, (Box<dyn StreamCipher + Send + Sync>, Box<dyn StreamCipher + Send + Sync>))> + Send + Sync>> + Send + Sync>;
-type OnDataHandlerFn = Box<dyn for<'a> Fn(PRUDPPacket, Arc<SocketData>, &'a mut MutexGuard<'_, ConnectionData>) -> Pin<Box<dyn Future<Output=()> + 'a + Send + Sync>> + Send + Sync>;
+// returns whether the connection is accepted, its ciphers and the pid of the ticket it connected with
+type OnConnectHandlerFn = Box<dyn Fn(PRUDPPacket) -> Pin<Box<dyn Future<Output=(bool, (Box<dyn StreamCipher + Send + Sync>, Box<dyn StreamCipher + Send + Sync>), Option<u32>)> + Send + Sync>> + Send + Sync>;
+type OnDataHandlerFn = Box<dyn for<'a> Fn(PRUDPPacket, Arc<SocketData>, &'a mut MutexGuard<'_, ConnectionData>) -> Pin<Box<dyn Future<Output=()> + 'a + Send>> + Send + Sync>;
 
 pub struct SocketData {
     virtual_port: VirtualPort,
     pub socket: Arc<UdpSocket>,
+    local_address: SocketAddrV4,
+    capture: Arc<PacketCapture>,
+    rate_limiter: Arc<RateLimiter>,
     pub access_key: &'static str,
     connections: RwLock<HashMap<PRUDPSockAddr, Arc<Mutex<ConnectionData>>>>,
+    pid_connections: RwLock<HashMap<u32, PRUDPSockAddr>>,
+    // how many connections each ip has, only changed while holding the write lock of `connections`
+    ip_connections: std::sync::Mutex<HashMap<Ipv4Addr, usize>>,
     on_connect_handler: OnConnectHandlerFn,
     on_data_handler: OnDataHandlerFn,
+    accepting: AtomicBool,
+    datagrams_sent: Arc<Counter>,
+    retransmissions_received: Arc<Counter>,
+}
+
+/// Snapshot of the connections of a socket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketStats {
+    pub connections: usize,
+    pub active_connections: usize,
+    // payload bytes of reliable packets which arrived out of order and wait for the ones before them
+    pub reassembly_bytes: usize,
 }
 
 pub struct ActiveConnectionData {
@@ -57,6 +91,11 @@ pub struct ActiveConnectionData {
     server_encryption: Box<dyn StreamCipher + Send + Sync>,
     client_decryption: Box<dyn StreamCipher + Send + Sync>,
     pub server_session_id: u8,
+    // the pid of the ticket the client connected with, it becomes `pid` once the client registers
+    pub ticket_pid: Option<u32>,
+    pub pid: Option<u32>,
+    pub station_urls: Vec<String>,
+    pub server_call_id_counter: u32,
 }
 
 
@@ -66,6 +105,10 @@ pub struct ConnectionData {
     pub signature: [u8; 16],
     pub server_signature: [u8; 16],
     pub active_connection_data: Option<ActiveConnectionData>,
+    pub connected_at: Instant,
+    pub last_activity: Instant,
+    pub packets_received: u64,
+    pub packets_sent: u64,
 }
 
 
@@ -85,6 +128,8 @@ impl Socket {
 
         router.add_socket(socket_data.clone()).await?;
 
+        tokio::spawn(evict_idle_connections(Arc::downgrade(&socket_data)));
+
         Ok(Self {
             socket_data,
             router,
@@ -108,6 +153,12 @@ impl Drop for Socket {
     }
 }
 
+impl Socket {
+    pub fn get_socket_data(&self) -> Arc<SocketData> {
+        self.socket_data.clone()
+    }
+}
+
 impl Deref for Socket {
     type Target = SocketData;
     fn deref(&self) -> &Self::Target {
@@ -125,11 +176,22 @@ impl SocketData {
     ) -> Self {
         SocketData {
             socket: router.get_udp_socket(),
+            local_address: router.get_own_address(),
+            capture: router.capture().clone(),
+            rate_limiter: router.rate_limiter().clone(),
             virtual_port: port,
             connections: Default::default(),
+            pid_connections: Default::default(),
+            ip_connections: Default::default(),
             access_key,
             on_connect_handler,
             on_data_handler,
+            accepting: AtomicBool::new(true),
+            datagrams_sent: router.datagrams_sent().clone(),
+            retransmissions_received: METRICS.retransmissions_received.with(&[
+                &router.get_own_address().to_string(),
+                &port.get_port_number().to_string()
+            ]),
         }
     }
 
@@ -137,15 +199,228 @@ impl SocketData {
         self.virtual_port
     }
 
+    // links the connection to the given pid so that it can be found by other players (e.g. for
+    // sending notifications), the connection has to be active for this
+    pub async fn register_pid(&self, connection: &mut ConnectionData, pid: u32) {
+        let Some(active_connection) = connection.active_connection_data.as_mut() else {
+            error!("tried to register pid {} on an inactive connection", pid);
+            return;
+        };
+
+        active_connection.pid = Some(pid);
+
+        self.pid_connections.write().await.insert(pid, connection.sock_addr);
+
+        self.capture.pid_registered(pid, connection.sock_addr.regular_socket_addr);
+    }
+
+    pub(crate) async fn pid_addresses(&self) -> Vec<(u32, SocketAddrV4)> {
+        self.pid_connections.read().await.iter()
+            .map(|(pid, addr)| (*pid, addr.regular_socket_addr))
+            .collect()
+    }
+
+    pub fn rate_limiter(&self) -> &Arc<RateLimiter> {
+        &self.rate_limiter
+    }
+
+    // everything going out has to go through here so that it ends up in packet captures
+    pub async fn send_datagram(&self, data: &[u8], addr: SocketAddrV4) -> io::Result<usize> {
+        self.capture.datagram(Direction::Outbound, self.local_address, addr, data);
+        self.datagrams_sent.inc();
+
+        self.socket.send_to(data, addr).await
+    }
+
+    // Dont call this while holding the lock of the connection you are looking for, that will
+    // deadlock as soon as you try to lock the returned connection
+    pub async fn find_connection_by_pid(&self, pid: u32) -> Option<Arc<Mutex<ConnectionData>>> {
+        let sock_addr = *self.pid_connections.read().await.get(&pid)?;
+
+        self.connections.read().await.get(&sock_addr).cloned()
+    }
+
+    pub async fn connections(&self) -> Vec<Arc<Mutex<ConnectionData>>> {
+        self.connections.read().await.values().cloned().collect()
+    }
+
+    // this locks every connection so it must not be called while holding the lock of one of them
+    pub async fn stats(&self) -> SocketStats {
+        let mut stats = SocketStats::default();
+
+        for connection in self.connections().await {
+            let connection = connection.lock().await;
+
+            stats.connections += 1;
+
+            if let Some(active_connection) = connection.active_connection_data.as_ref() {
+                stats.active_connections += 1;
+                stats.reassembly_bytes += active_connection.reliable_client_queue.iter()
+                    .map(|p| p.payload.len())
+                    .sum::<usize>();
+            }
+        }
+
+        stats
+    }
+
+    // new connections get ignored from now on, existing ones keep working
+    pub fn stop_accepting(&self) {
+        self.accepting.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::Relaxed)
+    }
+
+    // removes a connection which is not active anymore from the socket
+    async fn forget_connection(&self, sock_addr: PRUDPSockAddr, pid: Option<u32>) {
+        if let Some(pid) = pid {
+            let mut pid_connections = self.pid_connections.write().await;
+
+            if pid_connections.get(&pid) == Some(&sock_addr) {
+                pid_connections.remove(&pid);
+            }
+        }
+
+        let mut connections = self.connections.write().await;
+
+        if connections.remove(&sock_addr).is_some() {
+            self.release_ip(*sock_addr.regular_socket_addr.ip());
+        }
+    }
+
+    // has to be called while holding the write lock of the connections
+    fn release_ip(&self, ip: Ipv4Addr) {
+        let mut ip_connections = self.ip_connections.lock().unwrap();
+
+        if let Entry::Occupied(mut count) = ip_connections.entry(ip) {
+            *count.get_mut() -= 1;
+
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+
+    // Forgets every connection which hasn't sent anything for too long, established ones get a
+    // disconnect first. Returns how many were evicted.
+    pub(crate) async fn evict_idle_at(&self, now: Instant) -> usize {
+        let mut evicted = 0;
+
+        for connection in self.connections().await {
+            // a locked connection is being handled right now, so it isn't idle
+            let Ok(mut connection) = connection.try_lock() else {
+                continue;
+            };
+
+            let timeout = match connection.active_connection_data {
+                Some(_) => IDLE_TIMEOUT,
+                None => HANDSHAKE_TIMEOUT,
+            };
+
+            if now.saturating_duration_since(connection.last_activity) < timeout {
+                continue;
+            }
+
+            trace!("evicting idle connection from {}", connection.sock_addr.regular_socket_addr);
+
+            let pid = connection.active_connection_data.as_ref().and_then(|a| a.pid);
+            let sock_addr = connection.sock_addr;
+
+            connection.send_disconnect(self).await;
+            drop(connection);
+
+            self.forget_connection(sock_addr, pid).await;
+
+            evicted += 1;
+        }
+
+        evicted
+    }
+
+    // Disconnects every live connection the filter returns true for and returns how many that
+    // were, e.g. for kicking players. This locks every connection so it must not be called while
+    // holding the lock of one of them.
+    pub async fn disconnect_where(&self, filter: impl Fn(&ConnectionData) -> bool) -> usize {
+        let mut disconnected = 0;
+
+        for connection in self.connections().await {
+            let mut connection = connection.lock().await;
+
+            if connection.active_connection_data.is_none() || !filter(&connection) {
+                continue;
+            }
+
+            let pid = connection.active_connection_data.as_ref().and_then(|a| a.pid);
+            let sock_addr = connection.sock_addr;
+
+            connection.send_disconnect(self).await;
+            drop(connection);
+
+            self.forget_connection(sock_addr, pid).await;
+
+            disconnected += 1;
+        }
+
+        disconnected
+    }
+
+    // Sends a disconnect to every live connection and forgets about all of them, returns how many
+    // were live. Calls which are being handled right now get to finish first as we need the lock
+    // of their connection.
+    pub async fn disconnect_all(&self) -> usize {
+        let mut connection_list = self.connections.write().await;
+        let connections: Vec<_> = connection_list.drain().collect();
+        self.ip_connections.lock().unwrap().clear();
+        drop(connection_list);
+
+        self.pid_connections.write().await.clear();
+
+        let mut disconnected = 0;
+
+        for (_, connection) in connections {
+            let mut connection = connection.lock().await;
+
+            if connection.active_connection_data.is_some() {
+                connection.send_disconnect(self).await;
+                disconnected += 1;
+            }
+        }
+
+        disconnected
+    }
+
     pub async fn process_packet(self: &Arc<Self>, client_address: PRUDPSockAddr, packet: &PRUDPPacket) {
         let conn = self.connections.read().await;
 
         if !conn.contains_key(&client_address) {
             drop(conn);
 
+            // only a syn may start a connection, otherwise every spoofed packet would take up a
+            // slot in the connection list
+            if packet.header.types_and_flags.get_types() != SYN {
+                trace!("ignoring packet from unknown connection {}", client_address.regular_socket_addr);
+                return;
+            }
+
+            if !self.is_accepting() {
+                trace!("ignoring packet from {} as the socket doesn't accept connections anymore", client_address.regular_socket_addr);
+                return;
+            }
+
             let mut conn = self.connections.write().await;
             //only insert if we STILL dont have the connection preventing double insertion
             if !conn.contains_key(&client_address) {
+                let ip = *client_address.regular_socket_addr.ip();
+                let mut ip_connections = self.ip_connections.lock().unwrap();
+                let ip_connection_count = ip_connections.get(&ip).copied().unwrap_or(0);
+
+                if !self.rate_limiter.allow_connection(ip, ip_connection_count, conn.len()) {
+                    trace!("too many connections, ignoring packet from {}", client_address.regular_socket_addr);
+                    return;
+                }
+
                 conn.insert(client_address, Arc::new(Mutex::new(ConnectionData {
                     sock_addr: client_address,
                     id: random(),
@@ -153,7 +428,13 @@ impl SocketData {
                     server_signature: [0; 16],
 
                     active_connection_data: None,
+                    connected_at: Instant::now(),
+                    last_activity: Instant::now(),
+                    packets_received: 0,
+                    packets_sent: 0,
                 })));
+
+                *ip_connections.entry(ip).or_default() += 1;
             }
             drop(conn);
         } else {
@@ -174,6 +455,9 @@ impl SocketData {
 
         let mut connection = conn.lock().await;
 
+        connection.last_activity = Instant::now();
+        connection.packets_received += 1;
+
         if (packet.header.types_and_flags.get_flags() & ACK) != 0 {
             info!("acknowledgement recieved");
             return;
@@ -219,7 +503,7 @@ impl SocketData {
 
                 response_packet.write_to(&mut vec).expect("somehow failed to convert backet to bytes");
 
-                self.socket.send_to(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
+                self.send_datagram(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
             }
             CONNECT => {
                 info!("got connect");
@@ -270,11 +554,11 @@ impl SocketData {
                 let mut vec = Vec::new();
                 response_packet.write_to(&mut vec).expect("somehow failed to convert backet to bytes");
 
-                self.socket.send_to(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
+                self.send_datagram(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
 
                 let (send, recv) = channel(100);
 
-                let (accepted, (client_decryption, server_encryption))
+                let (accepted, (client_decryption, server_encryption), ticket_pid)
                     = (self.on_connect_handler)(packet.clone()).await;
 
                 if !accepted {
@@ -290,6 +574,10 @@ impl SocketData {
                     reliable_client_counter: 2,
                     reliable_server_counter: 1,
                     server_session_id: packet.header.session_id,
+                    ticket_pid,
+                    pid: None,
+                    station_urls: Vec::new(),
+                    server_call_id_counter: 1,
                 });
             }
             DATA => {
@@ -302,7 +590,10 @@ impl SocketData {
                     info!("ctr: {}, packet seq: {}", active_connection.reliable_client_counter, packet.header.sequence_id);
 
                     match active_connection.reliable_client_queue.binary_search_by_key(&packet.header.sequence_id, |p| p.header.sequence_id) {
-                        Ok(_) => warn!("recieved packet twice"),
+                        Ok(_) => {
+                            warn!("recieved packet twice");
+                            self.retransmissions_received.inc();
+                        }
                         Err(position) => active_connection.reliable_client_queue.insert(position, packet.clone()),
                     }
 
@@ -317,7 +608,7 @@ impl SocketData {
                         let mut vec = Vec::new();
                         ack.write_to(&mut vec).expect("somehow failed to convert backet to bytes");
 
-                        self.socket.send_to(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
+                        self.send_datagram(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
                     }
 
                     while let Some(mut packet) = {
@@ -341,6 +632,8 @@ impl SocketData {
 
                         active_connection.client_decryption.apply_keystream(&mut packet.payload);
 
+                        self.capture.decrypted_payload(Direction::Inbound, client_address.regular_socket_addr, active_connection.pid, &packet.payload);
+
                         // we cant divert this off to another thread we HAVE to process it now to keep order
 
                         (self.on_data_handler)(packet, self.clone(), &mut connection).await;
@@ -374,8 +667,34 @@ impl SocketData {
                     let mut vec = Vec::new();
                     ack.write_to(&mut vec).expect("somehow failed to convert backet to bytes");
 
-                    self.socket.send_to(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
+                    self.send_datagram(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
+                }
+            }
+
+            DISCONNECT => {
+                info!("got disconnect");
+
+                if (packet.header.types_and_flags.get_flags() & NEED_ACK) != 0 {
+                    let mut ack = packet.base_acknowledgement_packet();
+                    ack.header.session_id = connection.active_connection_data.as_ref().map(|a| a.server_session_id).unwrap_or(0);
+
+                    ack.set_sizes();
+                    ack.calculate_and_assign_signature(self.access_key, None, Some(connection.server_signature));
+
+                    let mut vec = Vec::new();
+                    ack.write_to(&mut vec).expect("somehow failed to convert backet to bytes");
+
+                    self.send_datagram(&vec, client_address.regular_socket_addr).await.expect("failed to send data back");
                 }
+
+                let pid = connection.active_connection_data.as_ref().and_then(|a| a.pid);
+                connection.active_connection_data = None;
+
+                // we are still holding the lock of the connection, so nobody can have gotten it
+                // through the pid index in the meantime
+                drop(connection);
+
+                self.forget_connection(client_address, pid).await;
             }
 
             _ => unimplemented!("unimplemented packet type: {}", packet.header.types_and_flags.get_types())
@@ -383,7 +702,55 @@ impl SocketData {
     }
 }
 
+// runs for as long as the socket exists
+async fn evict_idle_connections(socket: Weak<SocketData>) {
+    let mut interval = tokio::time::interval(EVICTION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let Some(socket) = socket.upgrade() else {
+            break;
+        };
+
+        let evicted = socket.evict_idle_at(Instant::now()).await;
+
+        if evicted != 0 {
+            info!("evicted {} idle connections on virtual port {:?}", evicted, socket.virtual_port);
+        }
+    }
+}
+
 impl ConnectionData{
+    // the registered pid or, for clients which haven't registered yet, the one of their ticket
+    pub fn authenticated_pid(&self) -> Option<u32>{
+        let active_connection = self.active_connection_data.as_ref()?;
+
+        active_connection.pid.or(active_connection.ticket_pid)
+    }
+
+    // tells the client that we closed the connection, the connection is inactive afterwards
+    pub async fn send_disconnect(&mut self, socket: &SocketData){
+        let Some(active_connection) = self.active_connection_data.as_ref() else {
+            return;
+        };
+
+        let mut packet = PRUDPPacket{
+            header: PRUDPHeader::default(),
+            packet_signature: [0; 16],
+            options: Vec::new(),
+            payload: Vec::new(),
+        };
+
+        packet.header.types_and_flags.set_types(DISCONNECT);
+        packet.header.types_and_flags.set_flag(RELIABLE | NEED_ACK);
+        packet.header.session_id = active_connection.server_session_id;
+
+        self.finish_and_send_packet_to(socket, packet).await;
+
+        self.active_connection_data = None;
+    }
+
     pub async fn finish_and_send_packet_to(&mut self, socket: &SocketData, mut packet: PRUDPPacket){
         if (packet.header.types_and_flags.get_flags() & RELIABLE) != 0{
             let Some(active_connection) = self.active_connection_data.as_mut() else {
@@ -394,6 +761,8 @@ impl ConnectionData{
             packet.header.sequence_id = active_connection.reliable_server_counter;
             active_connection.reliable_server_counter += 1;
 
+            socket.capture.decrypted_payload(Direction::Outbound, self.sock_addr.regular_socket_addr, active_connection.pid, &packet.payload);
+
             active_connection.server_encryption.apply_keystream(&mut packet.payload);
         }
 
@@ -408,9 +777,12 @@ impl ConnectionData{
 
         packet.write_to(&mut vec).expect("somehow failed to convert backet to bytes");
 
-        if let Err(e) = socket.socket.send_to(&vec, self.sock_addr.regular_socket_addr).await{
+        if let Err(e) = socket.send_datagram(&vec, self.sock_addr.regular_socket_addr).await{
             error!("unable to send packet to destination: {}", e);
+            return;
         }
+
+        self.packets_sent += 1;
     }
 }
 
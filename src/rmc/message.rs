@@ -1,7 +1,13 @@
 use std::io;
-use std::io::{Read, Seek};
+use std::io::{Cursor, ErrorKind, Read, Write};
 use log::error;
-use crate::endianness::{IS_BIG_ENDIAN, ReadExtensions};
+use thiserror::Error;
+use crate::endianness::{IS_BIG_ENDIAN, ReadExtensions, WriteExtensions};
+use crate::prudp::packet::{PRUDPPacket, PRUDPHeader};
+use crate::prudp::packet::flags::{NEED_ACK, RELIABLE};
+use crate::prudp::packet::PacketOption::FragmentId;
+use crate::prudp::packet::types::DATA;
+use crate::prudp::socket::{ConnectionData, SocketData};
 use crate::rmc::response::{ErrorCode, RMCResponseResult};
 
 #[derive(Debug)]
@@ -13,37 +19,76 @@ pub struct RMCMessage{
     pub rest_of_data: Vec<u8>
 }
 
-impl RMCMessage{
-    pub fn new(stream: &mut (impl Seek + Read)) -> io::Result<Self>{
-        let size: u32 = stream.read_struct(IS_BIG_ENDIAN)?;
-
-        let mut header_size = 1 + 4 + 4;
-
-        let protocol_id: u8 = stream.read_struct(IS_BIG_ENDIAN)?;
-        let protocol_id= protocol_id & (!0x80);
+#[derive(Error, Debug)]
+pub enum Error{
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("message for protocol {protocol_id} is a response and not a request")]
+    NotARequest{
+        protocol_id: u16,
+    },
+    #[error("message for protocol {protocol_id} is a request and not a response")]
+    NotAResponse{
+        protocol_id: u16,
+    },
+    #[error("unknown error code {error_code:#010x} in response to call {call_id}")]
+    UnknownErrorCode{
+        protocol_id: u16,
+        call_id: u32,
+        error_code: u32,
+    },
+    #[error("declared size {declared} of a message for protocol {protocol_id} is smaller than its header")]
+    InvalidSize{
+        protocol_id: u16,
+        // not known if the size doesn't even cover the protocol id
+        call_id: Option<u32>,
+        declared: u32,
+    },
+    #[error("declared size {declared} of call {call_id} exceeds the {available} available bytes")]
+    Truncated{
+        protocol_id: u16,
+        call_id: u32,
+        declared: u32,
+        available: u32,
+    },
+}
 
-        let protocol_id: u16 = match protocol_id{
-            0x7F => {
-                header_size += 2;
-                stream.read_struct(IS_BIG_ENDIAN)?
-            },
-            _ => protocol_id as u16
-        };
+impl Error{
+    // the call which should be answered with an error, if the message got far enough to tell
+    pub fn failed_call(&self) -> Option<(u16, u32, ErrorCode)>{
+        match self {
+            Self::InvalidSize { protocol_id, call_id: Some(call_id), .. } |
+            Self::Truncated { protocol_id, call_id, .. } => Some((*protocol_id, *call_id, ErrorCode::Core_InvalidArgument)),
+            Self::InvalidSize { call_id: None, .. } |
+            Self::Io(_) |
+            Self::NotARequest { .. } |
+            Self::NotAResponse { .. } |
+            Self::UnknownErrorCode { .. } => None,
+        }
+    }
+}
 
-        let call_id = stream.read_struct(IS_BIG_ENDIAN)?;
-        let method_id = stream.read_struct(IS_BIG_ENDIAN)?;
+pub type Result<T> = std::result::Result<T, Error>;
 
-        let mut rest_of_data = Vec::new();
+impl RMCMessage{
+    // reads exactly one request, the stream is left at the start of the next message unless the
+    // framing was broken (`Io`, `InvalidSize` or `Truncated`)
+    pub fn new(stream: &mut impl Read) -> Result<Self>{
+        let header = MessageHeader::read(stream)?;
+        let protocol_id = header.protocol_id;
 
-        stream.read_to_end(&mut rest_of_data)?;
+        if !header.is_request {
+            // responses have a different layout, skip over them so the next message can be read
+            header.skip_body(stream)?;
 
-        if header_size + rest_of_data.len() != size as usize {
-            error!("received incorrect rmc packet: expected size {} but found {}", size, header_size + rest_of_data.len());
+            return Err(Error::NotARequest { protocol_id });
         }
 
+        let call_id = stream.read_struct(IS_BIG_ENDIAN)?;
+        let method_id = stream.read_struct(IS_BIG_ENDIAN)?;
 
+        let rest_of_data = header.read_data(stream, call_id, 4 + 4)?;
 
-        //stream.
         Ok(Self{
             protocol_id,
             method_id,
@@ -52,10 +97,261 @@ impl RMCMessage{
         })
     }
 
+    // reads all messages contained in a reassembled payload in order, stops after the first
+    // message with broken framing as nothing after it can be trusted
+    pub fn read_all(payload: &[u8]) -> MessageIter<'_>{
+        MessageIter{
+            cursor: Cursor::new(payload),
+            done: false,
+        }
+    }
+
+    pub fn to_data(&self) -> io::Result<Vec<u8>>{
+        let header_size = protocol_id_size(self.protocol_id) + 4 + 4;
+
+        let size = header_size + self.rest_of_data.len();
+
+        let mut data_out = Vec::with_capacity(size + 4);
+
+        let u32_size: u32 = size as _;
+        data_out.write_struct(IS_BIG_ENDIAN, u32_size)?;
+
+        write_protocol_id(&mut data_out, self.protocol_id, true)?;
+
+        data_out.write_struct(IS_BIG_ENDIAN, self.call_id)?;
+        data_out.write_struct(IS_BIG_ENDIAN, self.method_id)?;
+        data_out.write_all(&self.rest_of_data)?;
+
+        assert_eq!(data_out.len(), size + 4);
+
+        Ok(data_out)
+    }
+
     pub fn error_result_with_code(&self, error_code: ErrorCode) -> RMCResponseResult{
         RMCResponseResult::Error {
             call_id: self.call_id,
             error_code
         }
     }
-}
\ No newline at end of file
+}
+
+// the part of the header which requests and responses have in common
+pub struct MessageHeader{
+    pub size: u32,
+    pub is_request: bool,
+    pub protocol_id: u16,
+}
+
+impl MessageHeader{
+    pub fn read(stream: &mut impl Read) -> Result<Self>{
+        let size: u32 = stream.read_struct(IS_BIG_ENDIAN)?;
+
+        let protocol_byte: u8 = stream.read_struct(IS_BIG_ENDIAN)?;
+        let is_request = protocol_byte & 0x80 != 0;
+        let protocol_id = protocol_byte & (!0x80);
+
+        let protocol_id: u16 = match protocol_id{
+            0x7F => stream.read_struct(IS_BIG_ENDIAN)?,
+            _ => protocol_id as u16
+        };
+
+        Ok(Self{
+            size,
+            is_request,
+            protocol_id
+        })
+    }
+
+    // skips everything after the protocol id
+    pub fn skip_body(&self, stream: &mut impl Read) -> Result<()>{
+        let Some(remaining) = self.size.checked_sub(protocol_id_size(self.protocol_id) as u32) else {
+            return Err(Error::InvalidSize { protocol_id: self.protocol_id, call_id: None, declared: self.size });
+        };
+        let skipped = io::copy(&mut stream.by_ref().take(remaining as u64), &mut io::sink())?;
+
+        if skipped != remaining as u64 {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(())
+    }
+
+    // reads the data after the protocol id and `read_header_size` bytes of already read headers,
+    // making sure that it matches the declared size
+    pub fn read_data(&self, stream: &mut impl Read, call_id: u32, read_header_size: usize) -> Result<Vec<u8>>{
+        let header_size = protocol_id_size(self.protocol_id) + read_header_size;
+
+        let Some(data_size) = (self.size as usize).checked_sub(header_size) else {
+            return Err(Error::InvalidSize { protocol_id: self.protocol_id, call_id: Some(call_id), declared: self.size });
+        };
+
+        // the size comes from the client, so the buffer only grows with the data which is actually
+        // there instead of reserving whatever was declared
+        let mut data = Vec::new();
+
+        stream.by_ref().take(data_size as u64).read_to_end(&mut data)?;
+
+        if data.len() != data_size {
+            return Err(Error::Truncated {
+                protocol_id: self.protocol_id,
+                call_id,
+                declared: self.size,
+                available: (header_size + data.len()) as u32
+            });
+        }
+
+        Ok(data)
+    }
+}
+
+pub struct MessageIter<'a>{
+    cursor: Cursor<&'a [u8]>,
+    done: bool,
+}
+
+impl Iterator for MessageIter<'_>{
+    type Item = Result<RMCMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.cursor.position() as usize >= self.cursor.get_ref().len() {
+            return None;
+        }
+
+        let result = RMCMessage::new(&mut self.cursor);
+
+        if let Err(e) = &result {
+            self.done = !matches!(e, Error::NotARequest { .. });
+        }
+
+        Some(result)
+    }
+}
+
+// protocol ids which don't fit into 7 bits are escaped with 0x7F followed by the full id as u16
+pub(crate) const fn protocol_id_size(protocol_id: u16) -> usize{
+    if protocol_id < 0x7F { 1 } else { 1 + 2 }
+}
+
+pub(crate) fn write_protocol_id(writer: &mut impl Write, protocol_id: u16, is_request: bool) -> io::Result<()>{
+    // the 0x80 bit marks a message as a request
+    let request_bit = if is_request { 0x80 } else { 0x00 };
+
+    if protocol_id < 0x7F {
+        writer.write_all(&[protocol_id as u8 | request_bit])
+    } else {
+        writer.write_all(&[0x7F | request_bit])?;
+        writer.write_struct(IS_BIG_ENDIAN, protocol_id)
+    }
+}
+
+// sends a request originating from the server (e.g. a notification) to the client, the call id of
+// the message gets overwritten with the next call id of the connection
+pub async fn send_request(socket: &SocketData, connection: &mut ConnectionData, mut message: RMCMessage){
+    let Some(active_connection) = connection.active_connection_data.as_mut() else {
+        error!("tried to send a request to an inactive connection");
+        return;
+    };
+
+    message.call_id = active_connection.server_call_id_counter;
+    active_connection.server_call_id_counter = active_connection.server_call_id_counter.wrapping_add(1);
+
+    let payload = match message.to_data(){
+        Ok(v) => v,
+        Err(e) => {
+            error!("unable to write rmc request: {}", e);
+            return;
+        }
+    };
+
+    let mut packet = PRUDPPacket{
+        header: PRUDPHeader::default(),
+        packet_signature: [0; 16],
+        options: vec![FragmentId(0)],
+        payload,
+    };
+
+    packet.header.types_and_flags.set_types(DATA);
+    packet.header.types_and_flags.set_flag(RELIABLE | NEED_ACK);
+
+    packet.header.session_id = active_connection.server_session_id;
+
+    connection.finish_and_send_packet_to(socket, packet).await;
+}
+
+#[cfg(test)]
+mod test{
+    use crate::rmc::response::{generate_response, RMCResponseResult};
+    use super::{Error, RMCMessage};
+
+    fn request(protocol_id: u16, call_id: u32, data: &[u8]) -> Vec<u8>{
+        RMCMessage{
+            protocol_id,
+            call_id,
+            method_id: 1,
+            rest_of_data: data.to_vec(),
+        }.to_data().unwrap()
+    }
+
+    #[test]
+    fn multiple_messages(){
+        let mut payload = request(10, 1, &[1, 2, 3]);
+        payload.extend(generate_response(14, RMCResponseResult::Success {
+            call_id: 5,
+            method_id: 1,
+            data: vec![],
+        }).unwrap());
+        payload.extend(request(0x1234, 2, &[]));
+
+        let messages: Vec<_> = RMCMessage::read_all(&payload).collect();
+        assert_eq!(messages.len(), 3);
+
+        let first = messages[0].as_ref().unwrap();
+        assert_eq!((first.protocol_id, first.call_id), (10, 1));
+        assert_eq!(first.rest_of_data, [1, 2, 3]);
+
+        assert!(matches!(messages[1], Err(Error::NotARequest { protocol_id: 14 })));
+
+        let third = messages[2].as_ref().unwrap();
+        assert_eq!((third.protocol_id, third.call_id), (0x1234, 2));
+        assert!(third.rest_of_data.is_empty());
+    }
+
+    #[test]
+    fn declared_size_is_enforced(){
+        let mut truncated = request(10, 7, &[1, 2, 3]);
+        truncated.pop();
+
+        let messages: Vec<_> = RMCMessage::read_all(&truncated).collect();
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0], Err(Error::Truncated { protocol_id: 10, call_id: 7, declared: 12, available: 11 })));
+
+        let mut huge = request(10, 7, &[1, 2, 3]);
+        huge[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let messages: Vec<_> = RMCMessage::read_all(&huge).collect();
+        assert!(matches!(messages[0], Err(Error::Truncated { protocol_id: 10, call_id: 7, declared: u32::MAX, available: 12 })));
+
+        let mut too_small = request(10, 7, &[]);
+        too_small[0] = 4;
+        too_small.extend(request(10, 8, &[]));
+
+        // nothing after a broken message can be trusted
+        let messages: Vec<_> = RMCMessage::read_all(&too_small).collect();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].as_ref().is_err_and(|e| e.failed_call().is_some()));
+
+        // a response which claims to be smaller than its protocol id can't be skipped over
+        let mut too_small = generate_response(0x1234, RMCResponseResult::Success {
+            call_id: 5,
+            method_id: 1,
+            data: vec![],
+        }).unwrap();
+        too_small[0..4].copy_from_slice(&1u32.to_le_bytes());
+        too_small.extend(request(10, 8, &[]));
+
+        let messages: Vec<_> = RMCMessage::read_all(&too_small).collect();
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0], Err(Error::InvalidSize { protocol_id: 0x1234, call_id: None, declared: 1 })));
+        assert!(messages[0].as_ref().is_err_and(|e| e.failed_call().is_none()));
+    }
+}
@@ -1,17 +1,21 @@
 use std::io;
-use std::io::{Cursor, Write};
-use std::mem::transmute;
+use std::io::{Cursor, Read, Write};
+use std::fmt::{Display, Formatter};
 use tokio::sync::Mutex;
-use bytemuck::bytes_of;
 use hmac::digest::consts::U5;
 use hmac::digest::KeyInit;
 use rc4::{Rc4, StreamCipher};
+use crate::endianness::{IS_BIG_ENDIAN, ReadExtensions, WriteExtensions};
 use crate::prudp::packet::{PRUDPHeader, PRUDPPacket, TypesFlags};
 use crate::prudp::packet::flags::{HAS_SIZE, NEED_ACK, RELIABLE};
 use crate::prudp::packet::PacketOption::FragmentId;
 use crate::prudp::packet::types::DATA;
 use crate::prudp::socket::{ConnectionData, SocketData};
+use crate::rmc::{message, structures};
+use crate::rmc::message::{protocol_id_size, write_protocol_id, MessageHeader};
+use crate::rmc::structures::result_code::ResultCode;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RMCResponseResult {
     Success{
         call_id: u32,
@@ -24,19 +28,72 @@ pub enum RMCResponseResult {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RMCResponse {
-    pub protocol_id: u8,
+    pub protocol_id: u16,
     pub response_result: RMCResponseResult
 }
 
 impl RMCResponse {
+    // reads exactly one response, see `RMCMessage::new` for the request counterpart
+    pub fn new(stream: &mut impl Read) -> message::Result<Self>{
+        let header = MessageHeader::read(stream)?;
+        let protocol_id = header.protocol_id;
+
+        if header.is_request {
+            header.skip_body(stream)?;
+
+            return Err(message::Error::NotAResponse { protocol_id });
+        }
+
+        let success: u8 = stream.read_struct(IS_BIG_ENDIAN)?;
+
+        let response_result = if success != 0 {
+            let call_id: u32 = stream.read_struct(IS_BIG_ENDIAN)?;
+            let method_id: u32 = stream.read_struct(IS_BIG_ENDIAN)?;
+
+            let data = header.read_data(stream, call_id, 1 + 4 + 4)?;
+
+            RMCResponseResult::Success {
+                call_id,
+                method_id: method_id & !0x8000,
+                data
+            }
+        } else {
+            let error_code: u32 = stream.read_struct(IS_BIG_ENDIAN)?;
+            let call_id: u32 = stream.read_struct(IS_BIG_ENDIAN)?;
+
+            // errors don't carry any data but the declared size still has to match
+            let data = header.read_data(stream, call_id, 1 + 4 + 4)?;
+
+            if !data.is_empty() {
+                return Err(message::Error::InvalidSize { protocol_id, call_id: Some(call_id), declared: header.size });
+            }
+
+            // official servers set the error bit of the result code, the codes themselves don't
+            // include it
+            let error_code = ErrorCode::try_from(ResultCode(error_code).error_code_value())
+                .map_err(|_| message::Error::UnknownErrorCode { protocol_id, call_id, error_code })?;
+
+            RMCResponseResult::Error {
+                error_code,
+                call_id
+            }
+        };
+
+        Ok(Self{
+            protocol_id,
+            response_result
+        })
+    }
+
     pub fn to_data(self) -> Vec<u8>{
         generate_response(self.protocol_id, self.response_result).expect("failed to generate response")
     }
 }
 
-pub fn generate_response(protocol_id: u8, response: RMCResponseResult) -> io::Result<Vec<u8>>{
-    let size = 1 + 1 + match &response{
+pub fn generate_response(protocol_id: u16, response: RMCResponseResult) -> io::Result<Vec<u8>>{
+    let size = protocol_id_size(protocol_id) + 1 + match &response{
         RMCResponseResult::Success {
             data,
             ..
@@ -48,8 +105,8 @@ pub fn generate_response(protocol_id: u8, response: RMCResponseResult) -> io::Re
 
     let u32_size: u32 = size as _;
 
-    data_out.write_all(bytes_of(&u32_size))?;
-    data_out.push(protocol_id);
+    data_out.write_struct(IS_BIG_ENDIAN, u32_size)?;
+    write_protocol_id(&mut data_out, protocol_id, false)?;
 
     match response{
         RMCResponseResult::Success {
@@ -58,9 +115,9 @@ pub fn generate_response(protocol_id: u8, response: RMCResponseResult) -> io::Re
             data
         } => {
             data_out.push(1);
-            data_out.write_all(bytes_of(&call_id))?;
+            data_out.write_struct(IS_BIG_ENDIAN, call_id)?;
             let ored_method_id = method_id | 0x8000;
-            data_out.write_all(bytes_of(&ored_method_id))?;
+            data_out.write_struct(IS_BIG_ENDIAN, ored_method_id)?;
             data_out.write_all(&data)?;
         },
         RMCResponseResult::Error {
@@ -68,9 +125,8 @@ pub fn generate_response(protocol_id: u8, response: RMCResponseResult) -> io::Re
             error_code
         } => {
             data_out.push(0);
-            let error_code_val: u32 = error_code.into();
-            data_out.write_all(bytes_of(&error_code_val))?;
-            data_out.write_all(bytes_of(&call_id))?;
+            data_out.write_struct(IS_BIG_ENDIAN, ResultCode::error(error_code).0)?;
+            data_out.write_struct(IS_BIG_ENDIAN, call_id)?;
         }
     }
 
@@ -105,10 +161,38 @@ pub async fn send_response(original_packet: &PRUDPPacket, socket: &SocketData, c
     connection.finish_and_send_packet_to(socket, packet).await;
 }
 
-//taken from kinnays error list directly
-#[allow(nonstandard_style)]
-#[repr(u32)]
-pub enum ErrorCode {
+macro_rules! error_codes {
+    ($($name:ident = $value:literal),* $(,)?) => {
+        //taken from kinnays error list directly
+        #[allow(nonstandard_style)]
+        #[repr(u32)]
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum ErrorCode {
+            $($name = $value),*
+        }
+
+        impl ErrorCode{
+            pub const fn name(self) -> &'static str{
+                match self {
+                    $(Self::$name => stringify!($name)),*
+                }
+            }
+        }
+
+        impl TryFrom<u32> for ErrorCode{
+            type Error = u32;
+
+            fn try_from(value: u32) -> Result<Self, u32> {
+                match value {
+                    $($value => Ok(Self::$name),)*
+                    _ => Err(value)
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
     Core_Unknown = 0x00010001,
     Core_NotImplemented = 0x00010002,
     Core_InvalidPointer = 0x00010003,
@@ -379,12 +463,43 @@ pub enum ErrorCode {
     Custom_Unknown = 0x00740001,
     Ess_Unknown = 0x00750001,
     Ess_GameSessionError = 0x00750002,
-    Ess_GameSessionMaintenance = 0x00750003
+    Ess_GameSessionMaintenance = 0x00750003,
 }
 
-impl Into<u32> for ErrorCode  {
-    fn into(self) -> u32 {
-        unsafe{ transmute(self) }
+impl ErrorCode{
+    // the upper half of the code identifies the module the error comes from
+    pub const fn category_id(self) -> u16{
+        (self as u32 >> 16) as u16
+    }
+
+    // the name of the module the error comes from e.g. `RendezVous` for `RendezVous_SessionFull`
+    pub fn category(self) -> &'static str{
+        let name = self.name();
+
+        name.split_once('_').map(|(category, _)| category).unwrap_or(name)
+    }
+}
+
+impl From<ErrorCode> for u32{
+    fn from(value: ErrorCode) -> Self {
+        value as u32
+    }
+}
+
+impl Display for ErrorCode{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "{} ({:#010x})", self.name(), *self as u32)
+        } else {
+            f.write_str(self.name())
+        }
+    }
+}
+
+// lets handlers use `?` on deserialization of their parameters
+impl From<structures::Error> for ErrorCode{
+    fn from(_: structures::Error) -> Self {
+        ErrorCode::Core_InvalidArgument
     }
 }
 
@@ -393,6 +508,9 @@ mod test{
     use hmac::digest::consts::U5;
     use hmac::digest::KeyInit;
     use rc4::{Rc4, StreamCipher};
+    use std::io::Cursor;
+    use crate::rmc::message::{Error, RMCMessage};
+    use super::{generate_response, ErrorCode, RMCResponse, RMCResponseResult};
 
     #[test]
     fn test(){
@@ -414,4 +532,118 @@ mod test{
         assert_eq!(data_orig, data);
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn response_is_little_endian(){
+        let data = generate_response(10, RMCResponseResult::Success {
+            call_id: 0x01020304,
+            method_id: 0x02,
+            data: vec![0xAA],
+        }).unwrap();
+
+        assert_eq!(data, [
+            0x0B, 0, 0, 0,
+            10,
+            1,
+            0x04, 0x03, 0x02, 0x01,
+            0x02, 0x80, 0, 0,
+            0xAA
+        ]);
+    }
+
+    #[test]
+    fn extended_protocol_id(){
+        let data = generate_response(0x1234, RMCResponseResult::Error {
+            call_id: 1,
+            error_code: ErrorCode::Core_NotImplemented,
+        }).unwrap();
+
+        assert_eq!(data, [
+            0x0C, 0, 0, 0,
+            0x7F, 0x34, 0x12,
+            0,
+            0x02, 0x00, 0x01, 0x80,
+            1, 0, 0, 0
+        ]);
+    }
+
+    #[test]
+    fn response_round_trip(){
+        let responses = [
+            RMCResponse{
+                protocol_id: 10,
+                response_result: RMCResponseResult::Success { call_id: 3, method_id: 2, data: vec![1, 2, 3] }
+            },
+            RMCResponse{
+                protocol_id: 0x1234,
+                response_result: RMCResponseResult::Error { call_id: 4, error_code: ErrorCode::RendezVous_SessionFull }
+            },
+        ];
+
+        let mut data = Vec::new();
+        for response in responses.clone(){
+            data.extend(response.to_data());
+        }
+
+        let mut cursor = Cursor::new(&data);
+        for response in responses{
+            assert_eq!(RMCResponse::new(&mut cursor).unwrap(), response);
+        }
+        assert_eq!(cursor.position() as usize, data.len());
+    }
+
+    #[test]
+    fn response_decoder_rejects_requests(){
+        let request = RMCMessage{
+            protocol_id: 10,
+            call_id: 1,
+            method_id: 1,
+            rest_of_data: vec![0; 8],
+        }.to_data().unwrap();
+
+        let mut cursor = Cursor::new(&request);
+        assert!(matches!(RMCResponse::new(&mut cursor), Err(Error::NotAResponse { protocol_id: 10 })));
+        assert_eq!(cursor.position() as usize, request.len());
+
+        let mut unknown = generate_response(10, RMCResponseResult::Error { call_id: 1, error_code: ErrorCode::Core_Unknown }).unwrap();
+        unknown[6..10].copy_from_slice(&0xDEADBEEFu32.to_le_bytes());
+
+        assert!(matches!(RMCResponse::new(&mut Cursor::new(&unknown)), Err(Error::UnknownErrorCode { error_code: 0xDEADBEEF, .. })));
+    }
+
+    #[test]
+    fn captured_error_response(){
+        // a LoginEx error the way official servers send it, with the error bit set
+        let data = [
+            0x0A, 0x00, 0x00, 0x00,
+            0x0A,
+            0x00,
+            0x64, 0x00, 0x03, 0x80,
+            0x02, 0x00, 0x00, 0x00
+        ];
+
+        let response = RMCResponse{
+            protocol_id: 10,
+            response_result: RMCResponseResult::Error { call_id: 2, error_code: ErrorCode::RendezVous_InvalidUsername }
+        };
+
+        assert_eq!(RMCResponse::new(&mut Cursor::new(&data)).unwrap(), response);
+        assert_eq!(response.to_data(), data);
+    }
+
+    #[test]
+    fn error_code_conversions(){
+        let code = ErrorCode::RendezVous_SessionFull;
+        let value: u32 = code.into();
+
+        assert_eq!(ErrorCode::try_from(value), Ok(code));
+        assert_eq!(ErrorCode::try_from(0xDEADBEEF), Err(0xDEADBEEF));
+
+        assert_eq!(code.to_string(), "RendezVous_SessionFull");
+        assert_eq!(format!("{:#}", ErrorCode::Core_NotImplemented), "Core_NotImplemented (0x00010002)");
+
+        assert_eq!(code.category(), "RendezVous");
+        assert_eq!(code.category_id(), 0x0003);
+        assert_eq!(ErrorCode::DOCore_Unknown.category(), "DOCore");
+    }
+}
@@ -1,33 +1,181 @@
-use std::io::{Read, Seek, Write};
-use crate::endianness::{IS_BIG_ENDIAN, ReadExtensions};
-use super::{string, Result, RmcSerialize};
+use std::io::{Cursor, Read, Write};
+use log::warn;
+use super::{read_bytes, Error, Result, RmcSerialize, SerializationContext};
+use super::authentication_info::AuthenticationInfo;
+use super::gathering::Gathering;
+use super::matchmake_session::MatchmakeSession;
 
-#[derive(Debug)]
+/// The content of an [`Any`], classes which aren't in the registry are kept as raw bytes
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnyData{
+    AuthenticationInfo(AuthenticationInfo),
+    Gathering(Gathering),
+    MatchmakeSession(MatchmakeSession),
+    Unknown(Vec<u8>),
+}
+
+type AnyDecoder = fn(&mut dyn Read, &SerializationContext) -> Result<AnyData>;
+
+// maps the class names sent over the wire to the decoders of the corresponding structure
+static REGISTRY: &[(&str, AnyDecoder)] = &[
+    ("AuthenticationInfo", |r, ctx| Ok(AnyData::AuthenticationInfo(AuthenticationInfo::deserialize(r, ctx)?))),
+    ("Gathering", |r, ctx| Ok(AnyData::Gathering(Gathering::deserialize(r, ctx)?))),
+    ("MatchmakeSession", |r, ctx| Ok(AnyData::MatchmakeSession(MatchmakeSession::deserialize(r, ctx)?))),
+];
+
+fn find_decoder(name: &str) -> Option<AnyDecoder>{
+    REGISTRY.iter().find(|(n, _)| *n == name).map(|(_, decoder)| *decoder)
+}
+
+impl AnyData{
+    fn serialize_content(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()>{
+        match self {
+            AnyData::AuthenticationInfo(v) => v.serialize(writer, ctx),
+            AnyData::Gathering(v) => v.serialize(writer, ctx),
+            AnyData::MatchmakeSession(v) => v.serialize(writer, ctx),
+            AnyData::Unknown(data) => {
+                writer.write_all(data)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A nex `AnyDataHolder`
+#[derive(Debug, Clone, PartialEq)]
 pub struct Any{
     pub name: String,
-    pub data: Vec<u8>
+    pub data: AnyData
 }
 
 impl RmcSerialize for Any{
-    fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
-        todo!()
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        self.name.serialize(writer, ctx)?;
+
+        let mut content = Vec::new();
+        self.data.serialize_content(&mut content, ctx)?;
+
+        // the outer length also counts the inner length field
+        let inner_length = content.len() as u32;
+        let outer_length = inner_length + 4;
+
+        outer_length.serialize(writer, ctx)?;
+        inner_length.serialize(writer, ctx)?;
+        writer.write_all(&content)?;
+
+        Ok(())
     }
-    fn deserialize(mut reader: &mut dyn Read) -> Result<Self> {
-        let name = String::deserialize(reader)?;
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        let name = String::deserialize(reader, ctx)?;
 
-        // also length ?
-        let len2: u32 = reader.read_struct(IS_BIG_ENDIAN)?;
-        let length: u32 = reader.read_struct(IS_BIG_ENDIAN)?;
+        let outer_length = u32::deserialize(reader, ctx)?;
+        let inner_length = u32::deserialize(reader, ctx)?;
 
-        let mut data = vec![0; length as usize];
+        if outer_length != inner_length.wrapping_add(4) {
+            return Err(Error::InvalidValue {
+                name: "AnyDataHolder length",
+                value: outer_length as u64
+            });
+        }
 
-        reader.read_exact(&mut data)?;
+        let content = read_bytes(reader, inner_length as usize)?;
 
-        Ok(
-            Any{
+        let Some(decoder) = find_decoder(&name) else {
+            return Ok(Any{
                 name,
-                data
-            }
-        )
+                data: AnyData::Unknown(content)
+            });
+        };
+
+        let mut cursor = Cursor::new(&content);
+        let data = decoder(&mut cursor, ctx)?;
+
+        // keep the raw data if we didn't understand all of it so it can still be sent back unchanged
+        if cursor.position() as usize != content.len() {
+            warn!("{} in any data holder has {} unread bytes", name, content.len() - cursor.position() as usize);
+
+            return Ok(Any{
+                name,
+                data: AnyData::Unknown(content)
+            });
+        }
+
+        Ok(Any{
+            name,
+            data
+        })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use super::{Any, AnyData};
+
+    #[test]
+    fn authentication_info(){
+        let ctx = SerializationContext::default();
+
+        let data = [
+            // class name
+            0x13, 0x00, b'A', b'u', b't', b'h', b'e', b'n', b't', b'i', b'c', b'a', b't', b'i', b'o', b'n', b'I', b'n', b'f', b'o', 0x00,
+            // outer and inner length
+            0x1A, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00,
+            // Data
+            0x00, 0x00, 0x00, 0x00, 0x00,
+            // AuthenticationInfo
+            0x00, 0x0C, 0x00, 0x00, 0x00,
+            0x01, 0x00, 0x00,
+            0x01, 0x00, 0x00, 0x00,
+            0x01,
+            0x03, 0x00, 0x00, 0x00,
+        ];
+
+        let any = Any::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+
+        let AnyData::AuthenticationInfo(info) = &any.data else {
+            panic!("expected AuthenticationInfo, got {:?}", any.data);
+        };
+
+        assert_eq!(info.token, "");
+        assert_eq!(info.ngs_version, 1);
+        assert_eq!(info.token_type, 1);
+        assert_eq!(info.server_version, 3);
+
+        let mut written = Vec::new();
+        any.serialize(&mut written, &ctx).unwrap();
+        assert_eq!(written, data);
+    }
+
+    #[test]
+    fn unknown_class(){
+        let ctx = SerializationContext::default();
+
+        let data = [
+            0x04, 0x00, b'F', b'o', b'o', 0x00,
+            0x06, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+            0xAB, 0xCD,
+        ];
+
+        let any = Any::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+        assert_eq!(any.data, AnyData::Unknown(vec![0xAB, 0xCD]));
+
+        let mut written = Vec::new();
+        any.serialize(&mut written, &ctx).unwrap();
+        assert_eq!(written, data);
+    }
+
+    #[test]
+    fn invalid_lengths(){
+        let ctx = SerializationContext::default();
+
+        let data = [
+            0x04, 0x00, b'F', b'o', b'o', 0x00,
+            0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+            0xAB, 0xCD,
+        ];
+
+        assert!(Any::deserialize(&mut Cursor::new(&data), &ctx).is_err());
+    }
+}
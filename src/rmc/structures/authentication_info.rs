@@ -0,0 +1,13 @@
+use super::RmcSerialize;
+use super::data::Data;
+
+#[derive(RmcSerialize, Debug, Clone, Default, PartialEq)]
+#[rmc(version = 0)]
+pub struct AuthenticationInfo{
+    #[rmc(parent)]
+    pub data: Data,
+    pub token: String,
+    pub ngs_version: u32,
+    pub token_type: u8,
+    pub server_version: u32,
+}
@@ -0,0 +1,77 @@
+use std::io::{Read, Write};
+use super::{read_bytes, Error, Result, RmcSerialize, SerializationContext};
+
+/// A `Buffer` which has its length written as u32
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Buffer(pub Vec<u8>);
+
+/// A `qBuffer` which has its length written as u16
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QBuffer(pub Vec<u8>);
+
+impl RmcSerialize for Buffer{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        (self.0.len() as u32).serialize(writer, ctx)?;
+        writer.write_all(&self.0)?;
+
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        let len = u32::deserialize(reader, ctx)?;
+
+        Ok(Self(read_bytes(reader, len as usize)?))
+    }
+}
+
+impl RmcSerialize for QBuffer{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        let len = u16::try_from(self.0.len()).map_err(|_| Error::InvalidValue {
+            name: "qBuffer length",
+            value: self.0.len() as u64
+        })?;
+
+        len.serialize(writer, ctx)?;
+        writer.write_all(&self.0)?;
+
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        let len = u16::deserialize(reader, ctx)?;
+
+        Ok(Self(read_bytes(reader, len as usize)?))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use super::{Buffer, QBuffer};
+
+    #[test]
+    fn buffers(){
+        let ctx = SerializationContext::default();
+
+        let buffer = Buffer(vec![0xDE, 0xAD]);
+        let mut data = Vec::new();
+        buffer.serialize(&mut data, &ctx).unwrap();
+        assert_eq!(data, [2, 0, 0, 0, 0xDE, 0xAD]);
+        assert_eq!(Buffer::deserialize(&mut Cursor::new(&data), &ctx).unwrap(), buffer);
+
+        let buffer = QBuffer(vec![0xBE, 0xEF, 0x00]);
+        let mut data = Vec::new();
+        buffer.serialize(&mut data, &ctx).unwrap();
+        assert_eq!(data, [3, 0, 0xBE, 0xEF, 0x00]);
+        assert_eq!(QBuffer::deserialize(&mut Cursor::new(&data), &ctx).unwrap(), buffer);
+
+        // the length says 4 gigabytes but only two bytes follow
+        assert!(Buffer::deserialize(&mut Cursor::new([0xFF, 0xFF, 0xFF, 0xFF, 0xDE, 0xAD]), &ctx).is_err());
+
+        // the u16 lengths must not wrap around, for strings the null terminator counts as well
+        assert!(QBuffer(vec![0; 65536]).serialize(&mut Vec::new(), &ctx).is_err());
+        assert!("a".repeat(65534).serialize(&mut Vec::new(), &ctx).is_ok());
+        assert!("a".repeat(65535).serialize(&mut Vec::new(), &ctx).is_err());
+    }
+}
@@ -0,0 +1,14 @@
+use super::RmcSerialize;
+use super::date_time::DateTime;
+
+/// Where the auth server sends clients after logging in, `RVConnectionData` in nex
+#[derive(RmcSerialize, Debug, Clone, Default, PartialEq)]
+#[rmc(version = 1)]
+pub struct ConnectionData{
+    // station url of the secure server
+    pub regular_protocols: String,
+    pub special_protocols: Vec<u8>,
+    pub special_protocols_url: String,
+    #[rmc(since_nex = "3.5")]
+    pub current_utc_time: DateTime,
+}
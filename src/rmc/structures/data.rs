@@ -0,0 +1,26 @@
+use super::RmcSerialize;
+
+/// The base class of most nex structures, it doesn't contain anything itself
+#[derive(RmcSerialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[rmc(version = 0)]
+pub struct Data;
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use super::Data;
+
+    #[test]
+    fn data(){
+        let ctx = SerializationContext::default();
+
+        let data = [0, 0, 0, 0, 0];
+
+        Data::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+
+        let mut written = Vec::new();
+        Data.serialize(&mut written, &ctx).unwrap();
+        assert_eq!(written, data);
+    }
+}
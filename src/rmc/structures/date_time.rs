@@ -0,0 +1,107 @@
+use std::io::{Read, Write};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike, Utc};
+use super::{Result, RmcSerialize, SerializationContext};
+
+/// A nex `DateTime`, the date is packed into a u64 like this:
+/// `year << 26 | month << 22 | day << 17 | hour << 12 | minute << 6 | second`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime(pub u64);
+
+impl DateTime{
+    pub const fn from_parts(year: u64, month: u64, day: u64, hour: u64, minute: u64, second: u64) -> Self{
+        Self(
+            (year << 26) |
+                ((month & 0xF) << 22) |
+                ((day & 0x1F) << 17) |
+                ((hour & 0x1F) << 12) |
+                ((minute & 0x3F) << 6) |
+                (second & 0x3F)
+        )
+    }
+
+    pub fn now() -> Self{
+        Self::from_chrono(&Utc::now().naive_utc())
+    }
+
+    pub fn from_chrono(date_time: &NaiveDateTime) -> Self{
+        Self::from_parts(
+            date_time.year() as u64,
+            date_time.month() as u64,
+            date_time.day() as u64,
+            date_time.hour() as u64,
+            date_time.minute() as u64,
+            date_time.second() as u64,
+        )
+    }
+
+    // returns None if the date is invalid (nex uses 0 for "no date" for example)
+    pub fn to_chrono(self) -> Option<NaiveDateTime>{
+        NaiveDate::from_ymd_opt(self.year() as i32, self.month(), self.day())?
+            .and_hms_opt(self.hour(), self.minute(), self.second())
+    }
+
+    pub const fn year(self) -> u32{
+        (self.0 >> 26) as u32
+    }
+
+    pub const fn month(self) -> u32{
+        ((self.0 >> 22) & 0xF) as u32
+    }
+
+    pub const fn day(self) -> u32{
+        ((self.0 >> 17) & 0x1F) as u32
+    }
+
+    pub const fn hour(self) -> u32{
+        ((self.0 >> 12) & 0x1F) as u32
+    }
+
+    pub const fn minute(self) -> u32{
+        ((self.0 >> 6) & 0x3F) as u32
+    }
+
+    pub const fn second(self) -> u32{
+        (self.0 & 0x3F) as u32
+    }
+}
+
+impl RmcSerialize for DateTime{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        self.0.serialize(writer, ctx)
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        Ok(Self(u64::deserialize(reader, ctx)?))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use chrono::NaiveDate;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use super::DateTime;
+
+    #[test]
+    fn date_time(){
+        let ctx = SerializationContext::default();
+
+        // 2015-05-28 12:30:45
+        let data = [0xAD, 0xC7, 0x78, 0x7D, 0x1F, 0x00, 0x00, 0x00];
+
+        let date_time = DateTime::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+
+        assert_eq!(date_time, DateTime::from_parts(2015, 5, 28, 12, 30, 45));
+        assert_eq!(
+            date_time.to_chrono(),
+            NaiveDate::from_ymd_opt(2015, 5, 28).unwrap().and_hms_opt(12, 30, 45)
+        );
+        assert_eq!(DateTime::from_chrono(&date_time.to_chrono().unwrap()), date_time);
+
+        let mut written = Vec::new();
+        date_time.serialize(&mut written, &ctx).unwrap();
+        assert_eq!(written, data);
+
+        assert_eq!(DateTime(0).to_chrono(), None);
+    }
+}
@@ -0,0 +1,17 @@
+use super::RmcSerialize;
+use super::pid::Pid;
+
+#[derive(RmcSerialize, Debug, Clone, Default, PartialEq)]
+#[rmc(version = 0)]
+pub struct Gathering{
+    pub id: u32,
+    pub owner_pid: Pid,
+    pub host_pid: Pid,
+    pub minimum_participants: u16,
+    pub maximum_participants: u16,
+    pub participation_policy: u32,
+    pub policy_argument: u32,
+    pub flags: u32,
+    pub state: u32,
+    pub description: String,
+}
@@ -0,0 +1,46 @@
+use std::io::{Read, Write};
+use super::{Result, RmcSerialize, SerializationContext};
+
+impl<T: RmcSerialize> RmcSerialize for Vec<T>{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        (self.len() as u32).serialize(writer, ctx)?;
+
+        for elem in self{
+            elem.serialize(writer, ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        let len = u32::deserialize(reader, ctx)?;
+
+        // dont trust the length for the allocation, somebody could just send us u32::MAX
+        let mut list = Vec::new();
+
+        for _ in 0..len{
+            list.push(T::deserialize(reader, ctx)?);
+        }
+
+        Ok(list)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+
+    #[test]
+    fn list(){
+        let ctx = SerializationContext::default();
+
+        let list = vec!["ab".to_string(), "".to_string()];
+
+        let mut data = Vec::new();
+        list.serialize(&mut data, &ctx).unwrap();
+
+        assert_eq!(data, [2, 0, 0, 0, 3, 0, b'a', b'b', 0, 1, 0, 0]);
+        assert_eq!(Vec::<String>::deserialize(&mut Cursor::new(&data), &ctx).unwrap(), list);
+    }
+}
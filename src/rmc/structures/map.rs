@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use super::{Result, RmcSerialize, SerializationContext};
+
+// nex uses a std::map so the entries are always sorted by their key
+impl<K: RmcSerialize + Ord, V: RmcSerialize> RmcSerialize for BTreeMap<K, V>{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        (self.len() as u32).serialize(writer, ctx)?;
+
+        for (key, value) in self{
+            key.serialize(writer, ctx)?;
+            value.serialize(writer, ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        let len = u32::deserialize(reader, ctx)?;
+
+        let mut map = BTreeMap::new();
+
+        for _ in 0..len{
+            let key = K::deserialize(reader, ctx)?;
+            let value = V::deserialize(reader, ctx)?;
+
+            map.insert(key, value);
+        }
+
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+
+    #[test]
+    fn map(){
+        let ctx = SerializationContext::default();
+
+        let map = BTreeMap::from([
+            ("b".to_string(), 2u32),
+            ("a".to_string(), 1u32),
+        ]);
+
+        let mut data = Vec::new();
+        map.serialize(&mut data, &ctx).unwrap();
+
+        assert_eq!(data, [
+            2, 0, 0, 0,
+            2, 0, b'a', 0, 1, 0, 0, 0,
+            2, 0, b'b', 0, 2, 0, 0, 0,
+        ]);
+        assert_eq!(BTreeMap::<String, u32>::deserialize(&mut Cursor::new(&data), &ctx).unwrap(), map);
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+use super::RmcSerialize;
+use super::buffer::Buffer;
+use super::date_time::DateTime;
+use super::gathering::Gathering;
+use super::variant::Variant;
+
+#[derive(RmcSerialize, Debug, Clone, Default, PartialEq)]
+#[rmc(version = 0)]
+pub struct MatchmakeParam{
+    pub params: BTreeMap<String, Variant>,
+}
+
+#[derive(RmcSerialize, Debug, Clone, Default, PartialEq)]
+#[rmc(version = 0)]
+pub struct MatchmakeSession{
+    #[rmc(parent)]
+    pub gathering: Gathering,
+    pub game_mode: u32,
+    pub attributes: Vec<u32>,
+    pub open_participation: bool,
+    pub matchmake_system_type: u32,
+    pub application_buffer: Buffer,
+    pub participation_count: u32,
+    #[rmc(since_nex = "3.4")]
+    pub progress_score: u8,
+    #[rmc(since_nex = "3.0")]
+    pub session_key: Buffer,
+    #[rmc(since_nex = "3.5")]
+    pub option_zero: u32,
+    #[rmc(since_nex = "3.6")]
+    pub matchmake_param: MatchmakeParam,
+    #[rmc(since_nex = "3.6")]
+    pub started_time: DateTime,
+    #[rmc(since_nex = "3.7")]
+    pub user_password: String,
+    #[rmc(since_nex = "3.8")]
+    pub refer_gid: u32,
+    #[rmc(since_nex = "3.8")]
+    pub user_password_enabled: bool,
+    #[rmc(since_nex = "3.8")]
+    pub system_password_enabled: bool,
+    #[rmc(since_nex = "4.0")]
+    pub codeword: String,
+}
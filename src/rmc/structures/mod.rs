@@ -1,26 +1,300 @@
+use std::fmt::{Display, Formatter};
 use std::io;
 use std::io::{Read, Seek, Write};
+use std::num::ParseIntError;
+use std::str::FromStr;
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 use md5::digest::impl_oid_carrier;
 use thiserror::Error;
+use crate::endianness::{IS_BIG_ENDIAN, ReadExtensions, WriteExtensions};
 
-//ideas for the future: make a proc macro library which allows generation of struct reads
+pub use rmc_macros::RmcSerialize;
 
 #[derive(Error, Debug)]
 pub enum Error{
     #[error("Io Error: {0}")]
     Io(#[from] io::Error),
     #[error("UTF8 conversion Error: {0}")]
-    Utf8(#[from] FromUtf8Error)
+    Utf8(#[from] FromUtf8Error),
+    #[error("invalid value {value} for {name}")]
+    InvalidValue{
+        name: &'static str,
+        value: u64,
+    }
 }
 
-type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = std::result::Result<T, Error>;
 
 pub mod string;
 pub mod any;
+pub mod primitives;
+pub mod buffer;
+pub mod list;
+pub mod map;
+pub mod date_time;
+pub mod result_code;
+pub mod pid;
+pub mod variant;
+pub mod quuid;
+pub mod result_range;
+pub mod data;
+pub mod authentication_info;
+pub mod connection_data;
+pub mod gathering;
+pub mod matchmake_session;
 
 pub trait RmcSerialize: Sized{
-    fn serialize(&self, writer: &mut dyn Write) -> Result<()>;
-    fn deserialize(reader: &mut dyn Read) -> Result<Self>;
-}
\ No newline at end of file
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()>;
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NexVersion{
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl NexVersion{
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Self{
+        Self{
+            major,
+            minor,
+            patch
+        }
+    }
+
+    pub fn uses_structure_header(self) -> bool{
+        self >= NexVersion::new(3, 5, 0)
+    }
+}
+
+impl FromStr for NexVersion{
+    type Err = ParseIntError;
+
+    // accepts "3.5" as well as "3.5.0"
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, '.');
+
+        let major = parts.next().unwrap_or_default().parse()?;
+        let minor = parts.next().unwrap_or("0").parse()?;
+        let patch = parts.next().unwrap_or("0").parse()?;
+
+        Ok(Self::new(major, minor, patch))
+    }
+}
+
+impl Display for NexVersion{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PidWidth{
+    U32,
+    U64,
+}
+
+/// Everything about the other side which changes how data is laid out on the wire
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SerializationContext{
+    pub nex_version: NexVersion,
+    pub pid_width: PidWidth,
+}
+
+impl SerializationContext{
+    // switch titles (nex 4 and up) use 64 bit pids, everything before uses 32 bit ones
+    pub fn for_version(nex_version: NexVersion) -> Self{
+        let pid_width = if nex_version >= NexVersion::new(4, 0, 0) {
+            PidWidth::U64
+        } else {
+            PidWidth::U32
+        };
+
+        Self{
+            nex_version,
+            pid_width
+        }
+    }
+}
+
+impl Default for SerializationContext{
+    // the nex version splatoon was built with
+    fn default() -> Self {
+        Self::for_version(NexVersion::new(3, 8, 3))
+    }
+}
+
+pub fn write_structure_header(writer: &mut dyn Write, ctx: &SerializationContext, version: u8, content: &[u8]) -> Result<()>{
+    if !ctx.nex_version.uses_structure_header(){
+        return Ok(());
+    }
+
+    let length: u32 = content.len() as u32;
+
+    writer.write_struct(IS_BIG_ENDIAN, version)?;
+    writer.write_struct(IS_BIG_ENDIAN, length)?;
+
+    Ok(())
+}
+
+// reads the header of a structure and returns its content, structures with a newer version than
+// we know of may contain more data than we read so this makes sure we always skip all of it
+pub fn read_structure_content(mut reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Option<Vec<u8>>>{
+    if !ctx.nex_version.uses_structure_header(){
+        return Ok(None);
+    }
+
+    let _version: u8 = reader.read_struct(IS_BIG_ENDIAN)?;
+    let length: u32 = reader.read_struct(IS_BIG_ENDIAN)?;
+
+    Ok(Some(read_bytes(reader, length as usize)?))
+}
+
+// reads data with a length that came from the other side, the buffer only grows with what actually
+// arrives so a bogus length like u32::MAX can't make us allocate gigabytes up front
+pub fn read_bytes(reader: &mut dyn Read, len: usize) -> Result<Vec<u8>>{
+    let mut data = Vec::new();
+    Read::take(reader, len as u64).read_to_end(&mut data)?;
+
+    if data.len() != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use super::{Error, NexVersion, RmcSerialize, SerializationContext};
+
+    #[derive(RmcSerialize, Debug, PartialEq, Default)]
+    #[rmc(version = 0)]
+    struct TestParent{
+        name: String
+    }
+
+    #[derive(RmcSerialize, Debug, PartialEq, Clone, Copy)]
+    #[repr(u8)]
+    enum TestKind{
+        First = 1,
+        Second = 2,
+    }
+
+    #[derive(RmcSerialize, Debug, PartialEq)]
+    #[rmc(version = 1)]
+    struct TestChild{
+        #[rmc(parent)]
+        parent: TestParent,
+        kind: TestKind,
+        description: String,
+        #[rmc(since_nex = "4.0")]
+        only_in_newer_versions: String,
+    }
+
+    #[derive(RmcSerialize, Debug, PartialEq)]
+    struct TestPlain(String, TestKind);
+
+    #[test]
+    fn derived_structure_hierarchy(){
+        let ctx = SerializationContext::default();
+
+        let child = TestChild{
+            parent: TestParent{ name: "a".to_string() },
+            kind: TestKind::Second,
+            description: "bc".to_string(),
+            only_in_newer_versions: String::new(),
+        };
+
+        let mut data = Vec::new();
+        child.serialize(&mut data, &ctx).unwrap();
+
+        assert_eq!(data, [
+            // parent header and content
+            0, 4, 0, 0, 0,
+            2, 0, b'a', 0,
+            // child header and content
+            1, 6, 0, 0, 0,
+            2,
+            3, 0, b'b', b'c', 0,
+        ]);
+
+        let read = TestChild::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+
+        assert_eq!(read, child);
+    }
+
+    #[test]
+    fn derived_structure_skips_unknown_content(){
+        let ctx = SerializationContext::default();
+
+        // a newer version of the parent with an additional byte we don't know about
+        let data = [0, 5, 0, 0, 0, 2, 0, b'a', 0, 0xFF];
+
+        let mut cursor = Cursor::new(&data);
+        let read = TestParent::deserialize(&mut cursor, &ctx).unwrap();
+
+        assert_eq!(read.name, "a");
+        assert_eq!(cursor.position() as usize, data.len());
+    }
+
+    #[test]
+    fn derived_plain_struct_and_enum(){
+        let ctx = SerializationContext::default();
+
+        let plain = TestPlain("x".to_string(), TestKind::First);
+
+        let mut data = Vec::new();
+        plain.serialize(&mut data, &ctx).unwrap();
+
+        assert_eq!(data, [2, 0, b'x', 0, 1]);
+        assert_eq!(TestPlain::deserialize(&mut Cursor::new(&data), &ctx).unwrap(), plain);
+
+        let invalid = TestKind::deserialize(&mut Cursor::new([3u8]), &ctx);
+
+        assert!(matches!(invalid, Err(Error::InvalidValue{ value: 3, .. })));
+    }
+
+    #[test]
+    fn derived_structure_depends_on_nex_version(){
+        let child = TestChild{
+            parent: TestParent{ name: "a".to_string() },
+            kind: TestKind::First,
+            description: "".to_string(),
+            only_in_newer_versions: "n".to_string(),
+        };
+
+        // no structure headers before nex 3.5
+        let old = SerializationContext::for_version(NexVersion::new(3, 4, 0));
+        let mut data = Vec::new();
+        child.serialize(&mut data, &old).unwrap();
+        assert_eq!(data, [2, 0, b'a', 0, 1, 1, 0, 0]);
+
+        let read = TestChild::deserialize(&mut Cursor::new(&data), &old).unwrap();
+        assert_eq!(read.only_in_newer_versions, "");
+
+        let new = SerializationContext::for_version(NexVersion::new(4, 0, 0));
+        let mut data = Vec::new();
+        child.serialize(&mut data, &new).unwrap();
+        assert_eq!(data, [
+            0, 4, 0, 0, 0,
+            2, 0, b'a', 0,
+            1, 8, 0, 0, 0,
+            1,
+            1, 0, 0,
+            2, 0, b'n', 0,
+        ]);
+
+        assert_eq!(TestChild::deserialize(&mut Cursor::new(&data), &new).unwrap(), child);
+    }
+
+    #[test]
+    fn nex_version_parsing(){
+        assert_eq!("3.5".parse::<NexVersion>().unwrap(), NexVersion::new(3, 5, 0));
+        assert_eq!("3.8.3".parse::<NexVersion>().unwrap(), NexVersion::new(3, 8, 3));
+        assert!("three".parse::<NexVersion>().is_err());
+    }
+}
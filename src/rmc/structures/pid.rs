@@ -0,0 +1,65 @@
+use std::io::{Read, Write};
+use super::{Error, PidWidth, Result, RmcSerialize, SerializationContext};
+
+/// A principal id, depending on the title these are sent as u32 or u64
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Pid(pub u64);
+
+impl RmcSerialize for Pid{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        match ctx.pid_width {
+            // cutting off the upper half would silently turn this into somebody else's pid
+            PidWidth::U32 => u32::try_from(self.0)
+                .map_err(|_| Error::InvalidValue {
+                    name: "32 bit pid",
+                    value: self.0
+                })?
+                .serialize(writer, ctx),
+            PidWidth::U64 => self.0.serialize(writer, ctx),
+        }
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        match ctx.pid_width {
+            PidWidth::U32 => Ok(Self(u32::deserialize(reader, ctx)? as u64)),
+            PidWidth::U64 => Ok(Self(u64::deserialize(reader, ctx)?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{NexVersion, RmcSerialize, SerializationContext};
+    use super::Pid;
+
+    #[test]
+    fn pid(){
+        let ctx = SerializationContext::default();
+
+        let data = [0x3B, 0x68, 0x80, 0x6B];
+
+        let pid = Pid::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+        assert_eq!(pid, Pid(1803577403));
+
+        let mut written = Vec::new();
+        pid.serialize(&mut written, &ctx).unwrap();
+        assert_eq!(written, data);
+
+        assert!(Pid(0x16B80683B).serialize(&mut Vec::new(), &ctx).is_err());
+    }
+
+    #[test]
+    fn pid_u64(){
+        let ctx = SerializationContext::for_version(NexVersion::new(4, 0, 0));
+
+        let data = [0x3B, 0x68, 0x80, 0x6B, 0x01, 0x00, 0x00, 0x00];
+
+        let pid = Pid::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+        assert_eq!(pid, Pid(0x16B80683B));
+
+        let mut written = Vec::new();
+        pid.serialize(&mut written, &ctx).unwrap();
+        assert_eq!(written, data);
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::{Read, Write};
+use crate::endianness::{IS_BIG_ENDIAN, ReadExtensions, WriteExtensions};
+use super::{Error, Result, RmcSerialize, SerializationContext};
+
+macro_rules! impl_rmc_serialize_for_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl RmcSerialize for $ty{
+                fn serialize(&self, writer: &mut dyn Write, _ctx: &SerializationContext) -> Result<()> {
+                    writer.write_struct(IS_BIG_ENDIAN, *self)?;
+                    Ok(())
+                }
+
+                fn deserialize(mut reader: &mut dyn Read, _ctx: &SerializationContext) -> Result<Self> {
+                    Ok(reader.read_struct(IS_BIG_ENDIAN)?)
+                }
+            }
+        )*
+    };
+}
+
+impl_rmc_serialize_for_integer!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl RmcSerialize for f32{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        self.to_bits().serialize(writer, ctx)
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        Ok(f32::from_bits(u32::deserialize(reader, ctx)?))
+    }
+}
+
+impl RmcSerialize for f64{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        self.to_bits().serialize(writer, ctx)
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        Ok(f64::from_bits(u64::deserialize(reader, ctx)?))
+    }
+}
+
+impl RmcSerialize for bool{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        (*self as u8).serialize(writer, ctx)
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        match u8::deserialize(reader, ctx)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(Error::InvalidValue { name: "bool", value: v as u64 })
+        }
+    }
+}
+
+// multiple values are just written one after another, this is mostly useful for the return values
+// of methods
+macro_rules! impl_rmc_serialize_for_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: RmcSerialize),*> RmcSerialize for ($($name,)*){
+            #[allow(non_snake_case, unused_variables)]
+            fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+                let ($($name,)*) = self;
+                $($name.serialize(writer, ctx)?;)*
+                Ok(())
+            }
+
+            #[allow(unused_variables)]
+            fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+                Ok(($($name::deserialize(reader, ctx)?,)*))
+            }
+        }
+    };
+}
+
+impl_rmc_serialize_for_tuple!();
+impl_rmc_serialize_for_tuple!(A);
+impl_rmc_serialize_for_tuple!(A, B);
+impl_rmc_serialize_for_tuple!(A, B, C);
+impl_rmc_serialize_for_tuple!(A, B, C, D);
+impl_rmc_serialize_for_tuple!(A, B, C, D, E);
+impl_rmc_serialize_for_tuple!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+
+    fn round_trip<T: RmcSerialize + PartialEq + std::fmt::Debug>(value: T, bytes: &[u8]){
+        let ctx = SerializationContext::default();
+
+        let mut data = Vec::new();
+        value.serialize(&mut data, &ctx).unwrap();
+        assert_eq!(data, bytes);
+
+        assert_eq!(T::deserialize(&mut Cursor::new(bytes), &ctx).unwrap(), value);
+    }
+
+    #[test]
+    fn integers(){
+        round_trip(0x12u8, &[0x12]);
+        round_trip(0x1234u16, &[0x34, 0x12]);
+        round_trip(0x12345678u32, &[0x78, 0x56, 0x34, 0x12]);
+        round_trip(0x0102030405060708u64, &[8, 7, 6, 5, 4, 3, 2, 1]);
+        round_trip(-2i8, &[0xFE]);
+        round_trip(-2i16, &[0xFE, 0xFF]);
+        round_trip(-2i32, &[0xFE, 0xFF, 0xFF, 0xFF]);
+        round_trip(-2i64, &[0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn floats_and_bools(){
+        let ctx = SerializationContext::default();
+
+        round_trip(1.5f32, &[0x00, 0x00, 0xC0, 0x3F]);
+        round_trip(-0.25f64, &[0, 0, 0, 0, 0, 0, 0xD0, 0xBF]);
+        round_trip(true, &[1]);
+        round_trip(false, &[0]);
+
+        assert!(bool::deserialize(&mut Cursor::new([2u8]), &ctx).is_err());
+    }
+
+    #[test]
+    fn tuples(){
+        round_trip((), &[]);
+        round_trip((1u8, 2u16, true), &[1, 2, 0, 1]);
+    }
+}
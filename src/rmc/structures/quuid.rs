@@ -0,0 +1,43 @@
+use std::io::{Read, Write};
+use super::{Result, RmcSerialize, SerializationContext};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Quuid(pub [u8; 16]);
+
+impl RmcSerialize for Quuid{
+    fn serialize(&self, writer: &mut dyn Write, _ctx: &SerializationContext) -> Result<()> {
+        writer.write_all(&self.0)?;
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut dyn Read, _ctx: &SerializationContext) -> Result<Self> {
+        let mut data = [0; 16];
+        reader.read_exact(&mut data)?;
+
+        Ok(Self(data))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use super::Quuid;
+
+    #[test]
+    fn quuid(){
+        let ctx = SerializationContext::default();
+
+        let data = [
+            0x6B, 0xA7, 0xB8, 0x10, 0x9D, 0xAD, 0x11, 0xD1,
+            0x80, 0xB4, 0x00, 0xC0, 0x4F, 0xD4, 0x30, 0xC8
+        ];
+
+        let quuid = Quuid::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+        assert_eq!(quuid.0, data);
+
+        let mut written = Vec::new();
+        quuid.serialize(&mut written, &ctx).unwrap();
+        assert_eq!(written, data);
+    }
+}
@@ -0,0 +1,68 @@
+use std::io::{Read, Write};
+use crate::rmc::response::ErrorCode;
+use super::{Result, RmcSerialize, SerializationContext};
+
+/// A nex `qResult`, errors are the error code with the 0x80000000 bit set
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResultCode(pub u32);
+
+impl ResultCode{
+    pub const SUCCESS: ResultCode = ResultCode(0x00010001);
+
+    const ERROR_BIT: u32 = 0x80000000;
+
+    pub fn error(error_code: ErrorCode) -> Self{
+        let code: u32 = error_code.into();
+
+        Self(code | Self::ERROR_BIT)
+    }
+
+    pub const fn is_success(self) -> bool{
+        (self.0 & Self::ERROR_BIT) == 0
+    }
+
+    // the error code without the error bit, only meaningful if this isn't a success
+    pub const fn error_code_value(self) -> u32{
+        self.0 & !Self::ERROR_BIT
+    }
+}
+
+impl Default for ResultCode{
+    fn default() -> Self {
+        Self::SUCCESS
+    }
+}
+
+impl RmcSerialize for ResultCode{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        self.0.serialize(writer, ctx)
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        Ok(Self(u32::deserialize(reader, ctx)?))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::response::ErrorCode;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use super::ResultCode;
+
+    #[test]
+    fn result_code(){
+        let ctx = SerializationContext::default();
+
+        let mut data = Vec::new();
+        ResultCode::SUCCESS.serialize(&mut data, &ctx).unwrap();
+        assert_eq!(data, [0x01, 0x00, 0x01, 0x00]);
+
+        let data = [0x0B, 0x00, 0x68, 0x80];
+        let result = ResultCode::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+
+        assert_eq!(result, ResultCode::error(ErrorCode::Authentication_UnderMaintenance));
+        assert!(!result.is_success());
+        assert_eq!(result.error_code_value(), 0x0068000B);
+    }
+}
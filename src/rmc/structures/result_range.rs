@@ -0,0 +1,29 @@
+use super::RmcSerialize;
+
+#[derive(RmcSerialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[rmc(version = 0)]
+pub struct ResultRange{
+    pub offset: u32,
+    pub length: u32,
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use super::ResultRange;
+
+    #[test]
+    fn result_range(){
+        let ctx = SerializationContext::default();
+
+        let data = [0, 8, 0, 0, 0, 0, 0, 0, 0, 0x0A, 0, 0, 0];
+
+        let range = ResultRange::deserialize(&mut Cursor::new(&data), &ctx).unwrap();
+        assert_eq!(range, ResultRange{ offset: 0, length: 10 });
+
+        let mut written = Vec::new();
+        range.serialize(&mut written, &ctx).unwrap();
+        assert_eq!(written, data);
+    }
+}
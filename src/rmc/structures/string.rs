@@ -1,13 +1,21 @@
 use std::ffi::CString;
 use std::io::{Read, Seek, Write};
-use bytemuck::bytes_of;
 use log::error;
-use crate::endianness::{IS_BIG_ENDIAN, ReadExtensions};
-use super::{Result, RmcSerialize};
+use crate::endianness::{IS_BIG_ENDIAN, ReadExtensions, WriteExtensions};
+use super::{Error, Result, RmcSerialize, SerializationContext};
 
 impl RmcSerialize for String{
-    fn deserialize(mut reader: &mut dyn Read) -> Result<Self> {
+    fn deserialize(mut reader: &mut dyn Read, _ctx: &SerializationContext) -> Result<Self> {
         let len: u16 = reader.read_struct(IS_BIG_ENDIAN)?;
+
+        // the length includes the null terminator, so there is no valid string with a length of 0
+        if len == 0 {
+            return Err(Error::InvalidValue {
+                name: "string length",
+                value: 0
+            });
+        }
+
         let mut data = vec![0; len as usize - 1];
         reader.read_exact(&mut data)?;
 
@@ -18,12 +26,16 @@ impl RmcSerialize for String{
 
         Ok(String::from_utf8(data)?)
     }
-    fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
-        let u16_len: u16 = self.len() as u16;
-        writer.write(bytes_of(&u16_len))?;
+    fn serialize(&self, writer: &mut dyn Write, _ctx: &SerializationContext) -> Result<()> {
+        // the length includes the null terminator
+        let u16_len = u16::try_from(self.len() + 1).map_err(|_| Error::InvalidValue {
+            name: "string length",
+            value: self.len() as u64
+        })?;
+        writer.write_struct(IS_BIG_ENDIAN, u16_len)?;
 
-        writer.write(self.as_bytes())?;
-        writer.write(&[0])?;
+        writer.write_all(self.as_bytes())?;
+        writer.write_all(&[0])?;
 
         Ok(())
     }
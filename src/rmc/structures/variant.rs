@@ -0,0 +1,91 @@
+use std::io::{Read, Write};
+use super::{Error, Result, RmcSerialize, SerializationContext};
+use super::date_time::DateTime;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Variant{
+    #[default]
+    None,
+    Int64(i64),
+    Double(f64),
+    Bool(bool),
+    String(String),
+    DateTime(DateTime),
+    UInt64(u64),
+}
+
+impl RmcSerialize for Variant{
+    fn serialize(&self, writer: &mut dyn Write, ctx: &SerializationContext) -> Result<()> {
+        match self {
+            Variant::None => 0u8.serialize(writer, ctx),
+            Variant::Int64(v) => {
+                1u8.serialize(writer, ctx)?;
+                v.serialize(writer, ctx)
+            }
+            Variant::Double(v) => {
+                2u8.serialize(writer, ctx)?;
+                v.serialize(writer, ctx)
+            }
+            Variant::Bool(v) => {
+                3u8.serialize(writer, ctx)?;
+                v.serialize(writer, ctx)
+            }
+            Variant::String(v) => {
+                4u8.serialize(writer, ctx)?;
+                v.serialize(writer, ctx)
+            }
+            Variant::DateTime(v) => {
+                5u8.serialize(writer, ctx)?;
+                v.serialize(writer, ctx)
+            }
+            Variant::UInt64(v) => {
+                6u8.serialize(writer, ctx)?;
+                v.serialize(writer, ctx)
+            }
+        }
+    }
+
+    fn deserialize(reader: &mut dyn Read, ctx: &SerializationContext) -> Result<Self> {
+        let val = match u8::deserialize(reader, ctx)? {
+            0 => Variant::None,
+            1 => Variant::Int64(i64::deserialize(reader, ctx)?),
+            2 => Variant::Double(f64::deserialize(reader, ctx)?),
+            3 => Variant::Bool(bool::deserialize(reader, ctx)?),
+            4 => Variant::String(String::deserialize(reader, ctx)?),
+            5 => Variant::DateTime(DateTime::deserialize(reader, ctx)?),
+            6 => Variant::UInt64(u64::deserialize(reader, ctx)?),
+            v => return Err(Error::InvalidValue { name: "Variant", value: v as u64 })
+        };
+
+        Ok(val)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::io::Cursor;
+    use crate::rmc::structures::{RmcSerialize, SerializationContext};
+    use super::Variant;
+
+    #[test]
+    fn variant(){
+        let ctx = SerializationContext::default();
+
+        let cases: [(Variant, &[u8]); 4] = [
+            (Variant::None, &[0]),
+            (Variant::Int64(-1), &[1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+            (Variant::Bool(true), &[3, 1]),
+            (Variant::String("hi".to_string()), &[4, 3, 0, b'h', b'i', 0]),
+        ];
+
+        for (variant, bytes) in cases{
+            let mut data = Vec::new();
+            variant.serialize(&mut data, &ctx).unwrap();
+            assert_eq!(data, bytes);
+
+            assert_eq!(Variant::deserialize(&mut Cursor::new(bytes), &ctx).unwrap(), variant);
+        }
+
+        assert!(Variant::deserialize(&mut Cursor::new([7u8]), &ctx).is_err());
+    }
+}